@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum OutputMode {
+    // moof + mdat media segments for MediaSource
+    FMP4,
+    // decoder configs + EncodedVideoChunk / EncodedAudioChunk inits for WebCodecs
+    WebCodecs,
+    // raw elementary streams: Annex-B H.264 / H.265, ADTS AAC, MP3
+    ElementaryStream,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum TextTrackMode {
+    Disabled,
+    // onTextCue callbacks
+    Cues,
+    // wvtt fMP4 track, through onInitSegment / onMediaSegment with type "text"
+    Wvtt,
+}
+
+// Which timeline the remuxer keeps when audio and video timestamps drift apart
+#[derive(Copy, Clone, PartialEq)]
+pub enum SyncStrategy {
+    // audio frames are laid out back to back, overlapping ones dropped and gaps filled,
+    // video keeps its timestamps
+    AudioMaster,
+    // video starts the timeline, audio drifting away from its own timestamps is shifted back
+    // onto them instead of being dropped or filled
+    VideoMaster,
+    // every sample keeps its FLV timestamp, segments are not stitched together
+    WallClock,
+}
+
+// What a video timestamp gap of whole frames, left by frames the encoder dropped, becomes
+#[derive(Copy, Clone, PartialEq)]
+pub enum VideoGapPolicy {
+    // the sample before the gap lasts until the next one
+    Stretch,
+    // the sample before the gap is repeated as non-sync samples of the frame duration when it
+    // is an H.264 non-reference picture, the only kind a decoder can take twice; stretched
+    // otherwise (IDR, reference pictures, HEVC whose pictures must differ in PicOrderCntVal)
+    Duplicate,
+    // the gap stays in the timeline, the samples after it start a new media segment, see
+    // MP4Remuxer.onDiscontinuity
+    Discontinuity,
+}
+
+// Where the wall clock written in prft boxes comes from
+#[derive(Copy, Clone, PartialEq)]
+pub enum ProducerReferenceTimeSource {
+    // the time the tags reached the demuxer
+    Arrival,
+    // user_data_unregistered SEI with Config.producerReferenceTimeSeiUuid, a 64-bit NTP timestamp
+    Sei,
+    // onFI script tags, the encoder system date and time
+    ScriptTag,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum EncryptionScheme {
+    // AES-CTR, per-sample IVs
+    Cenc,
+    // AES-CBC with a constant IV, 1:9 pattern on video
+    Cbcs,
+}
+
+// Written as a pssh box into the init segment
+#[derive(Clone)]
+pub struct PsshData {
+    pub systemId: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct EncryptionConfig {
+    pub scheme: EncryptionScheme,
+    pub key: Vec<u8>,
+    pub kid: Vec<u8>,
+    // cenc: 8 bytes, counted up per sample; cbcs: 16 bytes constant IV
+    pub iv: Vec<u8>,
+    pub pssh: Vec<PsshData>,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub isLive: bool,
+
+    pub fixAudioTimestampGap: bool,
+
+    pub syncStrategy: SyncStrategy,
+    // audio drift, in frames, above which audio frames are dropped, filled or shifted
+    pub maxAudioFramesDrift: u32,
+    // ms between a segment and the end of the previous one still considered continuous
+    pub dtsGapTolerance: i64,
+
+    // gaps are detected from the frame rate, see VideoTrackMetaData.refSampleDuration
+    pub videoGapPolicy: VideoGapPolicy,
+
+    // repair backwards / duplicate DTS, negative CTS and A/V offsets before remuxing
+    pub sanitizeTimestamps: bool,
+    // ms, DTS jumps beyond are spliced, or signalled as discontinuities
+    pub timestampJumpThreshold: i64,
+    pub signalTimestampDiscontinuity: bool,
+    // ms, audio further away from video is shifted onto it
+    pub maxAVOffset: i64,
+
+    // video decode times moved up by the initial composition delay, B-frames then carry
+    // negative composition offsets (trun version 1) and the init segment an elst
+    pub negativeCompositionOffsets: bool,
+
+    // collect the emitted fragments for MP4Remuxer.segmentIndex / randomAccessIndex (sidx / mfra),
+    // for VOD exports as a single byte-range seekable file
+    pub enableSegmentIndex: bool,
+
+    // prft before each moof, mapping its tfdt to the wall clock, for end-to-end latency
+    pub enableProducerReferenceTime: bool,
+    pub producerReferenceTimeSource: ProducerReferenceTimeSource,
+    pub producerReferenceTimeSeiUuid: [u8; 16],
+
+    // live only: estimate the latency behind the live edge from tag arrival time vs DTS.
+    // Past liveLatencyDropThreshold the incoming audio / video tags are discarded unparsed
+    // until the next video keyframe, which is a visible skip ahead
+    pub enableLatencyChasing: bool,
+    // ms, the recommended playback rate rises above 1 past it
+    pub liveLatencyTarget: f64,
+    // ms
+    pub liveLatencyDropThreshold: f64,
+    // recommended playback rate at liveLatencyDropThreshold
+    pub maxPlaybackRate: f64,
+
+    pub enableConstVideoViewSize: bool,
+    pub constVideoViewWidth: u32,
+    pub constVideoViewHeight: u32,
+
+    pub outputMode: OutputMode,
+
+    // turn the AudioSpecificConfig into the HE-AAC / LC one each browser decodes best
+    pub rewriteAudioSpecificConfig: bool,
+
+    pub encryption: Option<EncryptionConfig>,
+
+    // script tags other than onMetaData as emsg boxes in the media segments
+    pub enableTimedMetadata: bool,
+    // scheme_id_uri per script tag name, e.g. "onCuePoint" => "urn:example:cue"
+    pub timedMetadataSchemes: HashMap<String, String>,
+    // scheme_id_uri of the names not listed above
+    pub timedMetadataDefaultScheme: String,
+    // onCuePoint tags carrying SCTE-35 as urn:scte:scte35:2013:bin emsg and onScte35Cue
+    pub enableScte35: bool,
+
+    // CEA-608/708 captions from H.264 SEI, as onCaptionCue callbacks or WebVTT
+    pub enableCaptions: bool,
+
+    // user_data_unregistered SEI with these UUIDs are passed to onSEI
+    pub seiUuids: Vec<[u8; 16]>,
+    // and removed from the video samples
+    pub stripRegisteredSEI: bool,
+
+    // what onTextData subtitles become
+    pub textTrackMode: TextTrackMode,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            isLive: false,
+
+            fixAudioTimestampGap: true,
+
+            syncStrategy: SyncStrategy::AudioMaster,
+            maxAudioFramesDrift: 3,
+            dtsGapTolerance: 3,
+
+            videoGapPolicy: VideoGapPolicy::Stretch,
+
+            sanitizeTimestamps: false,
+            timestampJumpThreshold: 1000,
+            signalTimestampDiscontinuity: false,
+            maxAVOffset: 2000,
+
+            negativeCompositionOffsets: false,
+
+            enableSegmentIndex: false,
+
+            enableProducerReferenceTime: false,
+            producerReferenceTimeSource: ProducerReferenceTimeSource::Arrival,
+            producerReferenceTimeSeiUuid: [0; 16],
+
+            enableLatencyChasing: false,
+            liveLatencyTarget: 1500.0,
+            liveLatencyDropThreshold: 5000.0,
+            maxPlaybackRate: 1.2,
+
+            enableConstVideoViewSize: false,
+            constVideoViewWidth: 0,
+            constVideoViewHeight: 0,
+
+            outputMode: OutputMode::FMP4,
+
+            rewriteAudioSpecificConfig: true,
+
+            encryption: None,
+
+            enableTimedMetadata: false,
+            timedMetadataSchemes: HashMap::new(),
+            timedMetadataDefaultScheme: String::from("urn:flvjs:script"),
+            enableScte35: false,
+
+            enableCaptions: false,
+
+            seiUuids: Vec::new(),
+            stripRegisteredSEI: false,
+
+            textTrackMode: TextTrackMode::Disabled,
+        }
+    }
+}
@@ -62,6 +62,18 @@ pub(crate) struct MediaInfo {
     chromaFormat: Option<usize>,
     sarNum: Option<usize>,
     sarDen: Option<usize>,
+    // VUI colour description, ITU-T H.273 code points
+    colourPrimaries: Option<usize>,
+    transferCharacteristics: Option<usize>,
+    matrixCoefficients: Option<usize>,
+    fullRange: Option<bool>,
+    // "PQ" or "HLG", from the transfer characteristics
+    hdr: Option<String>,
+    // clockwise degrees and horizontal flip, the present size being the displayed one
+    rotation: Option<usize>,
+    mirror: Option<bool>,
+    presentWidth: Option<usize>,
+    presentHeight: Option<usize>,
     metadata: Option<u32>,
     segments: Option<u32>,
     segmentCount: Option<usize>,
@@ -0,0 +1,590 @@
+// MPEG-4 AudioSpecificConfig, ISO/IEC 14496-3 1.6.2.1, and the browser compatibility rewrite
+// applied to it before it goes into the esds
+
+pub const MPEG_SAMPLING_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+// Audio Object Types
+pub const AOT_AAC_MAIN: u8 = 1;
+pub const AOT_AAC_LC: u8 = 2;
+pub const AOT_SBR: u8 = 5;
+pub const AOT_ER_AAC_LD: u8 = 23;
+pub const AOT_PS: u8 = 29;
+pub const AOT_ER_AAC_ELD: u8 = 39;
+pub const AOT_USAC: u8 = 42;
+
+// number of channels per channelConfiguration, 0 meaning a program_config_element
+const CHANNEL_COUNTS: [u32; 16] = [0, 1, 2, 3, 4, 5, 6, 8, 0, 0, 0, 7, 8, 24, 8, 0];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, position: 0 }
+    }
+
+    fn bitsLeft(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.position)
+    }
+
+    fn read(&mut self, bits: usize) -> Result<u32, &'static str> {
+        if bits > self.bitsLeft() {
+            return Err("AudioSpecificConfig: unexpected end of data");
+        }
+        let mut value = 0u32;
+        for _ in 0..bits {
+            let byte = self.data[self.position / 8];
+            let bit = (byte >> (7 - self.position % 8)) & 0x01;
+            value = value << 1 | bit as u32;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn readBool(&mut self) -> Result<bool, &'static str> {
+        Ok(self.read(1)? == 1)
+    }
+
+    fn skip(&mut self, bits: usize) -> Result<(), &'static str> {
+        if bits > self.bitsLeft() {
+            return Err("AudioSpecificConfig: unexpected end of data");
+        }
+        self.position += bits;
+        Ok(())
+    }
+
+    fn byteAlign(&mut self) {
+        self.position = (self.position + 7) / 8 * 8;
+    }
+
+    // escapedValue() of USAC, ISO/IEC 23003-3 5.2
+    fn readEscaped(
+        &mut self,
+        bits1: usize,
+        bits2: usize,
+        bits3: usize,
+    ) -> Result<u32, &'static str> {
+        let mut value = self.read(bits1)?;
+        if value == (1 << bits1) - 1 {
+            let value2 = self.read(bits2)?;
+            value += value2;
+            if value2 == (1 << bits2) - 1 {
+                value += self.read(bits3)?;
+            }
+        }
+        Ok(value)
+    }
+}
+
+// program_config_element(), ISO/IEC 14496-3 4.4.1.1
+#[derive(Clone, Default, PartialEq)]
+pub struct ProgramConfigElement {
+    // (is_cpe, element_tag_select) of each front / side / back element
+    pub frontElements: Vec<(bool, u8)>,
+    pub sideElements: Vec<(bool, u8)>,
+    pub backElements: Vec<(bool, u8)>,
+    // lfe_element_tag_select
+    pub lfeElements: Vec<u8>,
+    pub comment: Vec<u8>,
+}
+
+impl ProgramConfigElement {
+    pub fn channelCount(&self) -> u32 {
+        let elements = self.frontElements.iter();
+        let elements = elements.chain(self.sideElements.iter());
+        let elements = elements.chain(self.backElements.iter());
+        let channels: u32 = elements.map(|&(isCpe, _)| if isCpe { 2 } else { 1 }).sum();
+        channels + self.lfeElements.len() as u32
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct AudioSpecificConfig {
+    // the core object type: 2 for HE-AAC signalled either way
+    pub audioObjectType: u8,
+    // 0x0F when the sampling frequency is escape coded
+    pub samplingIndex: u8,
+    pub samplingFrequency: u32,
+    pub channelConfiguration: u8,
+    // Some(5) (SBR) or Some(22) (ER BSAC) with extension signalling
+    pub extensionAudioObjectType: Option<u8>,
+    pub extensionSamplingFrequency: Option<u32>,
+    // None when not signalled: implicit SBR / PS may still be found in the bitstream
+    pub sbrPresent: Option<bool>,
+    pub psPresent: Option<bool>,
+    // PCM samples per frame at the core sampling frequency
+    pub frameLength: u32,
+    pub programConfig: Option<ProgramConfigElement>,
+    // USAC (xHE-AAC): channels signalled in the UsacConfig
+    pub usacChannelCount: Option<u32>,
+    // the first audioObjectType field, 5 / 29 with explicit SBR / PS signalling
+    pub signalledObjectType: u8,
+}
+
+impl AudioSpecificConfig {
+    pub fn channelCount(&self) -> u32 {
+        if let Some(channels) = self.usacChannelCount {
+            return channels;
+        }
+        if let Some(pce) = &self.programConfig {
+            return pce.channelCount();
+        }
+        // parametric stereo turns a mono core into stereo
+        if self.psPresent == Some(true) && self.channelConfiguration == 1 {
+            return 2;
+        }
+        CHANNEL_COUNTS[self.channelConfiguration as usize]
+    }
+
+    // sampling frequency of the decoded output
+    pub fn outputSamplingFrequency(&self) -> u32 {
+        match (self.sbrPresent, self.extensionSamplingFrequency) {
+            (Some(true), Some(frequency)) => frequency,
+            _ => self.samplingFrequency,
+        }
+    }
+
+    pub fn isEld(&self) -> bool {
+        self.audioObjectType == AOT_ER_AAC_ELD
+    }
+
+    pub fn isUsac(&self) -> bool {
+        self.audioObjectType == AOT_USAC
+    }
+
+    // RFC 6381 codecs parameter: the explicit SBR / PS object types win over the core one
+    pub fn codec(&self) -> String {
+        let objectType = if self.psPresent == Some(true) {
+            AOT_PS
+        } else if self.sbrPresent == Some(true) && self.audioObjectType == AOT_AAC_LC {
+            AOT_SBR
+        } else {
+            self.audioObjectType
+        };
+        format!("mp4a.40.{}", objectType)
+    }
+}
+
+fn readAudioObjectType(reader: &mut BitReader) -> Result<u8, &'static str> {
+    let audioObjectType = reader.read(5)?;
+    if audioObjectType == 31 {
+        return Ok((32 + reader.read(6)?) as u8);
+    }
+    Ok(audioObjectType as u8)
+}
+
+// samplingFrequencyIndex, and the 24 bits samplingFrequency when escape coded
+fn readSamplingFrequency(reader: &mut BitReader) -> Result<(u8, u32), &'static str> {
+    let samplingIndex = reader.read(4)? as u8;
+    let samplingFrequency = match samplingIndex {
+        0x0F => reader.read(24)?,
+        0x00..=0x0C => MPEG_SAMPLING_RATES[samplingIndex as usize],
+        _ => return Err("AudioSpecificConfig: reserved sampling frequency index"),
+    };
+    Ok((samplingIndex, samplingFrequency))
+}
+
+fn parseProgramConfigElement(reader: &mut BitReader) -> Result<ProgramConfigElement, &'static str> {
+    // element_instance_tag, object_type, sampling_frequency_index
+    reader.skip(4 + 2 + 4)?;
+    let numFront = reader.read(4)?;
+    let numSide = reader.read(4)?;
+    let numBack = reader.read(4)?;
+    let numLfe = reader.read(2)?;
+    let numAssocData = reader.read(3)?;
+    let numValidCc = reader.read(4)?;
+
+    // mono_mixdown, stereo_mixdown: element number
+    for _ in 0..2 {
+        if reader.readBool()? {
+            reader.skip(4)?;
+        }
+    }
+    // matrix_mixdown_idx + pseudo_surround_enable
+    if reader.readBool()? {
+        reader.skip(3)?;
+    }
+
+    let mut readElements = |count: u32| -> Result<Vec<(bool, u8)>, &'static str> {
+        let mut elements = vec![];
+        for _ in 0..count {
+            // is_cpe, element_tag_select
+            elements.push((reader.readBool()?, reader.read(4)? as u8));
+        }
+        Ok(elements)
+    };
+    let frontElements = readElements(numFront)?;
+    let sideElements = readElements(numSide)?;
+    let backElements = readElements(numBack)?;
+
+    let mut lfeElements = vec![];
+    for _ in 0..numLfe {
+        lfeElements.push(reader.read(4)? as u8); // lfe_element_tag_select
+    }
+    // assoc_data_element_tag_select, cc_element_is_ind_sw + valid_cc_element_tag_select
+    reader.skip(numAssocData as usize * 4 + numValidCc as usize * 5)?;
+
+    reader.byteAlign();
+    let commentLength = reader.read(8)?;
+    let mut comment = Vec::with_capacity(commentLength as usize);
+    for _ in 0..commentLength {
+        comment.push(reader.read(8)? as u8);
+    }
+
+    Ok(ProgramConfigElement {
+        frontElements,
+        sideElements,
+        backElements,
+        lfeElements,
+        comment,
+    })
+}
+
+// GASpecificConfig(), ISO/IEC 14496-3 4.4.1
+fn parseGASpecificConfig(
+    reader: &mut BitReader,
+    config: &mut AudioSpecificConfig,
+) -> Result<(), &'static str> {
+    let audioObjectType = config.audioObjectType;
+    let frameLengthFlag = reader.readBool()?;
+    config.frameLength = match (audioObjectType, frameLengthFlag) {
+        (AOT_ER_AAC_LD, false) => 512,
+        (AOT_ER_AAC_LD, true) => 480,
+        (_, false) => 1024,
+        (_, true) => 960,
+    };
+    // dependsOnCoreCoder: coreCoderDelay
+    if reader.readBool()? {
+        reader.skip(14)?;
+    }
+    let extensionFlag = reader.readBool()?;
+    if config.channelConfiguration == 0 {
+        config.programConfig = Some(parseProgramConfigElement(reader)?);
+    }
+    // AAC scalable: layerNr
+    if audioObjectType == 6 || audioObjectType == 20 {
+        reader.skip(3)?;
+    }
+    if extensionFlag {
+        // ER BSAC: numOfSubFrame + layer_length
+        if audioObjectType == 22 {
+            reader.skip(5 + 11)?;
+        }
+        // aacSectionDataResilienceFlag, aacScalefactorDataResilienceFlag,
+        // aacSpectralDataResilienceFlag
+        if [17, 19, 20, 23].contains(&audioObjectType) {
+            reader.skip(3)?;
+        }
+        // extensionFlag3
+        reader.skip(1)?;
+    }
+    Ok(())
+}
+
+// ELDSpecificConfig(), ISO/IEC 14496-3 4.4.1.2: only up to the low delay SBR signalling
+fn parseELDSpecificConfig(
+    reader: &mut BitReader,
+    config: &mut AudioSpecificConfig,
+) -> Result<(), &'static str> {
+    config.frameLength = if reader.readBool()? { 480 } else { 512 };
+    // aacSectionDataResilienceFlag, aacScalefactorDataResilienceFlag,
+    // aacSpectralDataResilienceFlag
+    reader.skip(3)?;
+    let ldSbrPresent = reader.readBool()?;
+    config.sbrPresent = Some(ldSbrPresent);
+    if ldSbrPresent {
+        // ldSbrSamplingRate: dual rate SBR
+        if reader.readBool()? {
+            config.extensionSamplingFrequency = Some(config.samplingFrequency * 2);
+        } else {
+            config.extensionSamplingFrequency = Some(config.samplingFrequency);
+        }
+    }
+    Ok(())
+}
+
+// UsacConfig(), ISO/IEC 23003-3 5.2: the sampling frequency, frame length and channels
+fn parseUsacConfig(
+    reader: &mut BitReader,
+    config: &mut AudioSpecificConfig,
+) -> Result<(), &'static str> {
+    let usacSamplingIndex = reader.read(5)?;
+    let usacSamplingFrequency = match usacSamplingIndex {
+        0x1F => reader.read(24)?,
+        0x00..=0x0C => MPEG_SAMPLING_RATES[usacSamplingIndex as usize],
+        _ => config.samplingFrequency,
+    };
+    // coreSbrFrameLengthIndex: core frame length, and SBR ratio
+    let (frameLength, sbrRatio) = match reader.read(3)? {
+        0 => (768, None),
+        1 => (1024, None),
+        2 => (768, Some((8, 3))),
+        3 => (1024, Some((2, 1))),
+        4 => (1024, Some((4, 1))),
+        _ => return Err("AudioSpecificConfig: reserved coreSbrFrameLengthIndex"),
+    };
+    config.frameLength = frameLength;
+    config.sbrPresent = Some(sbrRatio.is_some());
+    config.extensionSamplingFrequency = Some(usacSamplingFrequency);
+    config.samplingFrequency = match sbrRatio {
+        Some((numerator, denominator)) => usacSamplingFrequency * denominator / numerator,
+        None => usacSamplingFrequency,
+    };
+
+    let channelConfigurationIndex = reader.read(5)?;
+    let channelCount = if channelConfigurationIndex == 0 {
+        // UsacChannelConfig: numOutChannels
+        reader.readEscaped(5, 8, 16)?
+    } else {
+        match CHANNEL_COUNTS.get(channelConfigurationIndex as usize) {
+            Some(&channels) if channels > 0 => channels,
+            _ => return Err("AudioSpecificConfig: reserved USAC channelConfigurationIndex"),
+        }
+    };
+    config.usacChannelCount = Some(channelCount);
+    Ok(())
+}
+
+pub fn parseAudioSpecificConfig(data: &[u8]) -> Result<AudioSpecificConfig, &'static str> {
+    let mut reader = BitReader::new(data);
+
+    let signalledObjectType = readAudioObjectType(&mut reader)?;
+    let (samplingIndex, samplingFrequency) = readSamplingFrequency(&mut reader)?;
+    let channelConfiguration = reader.read(4)? as u8;
+
+    let mut config = AudioSpecificConfig {
+        audioObjectType: signalledObjectType,
+        samplingIndex,
+        samplingFrequency,
+        channelConfiguration,
+        extensionAudioObjectType: None,
+        extensionSamplingFrequency: None,
+        sbrPresent: None,
+        psPresent: None,
+        frameLength: 1024,
+        programConfig: None,
+        usacChannelCount: None,
+        signalledObjectType,
+    };
+
+    // explicit, non backward compatible SBR / PS signalling
+    if signalledObjectType == AOT_SBR || signalledObjectType == AOT_PS {
+        config.extensionAudioObjectType = Some(AOT_SBR);
+        config.sbrPresent = Some(true);
+        if signalledObjectType == AOT_PS {
+            config.psPresent = Some(true);
+        }
+        config.extensionSamplingFrequency = Some(readSamplingFrequency(&mut reader)?.1);
+        config.audioObjectType = readAudioObjectType(&mut reader)?;
+        // ER BSAC: extensionChannelConfiguration
+        if config.audioObjectType == 22 {
+            reader.skip(4)?;
+        }
+    }
+
+    match config.audioObjectType {
+        1 | 2 | 3 | 4 | 6 | 7 | 17 | 19 | 20 | 21 | 22 | 23 => {
+            parseGASpecificConfig(&mut reader, &mut config)?
+        }
+        AOT_ER_AAC_ELD => parseELDSpecificConfig(&mut reader, &mut config)?,
+        AOT_USAC => {
+            parseUsacConfig(&mut reader, &mut config)?;
+            return Ok(config);
+        }
+        _ => return Err("AudioSpecificConfig: unsupported audioObjectType"),
+    }
+    if config.programConfig.is_none() && CHANNEL_COUNTS[channelConfiguration as usize] == 0 {
+        return Err("AudioSpecificConfig: reserved channelConfiguration");
+    }
+
+    // epConfig of the error resilient object types
+    if (17..=27).contains(&config.audioObjectType) || config.isEld() {
+        let epConfig = reader.read(2)?;
+        if epConfig == 2 || epConfig == 3 {
+            // ErrorProtectionSpecificConfig is not needed to play the stream
+            return Ok(config);
+        }
+    }
+
+    // backward compatible SBR / PS signalling appended to the config
+    if config.extensionAudioObjectType.is_none() && !config.isEld() && reader.bitsLeft() >= 16 {
+        if reader.read(11)? == 0x2B7 {
+            let extensionAudioObjectType = readAudioObjectType(&mut reader)?;
+            if extensionAudioObjectType == AOT_SBR {
+                let sbrPresent = reader.readBool()?;
+                config.extensionAudioObjectType = Some(AOT_SBR);
+                config.sbrPresent = Some(sbrPresent);
+                if sbrPresent {
+                    config.extensionSamplingFrequency = Some(readSamplingFrequency(&mut reader)?.1);
+                    if reader.bitsLeft() >= 12 && reader.read(11)? == 0x548 {
+                        config.psPresent = Some(reader.readBool()?);
+                    }
+                }
+            } else if extensionAudioObjectType == 22 {
+                config.extensionAudioObjectType = Some(22);
+                let sbrPresent = reader.readBool()?;
+                config.sbrPresent = Some(sbrPresent);
+                if sbrPresent {
+                    config.extensionSamplingFrequency = Some(readSamplingFrequency(&mut reader)?.1);
+                }
+                // extensionChannelConfiguration
+                reader.skip(4)?;
+            }
+        }
+    }
+
+    Ok(config)
+}
+
+// Serializes the 2 bytes LC config, or the 4 bytes explicit HE-AAC one when
+// extensionSamplingIndex is set
+fn buildConfig(
+    audioObjectType: u8,
+    samplingIndex: u8,
+    channelConfiguration: u8,
+    extensionSamplingIndex: Option<u8>,
+) -> Vec<u8> {
+    let mut config = vec![
+        audioObjectType << 3 | (samplingIndex & 0x0F) >> 1,
+        (samplingIndex & 0x0F) << 7 | (channelConfiguration & 0x0F) << 3,
+    ];
+    if let Some(extensionSamplingIndex) = extensionSamplingIndex {
+        config[1] |= (extensionSamplingIndex & 0x0F) >> 1;
+        // extended audio object type: LC
+        config.push((extensionSamplingIndex & 0x01) << 7 | AOT_AAC_LC << 2);
+        config.push(0);
+    }
+    config
+}
+
+// Workarounds for the AAC decoders of the browsers: the config is turned into an explicit HE-AAC
+// or an LC one, depending on the user agent. Returns the new config and its audioObjectType, or
+// None when the config must be used as is (PCE, escape coded frequency, ELD, USAC, ...)
+pub fn rewriteForBrowser(config: &AudioSpecificConfig, userAgent: &str) -> Option<(Vec<u8>, u8)> {
+    let rewritable = (AOT_AAC_MAIN..=4).contains(&config.audioObjectType)
+        && config.samplingIndex < 0x0F
+        && config.programConfig.is_none()
+        && config.psPresent != Some(true);
+    if !rewritable {
+        return None;
+    }
+    let samplingIndex = config.samplingIndex;
+    let channelConfiguration = config.channelConfiguration;
+    let userAgent = userAgent.to_lowercase();
+
+    let (audioObjectType, extensionSamplingIndex) = if userAgent.contains("firefox") {
+        // firefox: use SBR (HE-AAC) if freq less than 24kHz
+        if samplingIndex >= 6 {
+            (AOT_SBR, Some(samplingIndex - 3))
+        } else {
+            (AOT_AAC_LC, None)
+        }
+    } else if userAgent.contains("android") {
+        // android: always use LC-AAC
+        (AOT_AAC_LC, None)
+    } else if samplingIndex >= 6 {
+        // for other browsers, e.g. chrome...
+        // Always use HE-AAC to make it easier to switch aac codec profile
+        (AOT_SBR, Some(samplingIndex - 3))
+    } else if channelConfiguration == 1 {
+        // Mono channel
+        (AOT_AAC_LC, None)
+    } else {
+        (AOT_SBR, Some(samplingIndex))
+    };
+
+    let config = buildConfig(
+        audioObjectType,
+        samplingIndex,
+        channelConfiguration,
+        extensionSamplingIndex,
+    );
+    Some((config, audioObjectType))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn aac_lc_44100_stereo() {
+        let config = parseAudioSpecificConfig(&[0x12, 0x10]).unwrap();
+        assert_eq!(config.audioObjectType, AOT_AAC_LC);
+        assert_eq!(config.samplingIndex, 4);
+        assert_eq!(config.samplingFrequency, 44100);
+        assert_eq!(config.channelCount(), 2);
+        assert_eq!(config.frameLength, 1024);
+        assert_eq!(config.sbrPresent, None);
+        assert_eq!(config.codec(), "mp4a.40.2");
+    }
+
+    #[wasm_bindgen_test]
+    fn explicit_he_aac() {
+        // SBR 24kHz stereo, extension 48kHz, core LC
+        let config = parseAudioSpecificConfig(&[0x2B, 0x11, 0x88, 0x00]).unwrap();
+        assert_eq!(config.signalledObjectType, AOT_SBR);
+        assert_eq!(config.audioObjectType, AOT_AAC_LC);
+        assert_eq!(config.samplingFrequency, 24000);
+        assert_eq!(config.outputSamplingFrequency(), 48000);
+        assert_eq!(config.codec(), "mp4a.40.5");
+    }
+
+    #[wasm_bindgen_test]
+    fn backward_compatible_sbr_and_ps() {
+        // LC 24kHz mono, then sync 0x2B7 SBR 48kHz and sync 0x548 PS
+        let config = parseAudioSpecificConfig(&[0x13, 0x08, 0x56, 0xE5, 0x9D, 0x48, 0x80]).unwrap();
+        assert_eq!(config.sbrPresent, Some(true));
+        assert_eq!(config.psPresent, Some(true));
+        assert_eq!(config.outputSamplingFrequency(), 48000);
+        assert_eq!(config.channelCount(), 2);
+        assert_eq!(config.codec(), "mp4a.40.29");
+    }
+
+    #[wasm_bindgen_test]
+    fn program_config_element() {
+        // LC 48kHz, channelConfiguration 0: front C + L/R, back Ls/Rs, one LFE
+        let data = [0x11, 0x80, 0x04, 0xC8, 0x05, 0x00, 0x01, 0x19, 0x18, 0x00];
+        let config = parseAudioSpecificConfig(&data).unwrap();
+        assert_eq!(config.channelCount(), 6);
+        assert!(rewriteForBrowser(&config, "Chrome").is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn truncated_config() {
+        assert!(parseAudioSpecificConfig(&[0x12]).is_err());
+        assert!(parseAudioSpecificConfig(&[]).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn rewrite_aac_lc_44100_stereo() {
+        let config = parseAudioSpecificConfig(&[0x12, 0x10]).unwrap();
+        // explicit HE-AAC at the same extension frequency
+        assert_eq!(
+            rewriteForBrowser(&config, "Chrome"),
+            Some((vec![0x2A, 0x12, 0x08, 0x00], AOT_SBR))
+        );
+        assert_eq!(
+            rewriteForBrowser(&config, "Firefox"),
+            Some((vec![0x12, 0x10], AOT_AAC_LC))
+        );
+        assert_eq!(
+            rewriteForBrowser(&config, "Android"),
+            Some((vec![0x12, 0x10], AOT_AAC_LC))
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn rewrite_low_sampling_frequency() {
+        // LC 22.05kHz stereo: SBR with twice the frequency, index 7 - 3
+        let config = parseAudioSpecificConfig(&[0x13, 0x90]).unwrap();
+        assert_eq!(
+            rewriteForBrowser(&config, "Firefox"),
+            Some((vec![0x2B, 0x92, 0x08, 0x00], AOT_SBR))
+        );
+    }
+}
@@ -29,6 +29,7 @@ pub enum AMFValue {
     Date(Date),
     Number(f64),
     Boolean(bool),
+    String(String),
     Object(HashMap<String, AMFValue>),
     Array(Vec<AMFValue>),
 }
@@ -0,0 +1,185 @@
+// Closed captions carried in SEI, decoded into timed cues
+
+use super::cea608::Cea608Decoder;
+use super::cea708::Cea708Decoder;
+use super::sei_parser;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct CaptionCue {
+    channel: String,
+    // in milliseconds
+    pub startTime: f64,
+    pub endTime: f64,
+    text: String,
+}
+
+#[wasm_bindgen]
+impl CaptionCue {
+    // "CC1" ~ "CC4" for CEA-608, "SERVICE1" ~ "SERVICE63" for CEA-708
+    #[wasm_bindgen(getter)]
+    pub fn channel(&self) -> String {
+        self.channel.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn text(&self) -> String {
+        self.text.clone()
+    }
+}
+
+impl CaptionCue {
+    pub fn new(channel: String, startTime: f64, endTime: f64, text: String) -> CaptionCue {
+        CaptionCue {
+            channel,
+            startTime,
+            endTime,
+            text,
+        }
+    }
+}
+
+pub struct CaptionDecoder {
+    _cea608: Cea608Decoder,
+    _cea708: Cea708Decoder,
+    // cc_data must be decoded in presentation order, while it arrives in decode order
+    _pending: Vec<(f64, Vec<(u8, u8, u8)>)>,
+}
+
+impl CaptionDecoder {
+    pub fn new() -> CaptionDecoder {
+        CaptionDecoder {
+            _cea608: Cea608Decoder::new(),
+            _cea708: Cea708Decoder::new(),
+            _pending: Vec::new(),
+        }
+    }
+
+    // Queues the cc_data of a user_data_registered_itu_t_t35 SEI payload
+    pub fn push(&mut self, pts: f64, payload: &[u8]) {
+        let ccData = sei_parser::parseCCData(payload);
+        if ccData.is_empty() {
+            return;
+        }
+        let index = self._pending.iter().rposition(|(time, _)| *time <= pts);
+        let index = index.map(|index| index + 1).unwrap_or(0);
+        self._pending.insert(index, (pts, ccData));
+    }
+
+    // Decodes the queued cc_data up to dts: no later frame can be presented before it
+    pub fn decode(&mut self, dts: f64) -> Vec<CaptionCue> {
+        let count = self
+            ._pending
+            .iter()
+            .take_while(|(pts, _)| *pts <= dts)
+            .count();
+        let ready: Vec<(f64, Vec<(u8, u8, u8)>)> = self._pending.drain(..count).collect();
+
+        let mut cues = vec![];
+        for (pts, ccData) in ready {
+            for (ccType, data1, data2) in ccData {
+                match ccType {
+                    // NTSC field 1 / field 2
+                    0 | 1 => self._cea608.push(pts, ccType, data1, data2, &mut cues),
+                    // DTVCC packet data / start
+                    _ => self._cea708.push(pts, ccType == 3, data1, data2, &mut cues),
+                }
+            }
+        }
+        cues
+    }
+
+    // Closes whatever is still on screen
+    pub fn flush(&mut self, time: f64) -> Vec<CaptionCue> {
+        let mut cues = self.decode(std::f64::INFINITY);
+        self._cea608.flush(time, &mut cues);
+        self._cea708.flush(time, &mut cues);
+        cues
+    }
+}
+
+fn formatTimestamp(time: f64) -> String {
+    let time = time.max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        time / 3600000,
+        time / 60000 % 60,
+        time / 1000 % 60,
+        time % 1000
+    )
+}
+
+pub fn toWebVTT(cues: &[CaptionCue]) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    for cue in cues {
+        vtt += &format!(
+            "{} --> {}\n{}\n\n",
+            formatTimestamp(cue.startTime),
+            formatTimestamp(cue.endTime),
+            cue.text
+        );
+    }
+    vtt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // user_data_registered_itu_t_t35 with CEA-608 field 1 pairs
+    fn t35(pairs: &[(u8, u8)]) -> Vec<u8> {
+        let mut payload = vec![0xB5, 0x00, 0x31, b'G', b'A', b'9', b'4', 0x03];
+        payload.push(0x40 | pairs.len() as u8);
+        payload.push(0xFF);
+        for &(data1, data2) in pairs {
+            payload.extend_from_slice(&[0xFC, data1, data2]);
+        }
+        payload
+    }
+
+    #[wasm_bindgen_test]
+    fn cc_data_is_decoded_in_presentation_order() {
+        let mut decoder = CaptionDecoder::new();
+        let mut cues = vec![];
+        // I P B in decode order: RCL + PAC, EOC, then the text of the B frame
+        decoder.push(
+            0.0,
+            &t35(&[(0x14, 0x20), (0x14, 0x20), (0x14, 0x70), (0x14, 0x70)]),
+        );
+        cues.extend(decoder.decode(0.0));
+        decoder.push(66.0, &t35(&[(0x14, 0x2F), (0x14, 0x2F)]));
+        cues.extend(decoder.decode(33.0));
+        decoder.push(33.0, &t35(&[(b'H', b'I')]));
+        cues.extend(decoder.decode(66.0));
+        // EDM
+        decoder.push(1000.0, &t35(&[(0x14, 0x2C), (0x14, 0x2C)]));
+        cues.extend(decoder.flush(2000.0));
+
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].channel(), "CC1");
+        assert_eq!((cues[0].startTime, cues[0].endTime), (66.0, 1000.0));
+        assert_eq!(cues[0].text(), "HI");
+    }
+
+    #[wasm_bindgen_test]
+    fn webvtt_output() {
+        let cues = vec![
+            CaptionCue::new("CC1".to_string(), 1000.0, 3500.0, "HI".to_string()),
+            CaptionCue::new(
+                "SERVICE1".to_string(),
+                3723004.0,
+                3725000.0,
+                "A\nB".to_string(),
+            ),
+        ];
+        assert_eq!(
+            toWebVTT(&cues),
+            "WEBVTT\n\n\
+             00:00:01.000 --> 00:00:03.500\nHI\n\n\
+             01:02:03.004 --> 01:02:05.000\nA\nB\n\n"
+        );
+        assert_eq!(toWebVTT(&[]), "WEBVTT\n\n");
+    }
+}
@@ -0,0 +1,411 @@
+// CEA-608 line 21 caption decoding: pop-on, roll-up and paint-on styles
+
+use super::captions::CaptionCue;
+
+const ROWS: usize = 15;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    None,
+    PopOn,
+    RollUp,
+    PaintOn,
+}
+
+// PAC row per (first byte & 0x07, second byte & 0x20), rows counted from 0
+const PAC_ROWS: [[usize; 2]; 8] = [
+    [10, 10],
+    [0, 1],
+    [2, 3],
+    [11, 12],
+    [13, 14],
+    [4, 5],
+    [6, 7],
+    [8, 9],
+];
+
+const SPECIAL_CHARS: [char; 16] = [
+    '®', '°', '½', '¿', '™', '¢', '£', '♪', 'à', ' ', 'è', 'â', 'ê', 'î', 'ô', 'û',
+];
+
+const EXTENDED_SPANISH_FRENCH: [char; 32] = [
+    'Á', 'É', 'Ó', 'Ú', 'Ü', 'ü', '‘', '¡', '*', '\'', '—', '©', '℠', '•', '“', '”', 'À', 'Â', 'Ç',
+    'È', 'Ê', 'Ë', 'ë', 'Î', 'Ï', 'ï', 'Ô', 'Ù', 'ù', 'Û', '«', '»',
+];
+
+const EXTENDED_PORTUGUESE_GERMAN: [char; 32] = [
+    'Ã', 'ã', 'Í', 'Ì', 'ì', 'Ò', 'ò', 'Õ', 'õ', '{', '}', '\\', '^', '_', '|', '~', 'Ä', 'ä', 'Ö',
+    'ö', 'ß', '¥', '¤', '│', 'Å', 'å', 'Ø', 'ø', '┌', '┐', '└', '┘',
+];
+
+// the basic character set is ASCII but for a few code points
+fn basicChar(byte: u8) -> char {
+    match byte {
+        0x2A => 'á',
+        0x5C => 'é',
+        0x5E => 'í',
+        0x5F => 'ó',
+        0x60 => 'ú',
+        0x7B => 'ç',
+        0x7C => '÷',
+        0x7D => 'Ñ',
+        0x7E => 'ñ',
+        0x7F => '█',
+        _ => byte as char,
+    }
+}
+
+// One caption channel (CC1 ~ CC4) with its displayed and non-displayed memories
+struct Cea608Channel {
+    name: &'static str,
+    mode: Mode,
+    displayed: Vec<String>,
+    nonDisplayed: Vec<String>,
+    row: usize,
+    rollUpRows: usize,
+    // when the displayed memory content started being shown
+    displayedSince: Option<f64>,
+}
+
+impl Cea608Channel {
+    fn new(name: &'static str) -> Cea608Channel {
+        Cea608Channel {
+            name,
+            mode: Mode::None,
+            displayed: vec![String::new(); ROWS],
+            nonDisplayed: vec![String::new(); ROWS],
+            row: ROWS - 1,
+            rollUpRows: 2,
+            displayedSince: None,
+        }
+    }
+
+    fn memory(&mut self) -> &mut Vec<String> {
+        if self.mode == Mode::PopOn {
+            &mut self.nonDisplayed
+        } else {
+            &mut self.displayed
+        }
+    }
+
+    fn text(memory: &[String]) -> String {
+        let rows: Vec<&str> = memory
+            .iter()
+            .map(|row| row.trim_end())
+            .filter(|row| !row.is_empty())
+            .collect();
+        rows.join("\n")
+    }
+
+    // Ends the cue of the displayed memory at time; what remains on screen starts a new one
+    fn flushDisplayed(&mut self, time: f64, cues: &mut Vec<CaptionCue>) {
+        let text = Cea608Channel::text(&self.displayed);
+        if let Some(since) = self.displayedSince {
+            if !text.is_empty() && time > since {
+                cues.push(CaptionCue::new(
+                    self.name.to_string(),
+                    since,
+                    time,
+                    text.clone(),
+                ));
+            }
+        }
+        self.displayedSince = if text.is_empty() { None } else { Some(time) };
+    }
+
+    fn write(&mut self, time: f64, c: char) {
+        if self.mode == Mode::None {
+            return;
+        }
+        if self.mode != Mode::PopOn && self.displayedSince.is_none() {
+            self.displayedSince = Some(time);
+        }
+        let row = self.row;
+        self.memory()[row].push(c);
+    }
+
+    fn backspace(&mut self) {
+        let row = self.row;
+        self.memory()[row].pop();
+    }
+
+    fn control(&mut self, time: f64, code: u8, cues: &mut Vec<CaptionCue>) {
+        match code {
+            // RCL: resume caption loading
+            0x20 => self.mode = Mode::PopOn,
+            // BS
+            0x21 => self.backspace(),
+            // DER: delete to end of row, the cursor being at the end already
+            0x24 => {}
+            // RU2, RU3, RU4
+            0x25..=0x27 => {
+                if self.mode != Mode::RollUp {
+                    self.flushDisplayed(time, cues);
+                    self.displayed = vec![String::new(); ROWS];
+                    self.displayedSince = None;
+                    self.row = ROWS - 1;
+                }
+                self.mode = Mode::RollUp;
+                self.rollUpRows = (code - 0x23) as usize;
+            }
+            // RDC: resume direct captioning
+            0x29 => {
+                if self.mode == Mode::RollUp {
+                    self.flushDisplayed(time, cues);
+                }
+                self.mode = Mode::PaintOn;
+            }
+            // EDM: erase displayed memory
+            0x2C => {
+                self.flushDisplayed(time, cues);
+                self.displayed = vec![String::new(); ROWS];
+                self.displayedSince = None;
+            }
+            // CR: roll the rows up
+            0x2D => {
+                if self.mode != Mode::RollUp {
+                    return;
+                }
+                self.flushDisplayed(time, cues);
+                self.displayed.remove(0);
+                self.displayed.push(String::new());
+                let top = (self.row + 1).saturating_sub(self.rollUpRows);
+                for row in 0..top {
+                    self.displayed[row].clear();
+                }
+            }
+            // ENM: erase non-displayed memory
+            0x2E => self.nonDisplayed = vec![String::new(); ROWS],
+            // EOC: end of caption, flip the memories
+            0x2F => {
+                self.flushDisplayed(time, cues);
+                std::mem::swap(&mut self.displayed, &mut self.nonDisplayed);
+                self.displayedSince = Some(time);
+                self.mode = Mode::PopOn;
+            }
+            // FON, TR, RTD: flash / text mode, not captions
+            _ => {}
+        }
+    }
+
+    fn preambleAddress(&mut self, row: usize, indent: usize) {
+        if self.mode == Mode::RollUp {
+            // the base row moves, the rows above it follow
+            if row != self.row {
+                let rows = std::cmp::min(self.rollUpRows, row + 1);
+                let mut moved = vec![String::new(); ROWS];
+                for i in 0..rows {
+                    if self.row >= i {
+                        moved[row - i] = std::mem::take(&mut self.displayed[self.row - i]);
+                    }
+                }
+                self.displayed = moved;
+            }
+        }
+        self.row = row;
+        let memory = self.memory();
+        if memory[row].chars().count() < indent {
+            let padding = indent - memory[row].chars().count();
+            memory[row].extend(std::iter::repeat(' ').take(padding));
+        }
+    }
+}
+
+pub struct Cea608Decoder {
+    // CC1, CC2 on field 1, CC3, CC4 on field 2
+    _channels: Vec<Cea608Channel>,
+    // data channel selected by the last control code, per field
+    _dataChannel: [usize; 2],
+    // control codes are sent twice, the repeat must be ignored
+    _lastControl: [Option<(u8, u8)>; 2],
+}
+
+impl Cea608Decoder {
+    pub fn new() -> Cea608Decoder {
+        Cea608Decoder {
+            _channels: vec![
+                Cea608Channel::new("CC1"),
+                Cea608Channel::new("CC2"),
+                Cea608Channel::new("CC3"),
+                Cea608Channel::new("CC4"),
+            ],
+            _dataChannel: [0, 0],
+            _lastControl: [None, None],
+        }
+    }
+
+    pub fn push(&mut self, time: f64, field: u8, data1: u8, data2: u8, cues: &mut Vec<CaptionCue>) {
+        let field = field as usize;
+        // strip the odd parity bit
+        let b1 = data1 & 0x7F;
+        let b2 = data2 & 0x7F;
+        if b1 == 0 && b2 == 0 {
+            return;
+        }
+
+        if b1 >= 0x10 && b1 <= 0x1F {
+            if self._lastControl[field] == Some((b1, b2)) {
+                self._lastControl[field] = None;
+                return;
+            }
+            self._lastControl[field] = Some((b1, b2));
+            self._dataChannel[field] = if b1 & 0x08 != 0 { 1 } else { 0 };
+            let channel = &mut self._channels[field * 2 + self._dataChannel[field]];
+            Cea608Decoder::_controlPair(channel, time, b1 & 0xF7, b2, cues);
+            return;
+        }
+        self._lastControl[field] = None;
+
+        let channel = &mut self._channels[field * 2 + self._dataChannel[field]];
+        if b1 >= 0x20 {
+            channel.write(time, basicChar(b1));
+        }
+        if b2 >= 0x20 {
+            channel.write(time, basicChar(b2));
+        }
+    }
+
+    fn _controlPair(
+        channel: &mut Cea608Channel,
+        time: f64,
+        b1: u8,
+        b2: u8,
+        cues: &mut Vec<CaptionCue>,
+    ) {
+        match (b1, b2) {
+            // miscellaneous control codes
+            (0x14, 0x20..=0x2F) | (0x15, 0x20..=0x2F) => channel.control(time, b2, cues),
+            // tab offsets
+            (0x17, 0x21..=0x23) => {
+                for _ in 0..(b2 - 0x20) {
+                    channel.write(time, ' ');
+                }
+            }
+            // mid-row codes: styling only, shown as a space
+            (0x11, 0x20..=0x2F) => channel.write(time, ' '),
+            (0x11, 0x30..=0x3F) => channel.write(time, SPECIAL_CHARS[(b2 - 0x30) as usize]),
+            // extended characters replace the basic character sent before them
+            (0x12, 0x20..=0x3F) => {
+                channel.backspace();
+                channel.write(time, EXTENDED_SPANISH_FRENCH[(b2 - 0x20) as usize]);
+            }
+            (0x13, 0x20..=0x3F) => {
+                channel.backspace();
+                channel.write(time, EXTENDED_PORTUGUESE_GERMAN[(b2 - 0x20) as usize]);
+            }
+            // preamble address codes
+            (0x10..=0x17, 0x40..=0x7F) => {
+                let row = PAC_ROWS[(b1 & 0x07) as usize][((b2 & 0x20) >> 5) as usize];
+                let indent = if b2 & 0x10 != 0 {
+                    ((b2 & 0x0E) >> 1) as usize * 4
+                } else {
+                    0
+                };
+                channel.preambleAddress(row, indent);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn flush(&mut self, time: f64, cues: &mut Vec<CaptionCue>) {
+        for channel in self._channels.iter_mut() {
+            channel.flushDisplayed(time, cues);
+            channel.displayedSince = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // RCL, ENM, PAC row 15, ..., every control code sent twice as broadcasters do
+    fn feed(
+        decoder: &mut Cea608Decoder,
+        time: f64,
+        pairs: &[(u8, u8)],
+        cues: &mut Vec<CaptionCue>,
+    ) {
+        for &(data1, data2) in pairs {
+            if data1 >= 0x10 && data1 <= 0x1F {
+                decoder.push(time, 0, data1, data2, cues);
+            }
+            decoder.push(time, 0, data1, data2, cues);
+        }
+    }
+
+    fn summary(cues: &[CaptionCue]) -> Vec<(String, f64, f64, String)> {
+        cues.iter()
+            .map(|cue| (cue.channel(), cue.startTime, cue.endTime, cue.text()))
+            .collect()
+    }
+
+    #[wasm_bindgen_test]
+    fn pop_on_caption_shows_from_eoc_to_edm() {
+        let mut decoder = Cea608Decoder::new();
+        let mut cues = vec![];
+        feed(
+            &mut decoder,
+            0.0,
+            &[(0x14, 0x20), (0x14, 0x2E), (0x14, 0x70), (b'H', b'I')],
+            &mut cues,
+        );
+        // loaded off screen, nothing shown yet
+        feed(&mut decoder, 1000.0, &[(0x14, 0x2F)], &mut cues);
+        assert!(cues.is_empty());
+
+        feed(&mut decoder, 3000.0, &[(0x14, 0x2C)], &mut cues);
+        assert_eq!(
+            summary(&cues),
+            vec![("CC1".to_string(), 1000.0, 3000.0, "HI".to_string())]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn roll_up_caption_scrolls_on_carriage_return() {
+        let mut decoder = Cea608Decoder::new();
+        let mut cues = vec![];
+        feed(&mut decoder, 0.0, &[(0x14, 0x25), (0x14, 0x70)], &mut cues);
+        feed(&mut decoder, 100.0, &[(b'A', b'B')], &mut cues);
+        feed(&mut decoder, 1000.0, &[(0x14, 0x2D)], &mut cues);
+        feed(&mut decoder, 1100.0, &[(b'C', b'D')], &mut cues);
+        feed(&mut decoder, 2000.0, &[(0x14, 0x2D)], &mut cues);
+        // RU2: only the last two rows stay up
+        feed(&mut decoder, 2100.0, &[(b'E', b'F')], &mut cues);
+        decoder.flush(3000.0, &mut cues);
+
+        assert_eq!(
+            summary(&cues),
+            vec![
+                ("CC1".to_string(), 100.0, 1000.0, "AB".to_string()),
+                ("CC1".to_string(), 1000.0, 2000.0, "AB\nCD".to_string()),
+                ("CC1".to_string(), 2000.0, 3000.0, "CD\nEF".to_string()),
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn doubled_control_code_applies_once() {
+        let mut decoder = Cea608Decoder::new();
+        let mut cues = vec![];
+        feed(
+            &mut decoder,
+            0.0,
+            &[(0x14, 0x20), (0x14, 0x70), (b'A', b'B'), (b'C', 0x00)],
+            &mut cues,
+        );
+        // BS sent twice removes one character
+        decoder.push(0.0, 0, 0x14, 0x21, &mut cues);
+        decoder.push(0.0, 0, 0x14, 0x21, &mut cues);
+        // a third one is a new command
+        decoder.push(0.0, 0, 0x14, 0x21, &mut cues);
+        feed(&mut decoder, 500.0, &[(0x14, 0x2F)], &mut cues);
+        feed(&mut decoder, 1500.0, &[(0x14, 0x2C)], &mut cues);
+
+        assert_eq!(
+            summary(&cues),
+            vec![("CC1".to_string(), 500.0, 1500.0, "A".to_string())]
+        );
+    }
+}
@@ -0,0 +1,488 @@
+// CEA-708 (DTVCC) caption decoding. Each service keeps the state of its 8 windows: text,
+// visibility (DFx, DSW, HDW, TGW) and row count. Windows are not laid out: every visible
+// window is a cue of its own, ended when the window is hidden, cleared or deleted, and when
+// a carriage return rolls its rows up. DLY / DLC and the pen / window attributes are ignored.
+
+use super::captions::CaptionCue;
+
+const WINDOWS: usize = 8;
+
+#[derive(Clone)]
+struct Cea708Window {
+    visible: bool,
+    // rows kept when rolling up, from the DFx row count
+    rowCount: usize,
+    text: String,
+    // when the current text started being shown
+    since: Option<f64>,
+}
+
+impl Cea708Window {
+    fn new() -> Cea708Window {
+        Cea708Window {
+            visible: false,
+            rowCount: 15,
+            text: String::new(),
+            since: None,
+        }
+    }
+
+    fn write(&mut self, time: f64, c: char) {
+        if self.visible && self.since.is_none() {
+            self.since = Some(time);
+        }
+        self.text.push(c);
+    }
+
+    // Ends the cue of what is shown at time
+    fn endCue(&mut self, channel: &str, time: f64, cues: &mut Vec<CaptionCue>) {
+        let text = self.text.trim().to_string();
+        if let Some(since) = self.since {
+            if !text.is_empty() && time > since {
+                cues.push(CaptionCue::new(channel.to_string(), since, time, text));
+            }
+        }
+        self.since = None;
+    }
+
+    fn show(&mut self, time: f64) {
+        if !self.visible {
+            self.visible = true;
+            if !self.text.trim().is_empty() {
+                self.since = Some(time);
+            }
+        }
+    }
+
+    fn hide(&mut self, channel: &str, time: f64, cues: &mut Vec<CaptionCue>) {
+        self.endCue(channel, time, cues);
+        self.visible = false;
+    }
+
+    fn clear(&mut self, channel: &str, time: f64, cues: &mut Vec<CaptionCue>) {
+        self.endCue(channel, time, cues);
+        self.text.clear();
+    }
+
+    // CR: a new row, the top ones scrolling out past the row count
+    fn carriageReturn(&mut self, channel: &str, time: f64, cues: &mut Vec<CaptionCue>) {
+        self.endCue(channel, time, cues);
+        self.text.push('\n');
+        let rows: Vec<&str> = self.text.split('\n').collect();
+        if rows.len() > self.rowCount {
+            self.text = rows[rows.len() - self.rowCount..].join("\n");
+        }
+        if self.visible && !self.text.trim().is_empty() {
+            self.since = Some(time);
+        }
+    }
+}
+
+struct Cea708Service {
+    number: u8,
+    channel: String,
+    windows: Vec<Cea708Window>,
+    current: usize,
+}
+
+impl Cea708Service {
+    fn new(number: u8) -> Cea708Service {
+        Cea708Service {
+            number,
+            channel: format!("SERVICE{}", number),
+            windows: vec![Cea708Window::new(); WINDOWS],
+            current: 0,
+        }
+    }
+
+    fn window(&mut self) -> &mut Cea708Window {
+        &mut self.windows[self.current]
+    }
+
+    fn write(&mut self, time: f64, c: char) {
+        self.windows[self.current].write(time, c);
+    }
+
+    // CLW, DSW, HDW, TGW, DLW: the windows of a bitmap
+    fn windowCommand(&mut self, code: u8, bitmap: u8, time: f64, cues: &mut Vec<CaptionCue>) {
+        let channel = &self.channel;
+        for (index, window) in self.windows.iter_mut().enumerate() {
+            if bitmap & (1 << index) == 0 {
+                continue;
+            }
+            match code {
+                0x88 => window.clear(channel, time, cues),
+                0x89 => window.show(time),
+                0x8A => window.hide(channel, time, cues),
+                0x8B => {
+                    if window.visible {
+                        window.hide(channel, time, cues)
+                    } else {
+                        window.show(time)
+                    }
+                }
+                0x8C => {
+                    window.clear(channel, time, cues);
+                    *window = Cea708Window::new();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // DF0 ~ DF7: defines (or updates) the window and makes it current
+    fn defineWindow(
+        &mut self,
+        index: usize,
+        parameters: &[u8],
+        time: f64,
+        cues: &mut Vec<CaptionCue>,
+    ) {
+        self.current = index;
+        let window = &mut self.windows[index];
+        // 0 0 v rl cl p2 p1 p0, rp av, ah, ap rc, ...
+        window.rowCount = (parameters[3] & 0x0F) as usize + 1;
+        if parameters[0] & 0x20 != 0 {
+            window.show(time);
+        } else {
+            window.hide(&self.channel, time, cues);
+        }
+    }
+
+    fn flush(&mut self, time: f64, cues: &mut Vec<CaptionCue>) {
+        let channel = &self.channel;
+        for window in self.windows.iter_mut() {
+            window.endCue(channel, time, cues);
+        }
+    }
+}
+
+// parameter bytes of the C1 window commands, 0x80 ~ 0x9F
+const C1_PARAMETER_SIZES: [usize; 32] = [
+    0, 0, 0, 0, 0, 0, 0, 0, // CW0 ~ CW7
+    1, 1, 1, 1, 1, 1, 0, 0, // CLW, DSW, HDW, TGW, DLW, DLY, DLC, RST
+    2, 3, 2, 0, 0, 0, 0, 4, // SPA, SPC, SPL, reserved, SWA
+    6, 6, 6, 6, 6, 6, 6, 6, // DF0 ~ DF7
+];
+
+pub struct Cea708Decoder {
+    _packet: Vec<u8>,
+    _packetTime: f64,
+    _services: Vec<Cea708Service>,
+}
+
+impl Cea708Decoder {
+    pub fn new() -> Cea708Decoder {
+        Cea708Decoder {
+            _packet: Vec::new(),
+            _packetTime: 0.0,
+            _services: Vec::new(),
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        time: f64,
+        packetStart: bool,
+        data1: u8,
+        data2: u8,
+        cues: &mut Vec<CaptionCue>,
+    ) {
+        if packetStart {
+            self._decodePacket(cues);
+            self._packetTime = time;
+        }
+        self._packet.push(data1);
+        self._packet.push(data2);
+    }
+
+    pub fn flush(&mut self, time: f64, cues: &mut Vec<CaptionCue>) {
+        self._decodePacket(cues);
+        for service in self._services.iter_mut() {
+            service.flush(time, cues);
+        }
+    }
+
+    // caption_channel_packet(): sequence_number(2) + packet_size_code(6) + service blocks
+    fn _decodePacket(&mut self, cues: &mut Vec<CaptionCue>) {
+        let packet = std::mem::take(&mut self._packet);
+        if packet.is_empty() {
+            return;
+        }
+        let packetSizeCode = (packet[0] & 0x3F) as usize;
+        let packetSize = if packetSizeCode == 0 {
+            128
+        } else {
+            packetSizeCode * 2
+        };
+        let end = std::cmp::min(packetSize, packet.len());
+        let mut offset = 1;
+
+        while offset < end {
+            // service_number(3) + block_size(5)
+            let mut serviceNumber = packet[offset] >> 5;
+            let blockSize = (packet[offset] & 0x1F) as usize;
+            offset += 1;
+            if serviceNumber == 7 && offset < end {
+                serviceNumber = packet[offset] & 0x3F;
+                offset += 1;
+            }
+            // null block header: padding up to the end of the packet
+            if serviceNumber == 0 {
+                break;
+            }
+            let blockEnd = std::cmp::min(offset + blockSize, end);
+            let time = self._packetTime;
+            self._decodeServiceBlock(serviceNumber, time, &packet[offset..blockEnd], cues);
+            offset = blockEnd;
+        }
+    }
+
+    fn _service(&mut self, number: u8) -> &mut Cea708Service {
+        let index = match self
+            ._services
+            .iter()
+            .position(|service| service.number == number)
+        {
+            Some(index) => index,
+            None => {
+                self._services.push(Cea708Service::new(number));
+                self._services.len() - 1
+            }
+        };
+        &mut self._services[index]
+    }
+
+    fn _decodeServiceBlock(
+        &mut self,
+        serviceNumber: u8,
+        time: f64,
+        data: &[u8],
+        cues: &mut Vec<CaptionCue>,
+    ) {
+        let service = self._service(serviceNumber);
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let code = data[offset];
+            offset += 1;
+            match code {
+                // C0: BS, FF, CR, HCR
+                0x08 => {
+                    service.window().text.pop();
+                }
+                0x0C => {
+                    let channel = &service.channel;
+                    service.windows[service.current].clear(channel, time, cues);
+                }
+                0x0D => {
+                    let channel = &service.channel;
+                    service.windows[service.current].carriageReturn(channel, time, cues);
+                }
+                0x0E => {
+                    let window = service.window();
+                    while !window.text.is_empty() && !window.text.ends_with('\n') {
+                        window.text.pop();
+                    }
+                }
+                // EXT1: G2 / G3 / C2 / C3 code sets, only the printable ones are kept
+                0x10 => {
+                    let extended = match data.get(offset) {
+                        Some(&extended) => extended,
+                        None => break,
+                    };
+                    offset += 1;
+                    match extended {
+                        0x00..=0x07 => {}
+                        0x08..=0x0F => offset += 1,
+                        0x10..=0x17 => offset += 2,
+                        0x18..=0x1F => offset += 3,
+                        0x80..=0x87 => offset += 4,
+                        0x88..=0x8F => offset += 5,
+                        // variable length C3 commands
+                        0x90..=0x9F => {
+                            let length = data.get(offset).map(|byte| (byte & 0x3F) as usize);
+                            offset += 1 + length.unwrap_or(0);
+                        }
+                        _ => service.write(time, g2Char(extended)),
+                    }
+                }
+                0x00..=0x0F => {}
+                0x11..=0x17 => offset += 1,
+                0x18..=0x1F => offset += 2,
+                // G0: ASCII, but for the music note
+                0x7F => service.write(time, '♪'),
+                0x20..=0x7E => service.write(time, code as char),
+                // C1: window commands
+                0x80..=0x9F => {
+                    let size = C1_PARAMETER_SIZES[(code - 0x80) as usize];
+                    let parameters = match data.get(offset..offset + size) {
+                        Some(parameters) => parameters,
+                        None => break,
+                    };
+                    offset += size;
+                    match code {
+                        // CW0 ~ CW7
+                        0x80..=0x87 => service.current = (code - 0x80) as usize,
+                        0x88..=0x8C => service.windowCommand(code, parameters[0], time, cues),
+                        // RST: all windows deleted
+                        0x8F => service.windowCommand(0x8C, 0xFF, time, cues),
+                        0x98..=0x9F => {
+                            service.defineWindow((code - 0x98) as usize, parameters, time, cues)
+                        }
+                        _ => {}
+                    }
+                }
+                // G1: ISO 8859-1
+                0xA0..=0xFF => service.write(time, code as char),
+            }
+        }
+    }
+}
+
+// the printable G2 characters, CEA-708 7.1.8
+fn g2Char(code: u8) -> char {
+    match code {
+        0x20 | 0x21 => ' ',
+        0x25 => '…',
+        0x2A => 'Š',
+        0x2C => 'Œ',
+        0x30 => '█',
+        0x31 => '‘',
+        0x32 => '’',
+        0x33 => '“',
+        0x34 => '”',
+        0x35 => '•',
+        0x39 => '™',
+        0x3A => 'š',
+        0x3C => 'œ',
+        0x3D => '℠',
+        0x3F => 'Ÿ',
+        0x76 => '⅛',
+        0x77 => '⅜',
+        0x78 => '⅝',
+        0x79 => '⅞',
+        0x7A => '│',
+        0x7B => '┐',
+        0x7C => '└',
+        0x7D => '─',
+        0x7E => '┘',
+        0x7F => '┌',
+        _ => ' ',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // caption_channel_packet carrying one service 1 block
+    fn feed(decoder: &mut Cea708Decoder, time: f64, block: &[u8], cues: &mut Vec<CaptionCue>) {
+        let mut packet = vec![0, 0x20 | block.len() as u8];
+        packet.extend_from_slice(block);
+        if packet.len() % 2 == 1 {
+            packet.push(0);
+        }
+        packet[0] = (packet.len() / 2) as u8;
+        for (i, pair) in packet.chunks(2).enumerate() {
+            decoder.push(time, i == 0, pair[0], pair[1], cues);
+        }
+    }
+
+    // DFx: visible flag, anchor, two rows, 32 columns
+    fn defineWindow(index: u8, visible: bool) -> Vec<u8> {
+        let visible = if visible { 0x20 } else { 0x00 };
+        vec![0x98 + index, visible, 0x00, 0x00, 0x01, 0x1F, 0x00]
+    }
+
+    fn summary(cues: &[CaptionCue]) -> Vec<(String, f64, f64, String)> {
+        cues.iter()
+            .map(|cue| (cue.channel(), cue.startTime, cue.endTime, cue.text()))
+            .collect()
+    }
+
+    #[wasm_bindgen_test]
+    fn hidden_window_shows_on_display_windows() {
+        let mut decoder = Cea708Decoder::new();
+        let mut cues = vec![];
+        let mut block = defineWindow(0, false);
+        block.extend_from_slice(b"Hi");
+        feed(&mut decoder, 0.0, &block, &mut cues);
+        // DSW window 0
+        feed(&mut decoder, 1000.0, &[0x89, 0x01], &mut cues);
+        // HDW window 0
+        feed(&mut decoder, 3000.0, &[0x8A, 0x01], &mut cues);
+        decoder.flush(4000.0, &mut cues);
+
+        assert_eq!(
+            summary(&cues),
+            vec![("SERVICE1".to_string(), 1000.0, 3000.0, "Hi".to_string())]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn toggle_windows_flips_visibility() {
+        let mut decoder = Cea708Decoder::new();
+        let mut cues = vec![];
+        let mut block = defineWindow(1, true);
+        block.extend_from_slice(b"A");
+        feed(&mut decoder, 0.0, &block, &mut cues);
+        // TGW window 1, twice
+        feed(&mut decoder, 500.0, &[0x8B, 0x02], &mut cues);
+        feed(&mut decoder, 800.0, &[0x8B, 0x02], &mut cues);
+        decoder.flush(1000.0, &mut cues);
+
+        assert_eq!(
+            summary(&cues),
+            vec![
+                ("SERVICE1".to_string(), 0.0, 500.0, "A".to_string()),
+                ("SERVICE1".to_string(), 800.0, 1000.0, "A".to_string()),
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn each_visible_window_is_its_own_cue() {
+        let mut decoder = Cea708Decoder::new();
+        let mut cues = vec![];
+        let mut block = defineWindow(0, true);
+        block.extend_from_slice(b"top");
+        block.extend(defineWindow(1, true));
+        block.extend_from_slice(b"bottom");
+        feed(&mut decoder, 0.0, &block, &mut cues);
+        // CLW windows 0 and 1
+        feed(&mut decoder, 2000.0, &[0x88, 0x03], &mut cues);
+        decoder.flush(3000.0, &mut cues);
+
+        assert_eq!(
+            summary(&cues),
+            vec![
+                ("SERVICE1".to_string(), 0.0, 2000.0, "top".to_string()),
+                ("SERVICE1".to_string(), 0.0, 2000.0, "bottom".to_string()),
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn carriage_return_rolls_up_the_window() {
+        let mut decoder = Cea708Decoder::new();
+        let mut cues = vec![];
+        let mut block = defineWindow(0, true);
+        block.extend_from_slice(b"A");
+        feed(&mut decoder, 0.0, &block, &mut cues);
+        feed(&mut decoder, 1000.0, &[0x0D, b'B'], &mut cues);
+        // two rows: the first one scrolls off
+        feed(&mut decoder, 2000.0, &[0x0D, b'C'], &mut cues);
+        decoder.flush(3000.0, &mut cues);
+
+        assert_eq!(
+            summary(&cues),
+            vec![
+                ("SERVICE1".to_string(), 0.0, 1000.0, "A".to_string()),
+                ("SERVICE1".to_string(), 1000.0, 2000.0, "A\nB".to_string()),
+                ("SERVICE1".to_string(), 2000.0, 3000.0, "B\nC".to_string()),
+            ]
+        );
+    }
+}
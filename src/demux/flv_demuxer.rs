@@ -15,10 +15,19 @@
  * See the License for the specific language governing permissions and
  * limitations under the License.
  */
+use super::aac_config;
 use super::amf_parser::{parseScriptData, AMFValue, ScriptData};
+use super::captions::{self, CaptionCue, CaptionDecoder};
 use super::demux_errors;
+use super::flv_tags::{self, TimestampExtender};
+use super::hdr::{self, HdrInfo};
+use super::producer_reference::{self, ProducerReference};
+use super::sei_parser::{self, SEIMessage, USER_DATA_REGISTERED_ITU_T_T35, USER_DATA_UNREGISTERED};
+use super::sps_parser::{self, ColorInfo, Size};
+use crate::config::{Config, ProducerReferenceTimeSource};
 use crate::core::media_info::MediaInfo;
 use crate::io::loader::Loader;
+use crate::remux::annexb::{self, NaluCodec};
 use crate::remux::mp4_muxer::MP4Remuxer;
 use crate::utils::{browser::Browser, logger::Log};
 use crate::web_sys_wrappers::interval::Interval;
@@ -45,78 +54,174 @@ const mpegAudioL3BitRateTable: &[u8] = &[
 
 const flvSoundRateTable: &[u32] = &[5500, 11025, 22050, 44100, 48000];
 
-const mpegSamplingRates: &[u8] = &[
-    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
-];
+pub struct KeyframesIndex {
+    pub times: Vec<f64>,
+    pub filepositions: Vec<f64>,
+}
+
+// keyframes index from onMetaData, times are converted to milliseconds
+pub fn parseKeyframesIndex(
+    keyframes: &HashMap<String, AMFValue>,
+    timestampBase: i32,
+) -> Option<KeyframesIndex> {
+    let (times, filepositions) = match (keyframes.get("times"), keyframes.get("filepositions")) {
+        (Some(AMFValue::Array(times)), Some(AMFValue::Array(filepositions))) => {
+            (times, filepositions)
+        }
+        _ => return None,
+    };
+
+    let mut keyframesIndex = KeyframesIndex {
+        times: vec![],
+        filepositions: vec![],
+    };
+
+    // the first keyframe is skipped, as it is located right after the sequence headers
+    for i in 1..times.len().min(filepositions.len()) {
+        if let (AMFValue::Number(time), AMFValue::Number(fileposition)) =
+            (&times[i], &filepositions[i])
+        {
+            keyframesIndex
+                .times
+                .push(timestampBase as f64 + (time * 1000.0).floor());
+            keyframesIndex.filepositions.push(*fileposition);
+        }
+    }
 
-struct KeyframesIndex {
-    times: Vec<f64>,
-    filepositions: Vec<f64>,
+    Some(keyframesIndex)
+}
+
+// Clockwise rotation in degrees, snapped to a multiple of 90 in [0, 360)
+fn normalizeRotation(degrees: f64) -> u32 {
+    let quarterTurns = (degrees / 90.0).round() as i64;
+    (quarterTurns.rem_euclid(4) * 90) as u32
 }
 
 pub struct VideoTrack {
-    _type: &'static str,
-    sequenceNumber: i32,
-    id: i32,
-    samples: Vec<Sample>,
-    length: i32,
+    pub _type: &'static str,
+    pub sequenceNumber: i32,
+    pub id: i32,
+    pub samples: Vec<Sample>,
+    pub length: i32,
 }
 
 pub struct VideoSample {
     pub units: Vec<Unit>,
     length: u32,
     isKeyframe: bool,
-    dts: u32,
+    // extended across 32-bit timestamp wraparounds
+    dts: i64,
     cts: i32,
-    pts: u32,
+    pts: i64,
     fileposition: Option<u32>,
 }
 
 pub struct AudioTrack {
-    _type: &'static str,
-    sequenceNumber: i32,
-    id: i32,
-    samples: Vec<Sample>,
-    length: i32,
+    pub _type: &'static str,
+    pub sequenceNumber: i32,
+    pub id: i32,
+    pub samples: Vec<Sample>,
+    pub length: i32,
 }
 
 pub struct AudioSample {
     unit: Vec<u8>,
     length: usize,
-    dts: u32,
-    pts: u32,
+    dts: i64,
+    pts: i64,
     units: (),
     isKeyframe: (),
     cts: (),
 }
 
 pub struct AudioTrackMetaData {
-    id: i32,
-    data: Vec<u8>,
-    timescale: u32,
-    duration: u32,
-    codec: String,
-    audioSampleRate: f64,
-    channelCount: usize,
+    pub id: i32,
+    pub data: Vec<u8>,
+    pub timescale: u32,
+    pub duration: u32,
+    pub codec: String,
+    pub originalCodec: String,
+    pub config: Vec<u8>,
+    // AudioSpecificConfig as found in the stream, before any browser workaround,
+    // the first frame header for mp3
+    pub originalConfig: Vec<u8>,
+    pub audioSampleRate: f64,
+    pub channelCount: usize,
+    pub refSampleDuration: f64,
 }
 
+#[derive(Clone)]
 pub struct VideoTrackMetaData {
-    id: u32,
-    timescale: u32,
-    duration: u32,
-    codecWidth: u32,
-    codecHeight: u32,
-    presentWidth: u32,
-    presentHeight: u32,
-    profile: u32,
-    level: u32,
-    bitDepth: u32,
-    chromaFormat: u32,
-    sarRatio: u32,
-    frameRate: u32,
-    refSampleDuration: u32,
-    codec: String,
-    avcc: Vec<u8>,
+    pub id: u32,
+    pub timescale: u32,
+    pub duration: u32,
+    pub codecWidth: u32,
+    pub codecHeight: u32,
+    pub presentWidth: u32,
+    pub presentHeight: u32,
+    pub profile: u32,
+    pub level: u32,
+    pub bitDepth: u32,
+    pub chromaFormat: u32,
+    pub sarRatio: Size,
+    pub color: Option<ColorInfo>,
+    pub hdr: HdrInfo,
+    // clockwise degrees and horizontal flip requested by the publisher
+    pub rotation: u32,
+    pub mirror: bool,
+    pub frameRate: u32,
+    // fractional, e.g. 33.367ms at 29.97fps
+    pub refSampleDuration: f64,
+    pub codec: String,
+    pub avcc: Vec<u8>,
+}
+
+// A script tag other than onMetaData (onCuePoint, onTextData, onFI, ...), kept as raw AMF0
+pub struct TimedMetadata {
+    pub name: String,
+    // in milliseconds, on the same timeline as the audio / video dts
+    pub dts: i64,
+    // the whole tag body: AMF0 name + value
+    pub data: Vec<u8>,
+}
+
+// A user_data_unregistered SEI message with one of the registered UUIDs
+#[wasm_bindgen]
+pub struct SEIData {
+    uuid: Vec<u8>,
+    payload: Vec<u8>,
+    // in milliseconds
+    pub pts: f64,
+}
+
+#[wasm_bindgen]
+impl SEIData {
+    #[wasm_bindgen(getter)]
+    pub fn uuid(&self) -> Vec<u8> {
+        self.uuid.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+}
+
+// Passed to onLatencyChase once the samples up to a keyframe have been dropped
+#[wasm_bindgen]
+#[derive(Copy, Clone)]
+pub struct LatencyChase {
+    // ms behind the live edge when the dropping started
+    pub latency: f64,
+    // ms of stream time dropped, closed in the output timeline
+    pub droppedDuration: f64,
+    // of the keyframe resumed at, raw tag timestamp
+    pub keyframeDts: f64,
+}
+
+struct LatencyChasing {
+    fromTimestamp: i64,
+    latency: f64,
 }
 
 pub enum TrackMetaData {
@@ -125,12 +230,15 @@ pub enum TrackMetaData {
 }
 
 struct AudioConfig {
-    config: (),
+    config: Vec<u8>,
+    originalConfig: Vec<u8>,
     bitRate: u32,
     samplingRate: u32,
     channelCount: u32,
-    codec: &'static str,
-    originalCodec: &'static str,
+    // PCM samples per frame at samplingRate
+    frameLength: u32,
+    codec: String,
+    originalCodec: String,
 }
 
 pub enum AudioData {
@@ -138,16 +246,17 @@ pub enum AudioData {
     Data(Vec<u8>),
 }
 
-struct FLVProbeData {
-    consumed: usize,
-    dataOffset: usize,
-    hasAudioTrack: bool,
-    hasVideoTrack: bool,
+pub struct FLVProbeData {
+    pub consumed: usize,
+    pub dataOffset: usize,
+    pub hasAudioTrack: bool,
+    pub hasVideoTrack: bool,
 }
 
-struct Unit {
-    _type: u32,
-    data: Vec<u8>,
+#[derive(Clone)]
+pub struct Unit {
+    pub _type: u32,
+    pub data: Vec<u8>,
 }
 
 struct VideoDimension {
@@ -173,10 +282,10 @@ struct BpsInfo {
     time_gap_factor: usize,
 }
 
-struct Config {}
-
 type MetaCallback = fn(this: &MP4Remuxer, meta: TrackMetaData);
 type DataCallback = fn(this: &MP4Remuxer, audioTrack: &mut AudioTrack, videoTrack: &mut VideoTrack);
+type TimedMetadataCallback = fn(this: &mut MP4Remuxer, metadata: TimedMetadata);
+type ProducerReferenceCallback = fn(this: &mut MP4Remuxer, reference: ProducerReference);
 
 pub struct FLVDemuxer {
     TAG: &'static str,
@@ -200,6 +309,7 @@ pub struct FLVDemuxer {
     _videoMetadata: Option<VideoTrackMetaData>,
     _naluLengthSize: i32,
     _timestampBase: i32,
+    _timestampExtender: TimestampExtender,
     _timescale: i32,
     _duration: i32,
     _durationOverrided: bool,
@@ -209,11 +319,34 @@ pub struct FLVDemuxer {
     _bpsInfo: Option<BpsInfo>,
 
     _now: Function,
+    // arrival time - tag timestamp, the lowest seen: tags delivered at the live edge
+    _liveEdgeOffset: Option<f64>,
+    // ms behind the live edge, of the latest audio / video tag
+    _liveLatency: f64,
+    // set while dropping tags up to the next keyframe
+    _latencyChasing: Option<LatencyChasing>,
 
     _videoTrack: VideoTrack,
     _audioTrack: AudioTrack,
+    // CEA-608/708 from SEI, when enabled
+    _captionDecoder: Option<CaptionDecoder>,
+    // cues decoded while no onCaptionCue callback is set, taken as WebVTT
+    _captionCues: Vec<CaptionCue>,
+    // dts of the last decoded video frame, where the cues still on screen end when flushed
+    _captionTime: f64,
+    // HDR static metadata from SEI or onMetaData colorInfo, colour description from colorInfo
+    _hdrInfo: HdrInfo,
+    _metadataColor: Option<ColorInfo>,
+    // from onMetaData
+    _rotation: u32,
+    _mirror: bool,
     _onTrackMetadata: Option<MetaCallback>,
     _onDataAvailable: Option<DataCallback>,
+    _onTimedMetadata: Option<TimedMetadataCallback>,
+    _onProducerReference: Option<ProducerReferenceCallback>,
+    _onCaptionCue: Option<Function>,
+    _onSEI: Option<Function>,
+    _onLatencyChase: Option<Function>,
     _onMetaDataArrived: Option<Function>,
     _onMediaInfo: Option<Function>,
     _onError: Option<Function>,
@@ -269,6 +402,7 @@ impl FLVDemuxer {
             _videoMetadata: None,
             _naluLengthSize: 4, // int32, in milliseconds
             _timestampBase: 0,
+            _timestampExtender: TimestampExtender::new(),
             _timescale: 1000,
 
             _duration: 0,
@@ -301,13 +435,31 @@ impl FLVDemuxer {
                     .and_then(|performance| performance.now())
                     .or(Date::now())
             },
+            _liveEdgeOffset: None,
+            _liveLatency: 0.0,
+            _latencyChasing: None,
 
             _audioMetadata: None,
             _videoMetadata: None,
+
+            _captionDecoder: if config.enableCaptions {
+                Some(CaptionDecoder::new())
+            } else {
+                None
+            },
+            _captionCues: Vec::new(),
+            _captionTime: 0.0,
+            _hdrInfo: HdrInfo::default(),
+            _metadataColor: None,
+            _rotation: 0,
+            _mirror: false,
         }
     }
 
-    fn destroy(&self) {
+    fn destroy(&mut self) {
+        // the cues still on screen go out before the callback is dropped
+        self._flushCaptions();
+
         self._mediaInfo = None;
         self._metadata = None;
         self._audioMetadata = None;
@@ -321,6 +473,13 @@ impl FLVDemuxer {
         self._onScriptDataArrived = None;
         self._onTrackMetadata = None;
         self._onDataAvailable = None;
+        self._onTimedMetadata = None;
+        self._onProducerReference = None;
+        self._onCaptionCue = None;
+        self._onSEI = None;
+        self._onLatencyChase = None;
+        self._captionDecoder = None;
+        self._captionCues.clear();
         self._lastVideoDimension = None;
         self._bpsCalculator = None;
 
@@ -334,7 +493,7 @@ impl FLVDemuxer {
     /*
      * 读取FLV的header信息
      */
-    fn probe(data: &[u8]) -> Option<FLVProbeData> {
+    pub fn probe(data: &[u8]) -> Option<FLVProbeData> {
         // "F L V version"
         if data[0] != 0x46 || data[1] != 0x4C || data[2] != 0x56 || data[3] != 0x01 {
             return None;
@@ -382,6 +541,104 @@ impl FLVDemuxer {
         self._onDataAvailable = callback;
     }
 
+    #[wasm_bindgen(getter, js_name = "onTimedMetadata")]
+    pub fn get_onTimedMetadata(&self) -> Option<TimedMetadataCallback> {
+        self._onTimedMetadata
+    }
+
+    #[wasm_bindgen(setter, js_name = "onTimedMetadata")]
+    pub fn set_onTimedMetadata(&self, callback: Option<TimedMetadataCallback>) {
+        self._onTimedMetadata = callback;
+    }
+
+    #[wasm_bindgen(getter, js_name = "onProducerReference")]
+    pub fn get_onProducerReference(&self) -> Option<ProducerReferenceCallback> {
+        self._onProducerReference
+    }
+
+    #[wasm_bindgen(setter, js_name = "onProducerReference")]
+    pub fn set_onProducerReference(&self, callback: Option<ProducerReferenceCallback>) {
+        self._onProducerReference = callback;
+    }
+
+    #[wasm_bindgen(getter, js_name = "onCaptionCue")]
+    pub fn get_onCaptionCue(&self) -> Option<Function> {
+        self._onCaptionCue.clone()
+    }
+
+    #[wasm_bindgen(setter, js_name = "onCaptionCue")]
+    pub fn set_onCaptionCue(&mut self, callback: Option<Function>) {
+        self._onCaptionCue = callback;
+    }
+
+    #[wasm_bindgen(getter, js_name = "onSEI")]
+    pub fn get_onSEI(&self) -> Option<Function> {
+        self._onSEI.clone()
+    }
+
+    #[wasm_bindgen(setter, js_name = "onSEI")]
+    pub fn set_onSEI(&mut self, callback: Option<Function>) {
+        self._onSEI = callback;
+    }
+
+    #[wasm_bindgen(getter, js_name = "onLatencyChase")]
+    pub fn get_onLatencyChase(&self) -> Option<Function> {
+        self._onLatencyChase.clone()
+    }
+
+    #[wasm_bindgen(setter, js_name = "onLatencyChase")]
+    pub fn set_onLatencyChase(&mut self, callback: Option<Function>) {
+        self._onLatencyChase = callback;
+    }
+
+    // ms behind the live edge, 0 unless Config.enableLatencyChasing
+    #[wasm_bindgen(getter, js_name = "liveLatency")]
+    pub fn liveLatency(&self) -> f64 {
+        self._liveLatency
+    }
+
+    // Playback rate for the player to catch up with the live edge: 1 up to liveLatencyTarget,
+    // then rising linearly up to maxPlaybackRate at liveLatencyDropThreshold
+    #[wasm_bindgen(getter, js_name = "recommendedPlaybackRate")]
+    pub fn recommendedPlaybackRate(&self) -> f64 {
+        let target = self._config.liveLatencyTarget;
+        let threshold = self._config.liveLatencyDropThreshold;
+        if self._liveLatency <= target || threshold <= target {
+            return 1.0;
+        }
+        let progress = ((self._liveLatency - target) / (threshold - target)).min(1.0);
+        1.0 + progress * (self._config.maxPlaybackRate - 1.0)
+    }
+
+    // Caption cues decoded so far as a WebVTT document, when no onCaptionCue callback is set.
+    // The cues still on screen are closed at the last video frame.
+    pub fn takeWebVTT(&mut self) -> String {
+        self._flushCaptions();
+        let cues = std::mem::take(&mut self._captionCues);
+        captions::toWebVTT(&cues)
+    }
+
+    fn _flushCaptions(&mut self) {
+        let cues = match &mut self._captionDecoder {
+            Some(captionDecoder) => captionDecoder.flush(self._captionTime),
+            None => vec![],
+        };
+        if !cues.is_empty() {
+            self._dispatchCaptionCues(cues);
+        }
+    }
+
+    fn _dispatchCaptionCues(&mut self, cues: Vec<CaptionCue>) {
+        match &self._onCaptionCue {
+            Some(onCaptionCue) => {
+                for cue in cues {
+                    let _ = onCaptionCue.call1(&JsValue::null(), &JsValue::from(cue));
+                }
+            }
+            None => self._captionCues.extend(cues),
+        }
+    }
+
     fn resetMediaInfo(&self) {
         self._mediaInfo = MediaInfo::default();
     }
@@ -425,7 +682,7 @@ impl FLVDemuxer {
         //     + ", time_gap_factor:" + self._bpsInfo.time_gap_factor);
     }
 
-    fn parseChunks(&self, chunk: &[u8], byteStart: usize) -> usize {
+    fn parseChunks(&mut self, chunk: &[u8], byteStart: usize) -> usize {
         if self._onError.is_none()
             || self._onMediaInfo.is_none()
             || self._onTrackMetadata.is_none()
@@ -468,6 +725,10 @@ impl FLVDemuxer {
 
         let videoBytes: u32 = 0;
         let audioBytes = 0;
+        // all the tags of a chunk arrive together
+        let arrivalTime = self._now();
+        // of the last audio / video tag
+        let mut lastMediaDts: Option<i64> = None;
 
         while offset < chunk.len() {
             self._dispatch = true;
@@ -503,6 +764,8 @@ impl FLVDemuxer {
 
             // 3字节时间戳 + 1字节扩展时间戳
             let timestamp = ts0 | (ts1 << 8) | (ts2 << 16) | (ts3 << 24);
+            // 64-bit from here on, across 32-bit wraparounds
+            let timestamp = self._timestampExtender.extend(timestamp);
 
             // 3字节：streamId，总是为0
             let streamId = v.read_u24::<BigEndian>().unwrap();
@@ -512,11 +775,20 @@ impl FLVDemuxer {
 
             let dataOffset = offset + 11; //+11跳过tag header，指向tag data
 
+            if self._config.isLive && self._config.enableLatencyChasing && tagType != 18 {
+                let tag = flv_tags::readTag(chunk, offset).unwrap();
+                if self._chaseLiveLatency(&tag, timestamp, arrivalTime) {
+                    offset += 11 + dataSize + 4;
+                    continue;
+                }
+            }
+
             match tagType {
                 // Audio
                 8 => {
                     self._parseAudioData(chunk, dataOffset, dataSize, timestamp);
                     audioBytes += dataSize;
+                    lastMediaDts = Some(self._timestampBase as i64 + timestamp);
                 }
                 // Video
                 9 => {
@@ -528,10 +800,11 @@ impl FLVDemuxer {
                         byteStart + offset,
                     );
                     videoBytes += dataSize;
+                    lastMediaDts = Some(self._timestampBase as i64 + timestamp);
                 }
                 // ScriptDataObject
                 18 => {
-                    self._parseScriptData(chunk, dataOffset, dataSize);
+                    self._parseScriptData(chunk, dataOffset, dataSize, timestamp);
                 }
                 _ => {
                     unreachable!();
@@ -568,6 +841,12 @@ impl FLVDemuxer {
             bpsInfo.data_updated_time = self._now();
         });
 
+        if let Some(dts) = lastMediaDts {
+            if self._config.producerReferenceTimeSource == ProducerReferenceTimeSource::Arrival {
+                self._emitProducerReference(dts, Date::now());
+            }
+        }
+
         if self._isInitialMetadataDispatched() {
             if self._dispatch && (self._audioTrack.length > 0 || self._videoTrack.length > 0) {
                 self._onDataAvailable.unwrap()(&mut self._audioTrack, &mut self._videoTrack);
@@ -577,14 +856,131 @@ impl FLVDemuxer {
         return offset; // consumed bytes, just equals latest offset index
     }
 
+    // Updates the latency estimate with an audio / video tag, tells whether to drop it.
+    // Once past liveLatencyDropThreshold, every incoming tag but the sequence headers is dropped
+    // before parsing, audio included, until the next video keyframe (until latency -
+    // liveLatencyTarget ms have gone by on audio only streams). The tag crossing the threshold
+    // is dropped too. The dropped span is taken out of the timestamps so the output timeline
+    // stays continuous, and onLatencyChase is called at the keyframe resumed at
+    fn _chaseLiveLatency(
+        &mut self,
+        tag: &flv_tags::FlvTag,
+        timestamp: i64,
+        arrivalTime: f64,
+    ) -> bool {
+        let offset = arrivalTime - timestamp as f64;
+        let edge = match self._liveEdgeOffset {
+            Some(edge) if edge <= offset => edge,
+            _ => {
+                self._liveEdgeOffset = Some(offset);
+                offset
+            }
+        };
+        self._liveLatency = offset - edge;
+
+        if flv_tags::isSequenceHeader(tag) {
+            return false;
+        }
+
+        if let Some(chasing) = &self._latencyChasing {
+            let droppedDuration = timestamp - chasing.fromTimestamp;
+            let canResume = if self._hasVideo {
+                tag.tagType == 9 && !tag.data.is_empty() && (tag.data[0] >> 4) == 1
+            } else {
+                droppedDuration as f64 >= chasing.latency - self._config.liveLatencyTarget
+            };
+            if !canResume {
+                return true;
+            }
+
+            let event = LatencyChase {
+                latency: chasing.latency,
+                droppedDuration: droppedDuration as f64,
+                keyframeDts: timestamp as f64,
+            };
+            self._latencyChasing = None;
+            self._timestampBase -= droppedDuration as i32;
+            // playback skips what was dropped, the latency with it
+            self._liveEdgeOffset = Some(edge + droppedDuration as f64);
+            self._liveLatency = (offset - edge - droppedDuration as f64).max(0.0);
+            Log::v(
+                self.TAG,
+                &format!(
+                    "Dropped {} ms up to the keyframe at {}, {} ms behind the live edge",
+                    droppedDuration, timestamp, self._liveLatency
+                ),
+            );
+            if let Some(onLatencyChase) = &self._onLatencyChase {
+                let _ = onLatencyChase.call1(&JsValue::null(), &JsValue::from(event));
+            }
+            return false;
+        }
+
+        if self._liveLatency > self._config.liveLatencyDropThreshold {
+            Log::w(
+                self.TAG,
+                &format!(
+                    "{} ms behind the live edge, dropping to the next keyframe",
+                    self._liveLatency
+                ),
+            );
+            self._latencyChasing = Some(LatencyChasing {
+                fromTimestamp: timestamp,
+                latency: self._liveLatency,
+            });
+            return true;
+        }
+        false
+    }
+
+    // With Config.enableProducerReferenceTime: the wall clock at dts, for the prft boxes
+    fn _emitProducerReference(&self, dts: i64, wallClock: f64) {
+        if !self._config.enableProducerReferenceTime {
+            return;
+        }
+        if let Some(onProducerReference) = self._onProducerReference {
+            onProducerReference(ProducerReference { dts, wallClock });
+        }
+    }
+
     fn _parseScriptData(
         &self,
         arrayBuffer: &[u8],
         dataOffset: usize,
         dataSize: usize,
+        tagTimestamp: i64,
     ) -> Result<(), JsValue> {
         let scriptData = parseScriptData(arrayBuffer, dataOffset, dataSize);
 
+        // onFI is taken as the prft wall clock instead when it is the source
+        let onFIIsProducerReference = self._config.enableProducerReferenceTime
+            && self._config.producerReferenceTimeSource == ProducerReferenceTimeSource::ScriptTag;
+
+        // anything but onMetaData is timed metadata, passed on with its tag timestamp
+        for name in scriptData.keys() {
+            if name == "onMetaData" || (name == "onFI" && onFIIsProducerReference) {
+                continue;
+            }
+            if let Some(onTimedMetadata) = self._onTimedMetadata {
+                onTimedMetadata(TimedMetadata {
+                    name: name.clone(),
+                    dts: self._timestampBase as i64 + tagTimestamp,
+                    data: Vec::from(&arrayBuffer[dataOffset..dataOffset + dataSize]),
+                });
+            }
+        }
+
+        if let Some(onFI) = scriptData.get("onFI") {
+            if onFIIsProducerReference {
+                if let Some(wallClock) = producer_reference::parseOnFI(onFI) {
+                    self._emitProducerReference(
+                        self._timestampBase as i64 + tagTimestamp,
+                        wallClock,
+                    );
+                }
+            }
+        }
+
         scriptData.get("onMetaData").and_then(|onMetaData| {
             if self._metadata.is_some() {
                 Log::w(self.TAG, "Found another onMetaData tag!");
@@ -649,6 +1045,23 @@ impl FLVDemuxer {
                 }
             }
 
+            if let Some(AMFValue::Object(colorInfo)) = onMetaData.get("colorInfo") {
+                // Enhanced RTMP
+                self._onColorInfo(colorInfo);
+            }
+
+            let rotation = match onMetaData.get("rotation").or(onMetaData.get("rotate")) {
+                Some(&AMFValue::Number(rotation)) => normalizeRotation(rotation),
+                _ => 0,
+            };
+            let mirror = match onMetaData.get("mirror") {
+                Some(&AMFValue::Boolean(mirror)) => mirror,
+                _ => false,
+            };
+            if rotation != self._rotation || mirror != self._mirror {
+                self._onOrientationChanged(rotation, mirror);
+            }
+
             if let Some(AMFValue::Object(keyframes)) = onMetaData.remove("keyframes") {
                 // keyframes
                 self._mediaInfo.hasKeyframesIndex = true;
@@ -668,26 +1081,11 @@ impl FLVDemuxer {
         });
     }
 
-    fn _parseKeyframesIndex(&self, keyframes: HashMap<String, AMFValue>) -> Result<usize, JsValue> {
-        let times = vec![];
-        let filepositions = vec![];
-
-        if let (Some(AMFValue::Array(times)), Some(AMFValue::Array(filepositions))) =
-            (keyframes.get("times"), keyframes.get("filepositions"))
-        {
-            for i in 1..times.len() {
-                let time = self._timestampBase + unsafe { Math::floor(times[i] * 1000) };
-                times.push(time);
-                filepositions.push(filepositions[i]);
-            }
-        } else {
-            panic!("flv amf keyframes format error");
+    fn _parseKeyframesIndex(&self, keyframes: HashMap<String, AMFValue>) -> KeyframesIndex {
+        match parseKeyframesIndex(&keyframes, self._timestampBase) {
+            Some(keyframesIndex) => keyframesIndex,
+            None => panic!("flv amf keyframes format error"),
         }
-
-        return KeyframesIndex {
-            times,
-            filepositions,
-        };
     }
 
     fn _parseAudioData(
@@ -695,7 +1093,7 @@ impl FLVDemuxer {
         arrayBuffer: &[u8],
         dataOffset: usize,
         dataSize: usize,
-        tagTimestamp: u32,
+        tagTimestamp: i64,
     ) {
         if dataSize <= 1 {
             Log::w(
@@ -765,8 +1163,12 @@ impl FLVDemuxer {
                 duration: self._duration,
                 audioSampleRate: soundRate,
                 channelCount: if soundType == 0 { 1 } else { 2 },
+                refSampleDuration: 0.0,
                 data: vec![],
                 codec: "".into(),
+                originalCodec: "".into(),
+                config: vec![],
+                originalConfig: vec![],
             }));
         }
 
@@ -804,8 +1206,9 @@ impl FLVDemuxer {
                 meta.codec = misc.codec;
                 meta.originalCodec = misc.originalCodec;
                 meta.config = misc.config;
-                // The decode result of an aac sample is 1024 PCM samples
-                meta.refSampleDuration = 1024 / meta.audioSampleRate * meta.timescale;
+                meta.originalConfig = misc.originalConfig;
+                // The decode result of an aac sample is 1024 PCM samples, 960 / 512 / 480 for some
+                meta.refSampleDuration = misc.frameLength / meta.audioSampleRate * meta.timescale;
 
                 if (self._isInitialMetadataDispatched()) {
                     // Non-initial metadata, force dispatch (or flush) parsed frames to remuxer
@@ -837,7 +1240,7 @@ impl FLVDemuxer {
                 }
             } else if let AudioData::Data(data) = aacData {
                 // AAC raw frame data
-                let dts = self._timestampBase + tagTimestamp;
+                let dts = self._timestampBase as i64 + tagTimestamp;
                 let aacSample = Sample {
                     unit: data,
                     length: data.len(),
@@ -870,8 +1273,9 @@ impl FLVDemuxer {
                 meta.channelCount = misc.channelCount;
                 meta.codec = misc.codec;
                 meta.originalCodec = misc.originalCodec;
-                // The decode result of an mp3 sample is 1152 PCM samples
-                meta.refSampleDuration = 1152 / meta.audioSampleRate * meta.timescale;
+                meta.originalConfig = misc.originalConfig;
+                // 1152 PCM samples per mp3 frame, 576 for MPEG-2 / 2.5 Layer III
+                meta.refSampleDuration = misc.frameLength / meta.audioSampleRate * meta.timescale;
                 Log::v(self.TAG, "Parsed MPEG Audio Frame Header");
 
                 self._audioInitialMetadataDispatched = true;
@@ -899,7 +1303,7 @@ impl FLVDemuxer {
             if (data == undefined) {
                 return;
             }
-            let dts = self._timestampBase + tagTimestamp;
+            let dts = self._timestampBase as i64 + tagTimestamp;
             //  let mp3Sample = {unit: data, length: data.byteLength, dts: dts, pts: dts};
             track.samples.push(mp3Sample);
             track.length += data.length;
@@ -927,7 +1331,7 @@ impl FLVDemuxer {
                 arrayBuffer,
                 dataOffset + 1,
                 dataSize - 1,
-            ))
+            )?)
         } else {
             AudioData::Data(Vec::from(
                 &arrayBuffer[dataOffset + 1..dataOffset + dataSize],
@@ -942,116 +1346,41 @@ impl FLVDemuxer {
         arrayBuffer: &[u8],
         dataOffset: usize,
         dataSize: usize,
-    ) -> AudioConfig {
-        let array = Cursor::new(arrayBuffer);
-        let config;
-
-        /* Audio Object Type:
-           0: Null
-           1: AAC Main
-           2: AAC LC
-           3: AAC SSR (Scalable Sample Rate)
-           4: AAC LTP (Long Term Prediction)
-           5: HE-AAC / SBR (Spectral Band Replication)
-           6: AAC Scalable
-        */
-
-        let audioObjectType = 0;
-        let originalAudioObjectType = 0;
-        let audioExtensionObjectType;
-        let samplingIndex = 0;
-        let extensionSamplingIndex;
-
-        // 5 bits
-        audioObjectType = originalAudioObjectType = array[0] >> 3;
-        // 4 bits
-        samplingIndex = ((array[0] & 0x07) << 1) | (array[1] >> 7);
-        if samplingIndex < 0 || samplingIndex >= self._mpegSamplingRates.length {
-            self._onError.unwrap().call(
-                &JsValue::null(),
-                &JsValue::from(demux_errors::FORMAT_ERROR),
-                &JsValue::from("Flv: AAC invalid sampling frequency index!"),
-            );
-            return;
-        }
-
-        let samplingFrequence = mpegSamplingRates[samplingIndex];
-
-        // 4 bits
-        let channelConfig = (array[1] & 0x78) >> 3;
-        if channelConfig < 0 || channelConfig >= 8 {
-            self._onError(
-                demux_errors::FORMAT_ERROR,
-                "Flv: AAC invalid channel configuration",
-            );
-            return;
-        }
+    ) -> Option<AudioConfig> {
+        let originalConfig = &arrayBuffer[dataOffset..dataOffset + dataSize];
+        let asc = match aac_config::parseAudioSpecificConfig(originalConfig) {
+            Ok(asc) => asc,
+            Err(message) => {
+                self._onError(demux_errors::FORMAT_ERROR, &format!("Flv: {}", message));
+                return None;
+            }
+        };
 
-        if audioObjectType == 5 {
-            // HE-AAC?
-            // 4 bits
-            extensionSamplingIndex = ((array[1] & 0x07) << 1) | (array[2] >> 7);
-            // 5 bits
-            audioExtensionObjectType = (array[2] & 0x7C) >> 2;
+        // workarounds for various browsers, on a config the parser left untouched
+        let mut config = Vec::from(originalConfig);
+        let mut codec = asc.codec();
+        if self._config.rewriteAudioSpecificConfig {
+            let userAgent = web_sys::window()
+                .and_then(|window| window.navigator().user_agent().ok())
+                .unwrap_or_default();
+            if let Some((rewritten, audioObjectType)) =
+                aac_config::rewriteForBrowser(&asc, &userAgent)
+            {
+                config = rewritten;
+                codec = format!("mp4a.40.{}", audioObjectType);
+            }
         }
 
-        // workarounds for various browsers
-        let userAgent: String = window()?.navigator()?.user_agent()?.to_lowercase();
-
-        if (userAgent.find("firefox").is_some()) {
-            // firefox: use SBR (HE-AAC) if freq less than 24kHz
-            if (samplingIndex >= 6) {
-                audioObjectType = 5;
-                config = [0; 4];
-                extensionSamplingIndex = samplingIndex - 3;
-            } else {
-                // use LC-AAC
-                audioObjectType = 2;
-                config = [0; 2];
-                extensionSamplingIndex = samplingIndex;
-            }
-        } else if (userAgent.find("android").is_some()) {
-            // android: always use LC-AAC
-            audioObjectType = 2;
-            config = [0; 2];
-            extensionSamplingIndex = samplingIndex;
-        } else {
-            // for other browsers, e.g. chrome...
-            // Always use HE-AAC to make it easier to switch aac codec profile
-            audioObjectType = 5;
-            extensionSamplingIndex = samplingIndex;
-            config = [0; 4];
-
-            if samplingIndex >= 6 {
-                extensionSamplingIndex = samplingIndex - 3;
-            } else if channelConfig == 1 {
-                // Mono channel
-                audioObjectType = 2;
-                config = [0; 4];
-                extensionSamplingIndex = samplingIndex;
-            }
-        }
-
-        config[0] = audioObjectType << 3;
-        config[0] |= (samplingIndex & 0x0F) >> 1;
-        config[1] = (samplingIndex & 0x0F) << 7;
-        config[1] |= (channelConfig & 0x0F) << 3;
-        if audioObjectType == 5 {
-            config[1] |= (extensionSamplingIndex & 0x0F) >> 1;
-            config[2] = (extensionSamplingIndex & 0x01) << 7;
-            // extended audio object type: force to 2 (LC-AAC)
-            config[2] |= 2 << 2;
-            config[3] = 0;
-        }
-
-        return AudioConfig {
-            config: config,
+        return Some(AudioConfig {
+            config,
+            originalConfig: Vec::from(originalConfig),
             bitRate: 0,
-            samplingRate: samplingFrequence,
-            channelCount: channelConfig,
-            codec: "mp4a.40." + audioObjectType,
-            originalCodec: "mp4a.40." + originalAudioObjectType,
-        };
+            samplingRate: asc.samplingFrequency,
+            channelCount: asc.channelCount(),
+            frameLength: asc.frameLength,
+            codec,
+            originalCodec: asc.codec(),
+        });
     }
 
     fn _parseMP3AudioData(
@@ -1084,6 +1413,7 @@ impl FLVDemuxer {
 
             let sample_rate = 0;
             let bit_rate = 0;
+            let frame_length = 1152;
             let object_type = 34; // Layer-3, listed in MPEG-4 Audio Object Types
 
             let codec = "mp3";
@@ -1101,6 +1431,9 @@ impl FLVDemuxer {
                 // Layer 3
                 1 => {
                     object_type = 34;
+                    if ver != 3 {
+                        frame_length = 576;
+                    }
                     if bitrate_index < self._mpegAudioL3BitRateTable.length {
                         bit_rate = self._mpegAudioL3BitRateTable[bitrate_index];
                     }
@@ -1115,6 +1448,7 @@ impl FLVDemuxer {
                 // Layer 1
                 3 => {
                     object_type = 32;
+                    frame_length = 384;
                     if bitrate_index < self._mpegAudioL1BitRateTable.length {
                         bit_rate = self._mpegAudioL1BitRateTable[bitrate_index];
                     }
@@ -1122,11 +1456,15 @@ impl FLVDemuxer {
             }
 
             result = AudioConfig {
+                config: vec![],
+                // silent frames are generated from it
+                originalConfig: Vec::from(&array[0..4]),
                 bitRate: bit_rate,
                 samplingRate: sample_rate,
                 channelCount: channel_count,
-                codec: codec,
-                originalCodec: codec,
+                frameLength: frame_length,
+                codec: codec.into(),
+                originalCodec: codec.into(),
             };
         } else {
             result = array;
@@ -1136,11 +1474,11 @@ impl FLVDemuxer {
     }
 
     fn _parseVideoData(
-        &self,
+        &mut self,
         arrayBuffer: &[u8],
         dataOffset: usize,
         dataSize: usize,
-        tagTimestamp: u32,
+        tagTimestamp: i64,
         tagPosition: u32,
     ) {
         if dataSize <= 1 {
@@ -1159,28 +1497,184 @@ impl FLVDemuxer {
 
         let spec = arrayBuffer[0];
 
+        if spec & 0x80 != 0 {
+            // Enhanced RTMP: IsExHeader + FrameType(3) + PacketType(4), then a FourCC
+            self._parseEnhancedVideoPacket(
+                arrayBuffer,
+                dataOffset,
+                dataSize,
+                tagTimestamp,
+                tagPosition,
+            );
+            return;
+        }
+
         let frameType = (spec & 0xF0) >> 4;
         let codecId = spec & 0x0F;
 
-        if codecId != 7 {
+        let codec = match codecId {
+            7 => NaluCodec::H264,
+            // the CodecID 12 extension most CDNs use for HEVC in legacy FLV
+            12 => NaluCodec::H265,
+            _ => {
+                self._onError(
+                    demux_errors::CODEC_UNSUPPORTED,
+                    "Flv: Unsupported codec in video frame: ${codecId}",
+                );
+                return;
+            }
+        };
+
+        self._parseAVCVideoPacket(arrayBuffer, tagTimestamp, tagPosition, frameType, codec);
+    }
+
+    // colorInfo of onMetaData or of a PacketTypeMetadata video packet
+    fn _onColorInfo(&mut self, colorInfo: &HashMap<String, AMFValue>) {
+        let (color, hdrInfo) = hdr::parseColorInfo(colorInfo);
+        self._metadataColor = color;
+        if !hdrInfo.is_empty() && hdrInfo != self._hdrInfo {
+            self._onHdrInfoChanged(hdrInfo);
+        }
+        if let Some(color) = color {
+            let hdrFormat = hdr::hdrFormat(color.transfer_characteristics);
+            self._mediaInfo.hdr = hdrFormat.map(String::from);
+        }
+    }
+
+    // ExVideoTagHeader of the Enhanced RTMP spec, only the hvc1 FourCC is supported.
+    // Neither ModEx nor the video metadata carries an orientation: rotation and mirroring
+    // only come from onMetaData
+    fn _parseEnhancedVideoPacket(
+        &mut self,
+        arrayBuffer: &[u8],
+        dataOffset: usize,
+        dataSize: usize,
+        tagTimestamp: i64,
+        tagPosition: u32,
+    ) {
+        if dataSize < 5 {
+            Log::w(
+                self.TAG,
+                "Flv: Invalid enhanced video packet, missing FourCC",
+            );
+            return;
+        }
+
+        let spec = arrayBuffer[0];
+        let frameType = ((spec >> 4) & 0x07) as u32;
+        let mut packetType = spec & 0x0F;
+        let mut offset = 1;
+
+        // PacketTypeModEx (v2): modifier extensions ahead of the actual packet type. The only one
+        // defined, TimestampOffsetNano, is below the millisecond and skipped
+        while packetType == 7 {
+            let mut modExDataSize = match arrayBuffer.get(offset) {
+                Some(&size) => size as usize + 1,
+                None => break,
+            };
+            offset += 1;
+            if modExDataSize == 256 && offset + 2 <= arrayBuffer.len() {
+                modExDataSize =
+                    ((arrayBuffer[offset] as usize) << 8 | arrayBuffer[offset + 1] as usize) + 1;
+                offset += 2;
+            }
+            offset += modExDataSize;
+            // modExType(4) + packetType(4)
+            packetType = match arrayBuffer.get(offset) {
+                Some(&byte) => byte & 0x0F,
+                None => break,
+            };
+            offset += 1;
+        }
+
+        if frameType == 5 && packetType != 4 {
+            // video info / command frame, no coded data
+            return;
+        }
+        if arrayBuffer.len() < offset + 4 {
+            Log::w(
+                self.TAG,
+                "Flv: Invalid enhanced video packet, missing FourCC",
+            );
+            return;
+        }
+        if &arrayBuffer[offset..offset + 4] != b"hvc1" {
             self._onError(
                 demux_errors::CODEC_UNSUPPORTED,
-                "Flv: Unsupported codec in video frame: ${codecId}",
+                "Flv: Unsupported FourCC in enhanced video frame",
             );
             return;
         }
-
-        self._parseAVCVideoPacket(arrayBuffer, tagTimestamp, tagPosition, frameType);
+        offset += 4;
+
+        let payload = &arrayBuffer[offset..];
+        let dataOffset = dataOffset + offset;
+        let dataSize = dataSize - offset;
+        match packetType {
+            // PacketTypeSequenceStart: HEVCDecoderConfigurationRecord
+            0 => self._parseHEVCDecoderConfigurationRecord(payload, dataOffset, dataSize),
+            // PacketTypeCodedFrames: SI24 composition time + NALUs
+            1 => {
+                if payload.len() < 3 {
+                    Log::w(
+                        self.TAG,
+                        "Flv: Invalid HEVC packet, missing CompositionTime",
+                    );
+                    return;
+                }
+                let cts = ((payload[0] as i32) << 24
+                    | (payload[1] as i32) << 16
+                    | (payload[2] as i32) << 8)
+                    >> 8;
+                self._parseAVCVideoData(
+                    &payload[3..],
+                    dataOffset + 3,
+                    dataSize - 3,
+                    tagTimestamp,
+                    tagPosition,
+                    frameType,
+                    cts,
+                    NaluCodec::H265,
+                );
+            }
+            // PacketTypeCodedFramesX: NALUs, composition time 0
+            3 => self._parseAVCVideoData(
+                payload,
+                dataOffset,
+                dataSize,
+                tagTimestamp,
+                tagPosition,
+                frameType,
+                0,
+                NaluCodec::H265,
+            ),
+            // PacketTypeMetadata: AMF "colorInfo" object
+            4 => {
+                let scriptData = parseScriptData(payload, 0, payload.len());
+                if let Some(colorInfo) = scriptData.get("colorInfo") {
+                    self._onColorInfo(colorInfo);
+                }
+            }
+            // PacketTypeSequenceEnd, PacketTypeMPEG2TSSequenceStart
+            2 | 5 => {}
+            _ => {
+                self._onError(
+                    demux_errors::FORMAT_ERROR,
+                    "Flv: Invalid video packet type ${packetType}",
+                );
+            }
+        }
     }
 
     fn _parseAVCVideoPacket(
-        &self,
+        &mut self,
         arrayBuffer: &[u8],
         dataOffset: usize,
         dataSize: usize,
-        tagTimestamp: u32,
+        tagTimestamp: i64,
         tagPosition: u32,
         frameType: u32,
+        codec: NaluCodec,
     ) {
         if dataSize < 4 {
             Log::w(
@@ -1197,11 +1691,18 @@ impl FLVDemuxer {
         // let cts = (cts_unsigned << 8) >> 8; // convert to 24-bit signed int
 
         if packetType == 0 {
-            // AVCDecoderConfigurationRecord
-            self._parseAVCDecoderConfigurationRecord(arrayBuffer);
+            // AVCDecoderConfigurationRecord / HEVCDecoderConfigurationRecord
+            match codec {
+                NaluCodec::H264 => self._parseAVCDecoderConfigurationRecord(arrayBuffer),
+                NaluCodec::H265 => self._parseHEVCDecoderConfigurationRecord(
+                    &arrayBuffer[4..],
+                    dataOffset + 4,
+                    dataSize - 4,
+                ),
+            }
         } else if packetType == 1 {
             // One or more Nalus
-            self._parseAVCVideoData(arrayBuffer[4..], tagTimestamp, tagPosition, frameType, cts);
+            self._parseAVCVideoData(arrayBuffer[4..], tagTimestamp, tagPosition, frameType, cts, codec);
         } else if packetType == 2 {
             // empty, AVC end of sequence
         } else {
@@ -1373,6 +1874,11 @@ impl FLVDemuxer {
             meta.bitDepth = config.bit_depth;
             meta.chromaFormat = config.chroma_format;
             meta.sarRatio = config.sar_ratio;
+            // the SPS VUI wins over onMetaData colorInfo
+            meta.color = config.color.or(self._metadataColor);
+            meta.hdr = self._hdrInfo;
+            meta.rotation = self._rotation;
+            meta.mirror = self._mirror;
             meta.frameRate = config.frame_rate;
 
             if config.frame_rate.fixed == false
@@ -1384,7 +1890,7 @@ impl FLVDemuxer {
 
             let fps_den = meta.frameRate.fps_den;
             let fps_num = meta.frameRate.fps_num;
-            meta.refSampleDuration = meta.timescale * (fps_den / fps_num);
+            meta.refSampleDuration = meta.timescale as f64 * fps_den as f64 / fps_num as f64;
 
             let codecArray = sps[1..4];
             let codecString = format!(
@@ -1403,6 +1909,14 @@ impl FLVDemuxer {
             mi.chromaFormat = config.chroma_format_string;
             mi.sarNum = meta.sarRatio.width;
             mi.sarDen = meta.sarRatio.height;
+            self._setMediaInfoOrientation(meta.presentWidth, meta.presentHeight);
+            if let Some(color) = meta.color {
+                mi.hdr = hdr::hdrFormat(color.transfer_characteristics).map(String::from);
+                mi.colourPrimaries = Some(color.colour_primaries as usize);
+                mi.transferCharacteristics = Some(color.transfer_characteristics as usize);
+                mi.matrixCoefficients = Some(color.matrix_coefficients as usize);
+                mi.fullRange = Some(color.full_range);
+            }
             mi.videoCodec = codecString;
 
             if mi.hasAudio {
@@ -1500,15 +2014,240 @@ impl FLVDemuxer {
         self._onTrackMetadata("video", meta);
     }
 
+    // The hvcC record goes to the remuxer as is (hvc1 sample entry, WebCodecs description, Annex-B
+    // parameter sets), the picture size comes from its first SPS
+    fn _parseHEVCDecoderConfigurationRecord(
+        &mut self,
+        arrayBuffer: &[u8],
+        dataOffset: usize,
+        dataSize: usize,
+    ) {
+        let parameterSets = match annexb::parseHEVCDecoderConfigurationRecord(arrayBuffer) {
+            Some(parameterSets) => parameterSets,
+            None => {
+                self._onError(
+                    demux_errors::FORMAT_ERROR,
+                    "Flv: Invalid HEVCDecoderConfigurationRecord",
+                );
+                return;
+            }
+        };
+        let sps = parameterSets
+            .nalus
+            .iter()
+            .find(|nalu| NaluCodec::H265.naluType(nalu[0]) == 33);
+        let config = match sps.map(|sps| sps_parser::parseHEVCSPS(sps)) {
+            Some(Ok(config)) => config,
+            _ => {
+                self._onError(
+                    demux_errors::FORMAT_ERROR,
+                    "Flv: Invalid HEVCDecoderConfigurationRecord: No SPS",
+                );
+                return;
+            }
+        };
+
+        if let Some(meta) = &self._videoMetadata {
+            if meta.avcc == arrayBuffer {
+                // the same record again, nothing to reset
+                return;
+            }
+        } else if self._hasVideo == false && self._hasVideoFlagOverrided == false {
+            self._hasVideo = true;
+            self._mediaInfo.hasVideo = Some(true);
+        }
+
+        self._naluLengthSize = parameterSets.naluLengthSize as i32;
+
+        // no VUI timing parsed for HEVC, the onMetaData framerate is used
+        let fps = self._referenceFrameRate.fps;
+        let refSampleDuration = self._timescale as f64 * self._referenceFrameRate.fps_den as f64
+            / self._referenceFrameRate.fps_num as f64;
+        let codec = hevcCodecString(arrayBuffer);
+        let meta = VideoTrackMetaData {
+            id: self._videoTrack.id,
+            timescale: self._timescale,
+            duration: self._duration,
+            codecWidth: config.codec_size.width,
+            codecHeight: config.codec_size.height,
+            presentWidth: config.present_size.width,
+            presentHeight: config.present_size.height,
+            profile: (arrayBuffer[1] & 0x1F) as u32,
+            level: arrayBuffer[12] as u32,
+            bitDepth: config.bit_depth,
+            chromaFormat: config.chroma_format,
+            sarRatio: Size {
+                width: 1,
+                height: 1,
+            },
+            color: self._metadataColor,
+            hdr: self._hdrInfo,
+            rotation: self._rotation,
+            mirror: self._mirror,
+            frameRate: fps as u32,
+            refSampleDuration,
+            codec: codec.clone(),
+            avcc: Vec::from(arrayBuffer),
+        };
+
+        let mi = &mut self._mediaInfo;
+        mi.width = Some(meta.codecWidth as usize);
+        mi.height = Some(meta.codecHeight as usize);
+        mi.fps = Some(fps as usize);
+        mi.chromaFormat = Some(config.chroma_format as usize);
+        mi.videoCodec = Some(codec);
+        self._setMediaInfoOrientation(meta.presentWidth, meta.presentHeight);
+        if self._mediaInfo.isComplete() {
+            self._onMediaInfo
+                .unwrap()
+                .call1(&JsValue::null(), &JsValue::from(self._mediaInfo));
+        }
+
+        Log::v(
+            self.TAG,
+            &format!(
+                "Parsed HEVCDecoderConfigurationRecord done, {}x{}, {}",
+                meta.codecWidth, meta.codecHeight, meta.codec
+            ),
+        );
+
+        if self._isInitialMetadataDispatched() {
+            // flush parsed frames
+            if self._dispatch && (self._audioTrack.length > 0 || self._videoTrack.length > 0) {
+                self._onDataAvailable(self._audioTrack, self._videoTrack);
+            }
+        } else {
+            self._videoInitialMetadataDispatched = true;
+        }
+        // notify new metadata
+        self._dispatch = false;
+
+        self._videoMetadata = Some(meta.clone());
+        self._onTrackMetadata("video", meta);
+    }
+
+    // Feeds captions, onSEI and the HDR metadata, then returns the SEI NALU (length prefixed) to
+    // keep in the sample: unchanged, rebuilt without the stripped messages, or None when all are
+    // stripped
+    fn _processSEI(
+        &mut self,
+        data: Vec<u8>,
+        lengthSize: usize,
+        codec: NaluCodec,
+        dts: i64,
+        pts: f64,
+    ) -> Option<Vec<u8>> {
+        let headerSize = codec.headerSize();
+        let messages = sei_parser::parseSEI(&data[lengthSize..], headerSize);
+        let mut kept: Vec<SEIMessage> = Vec::with_capacity(messages.len());
+        let mut stripped = false;
+
+        let mut hdrInfo = self._hdrInfo;
+
+        for message in messages {
+            if message.payloadType == hdr::MASTERING_DISPLAY_COLOUR_VOLUME {
+                let masteringDisplay = hdr::parseMasteringDisplayColourVolume(&message.payload);
+                hdrInfo.masteringDisplay = masteringDisplay.or(hdrInfo.masteringDisplay);
+            }
+            if message.payloadType == hdr::CONTENT_LIGHT_LEVEL_INFO {
+                let contentLightLevel = hdr::parseContentLightLevelInfo(&message.payload);
+                hdrInfo.contentLightLevel = contentLightLevel.or(hdrInfo.contentLightLevel);
+            }
+
+            if message.payloadType == USER_DATA_REGISTERED_ITU_T_T35 {
+                if let Some(captionDecoder) = &mut self._captionDecoder {
+                    captionDecoder.push(pts, &message.payload);
+                }
+            }
+
+            if message.payloadType == USER_DATA_UNREGISTERED {
+                let userData = sei_parser::parseUserDataUnregistered(&message.payload);
+                if let Some((uuid, payload)) = userData {
+                    if self._config.producerReferenceTimeSource == ProducerReferenceTimeSource::Sei
+                        && uuid == self._config.producerReferenceTimeSeiUuid
+                    {
+                        if let Some(wallClock) = producer_reference::parseSeiWallClock(payload) {
+                            self._emitProducerReference(dts, wallClock);
+                        }
+                    }
+                    if self._config.seiUuids.contains(&uuid) {
+                        if let Some(onSEI) = &self._onSEI {
+                            let seiData = SEIData {
+                                uuid: uuid.to_vec(),
+                                payload: payload.to_vec(),
+                                pts,
+                            };
+                            let _ = onSEI.call1(&JsValue::null(), &JsValue::from(seiData));
+                        }
+                        if self._config.stripRegisteredSEI {
+                            stripped = true;
+                            continue;
+                        }
+                    }
+                }
+            }
+            kept.push(message);
+        }
+
+        if hdrInfo != self._hdrInfo {
+            self._onHdrInfoChanged(hdrInfo);
+        }
+
+        if !stripped {
+            return Some(data);
+        }
+        let nalHeader = &data[lengthSize..lengthSize + headerSize];
+        sei_parser::buildSEIUnit(nalHeader, lengthSize, &kept)
+    }
+
+    // mdcv / clli are in the sample entry: the video track metadata is sent again for a new init
+    // segment when they show up after the AVCDecoderConfigurationRecord
+    fn _onHdrInfoChanged(&mut self, hdrInfo: HdrInfo) {
+        self._hdrInfo = hdrInfo;
+        if let Some(meta) = &mut self._videoMetadata {
+            meta.hdr = hdrInfo;
+            self._onTrackMetadata("video", meta);
+        }
+    }
+
+    // the tkhd matrix is in the init segment: a new one is needed when the orientation changes
+    // after the AVCDecoderConfigurationRecord
+    fn _onOrientationChanged(&mut self, rotation: u32, mirror: bool) {
+        self._rotation = rotation;
+        self._mirror = mirror;
+        if let Some(meta) = &mut self._videoMetadata {
+            meta.rotation = rotation;
+            meta.mirror = mirror;
+            let (presentWidth, presentHeight) = (meta.presentWidth, meta.presentHeight);
+            self._setMediaInfoOrientation(presentWidth, presentHeight);
+            self._onTrackMetadata("video", meta);
+        }
+    }
+
+    // the displayed size is the present size turned by the rotation
+    fn _setMediaInfoOrientation(&mut self, presentWidth: u32, presentHeight: u32) {
+        let mi = &mut self._mediaInfo;
+        mi.rotation = Some(self._rotation as usize);
+        mi.mirror = Some(self._mirror);
+        if self._rotation == 90 || self._rotation == 270 {
+            mi.presentWidth = Some(presentHeight as usize);
+            mi.presentHeight = Some(presentWidth as usize);
+        } else {
+            mi.presentWidth = Some(presentWidth as usize);
+            mi.presentHeight = Some(presentHeight as usize);
+        }
+    }
+
     fn _parseAVCVideoData(
-        &self,
+        &mut self,
         arrayBuffer: &[u8],
         dataOffset: usize,
         dataSize: usize,
-        tagTimestamp: u32,
+        tagTimestamp: i64,
         tagPosition: u32,
         frameType: u32,
         cts: i32,
+        codec: NaluCodec,
     ) {
         let v = Cursor::new(arrayBuffer);
 
@@ -1517,7 +2256,7 @@ impl FLVDemuxer {
 
         let offset = 0;
         let lengthSize = self._naluLengthSize;
-        let dts = self._timestampBase + tagTimestamp;
+        let dts = self._timestampBase as i64 + tagTimestamp;
         let keyframe = frameType == 1; // from FLV Frame Type constants
 
         while offset < arrayBuffer.len() {
@@ -1538,15 +2277,28 @@ impl FLVDemuxer {
                 return;
             }
 
-            let unitType = v.read_u8() & 0x1F;
+            let unitType = codec.naluType(v.read_u8()?);
 
-            if unitType == 5 {
-                // IDR
+            if codec.isRandomAccess(unitType) {
+                // IDR, or IRAP for HEVC
                 keyframe = true;
             }
 
-            let data: Vec<u8> = Vec::with_capacity(lengthSize + naluSize);
+            let mut data: Vec<u8> = Vec::with_capacity(lengthSize + naluSize);
             v.read_exact(&data)?;
+
+            if codec.isSEI(unitType) {
+                let pts = (dts + cts) as f64;
+                data = match self._processSEI(data, lengthSize, codec, dts, pts) {
+                    Some(data) => data,
+                    // nothing left in it
+                    None => {
+                        offset += lengthSize + naluSize;
+                        continue;
+                    }
+                };
+            }
+
             let unit = Unit {
                 _type: unitType,
                 data: data,
@@ -1574,5 +2326,189 @@ impl FLVDemuxer {
             track.samples.push(avcSample);
             track.length += length;
         }
+
+        self._captionTime = dts as f64;
+        let cues = match &mut self._captionDecoder {
+            Some(captionDecoder) => captionDecoder.decode(dts as f64),
+            None => vec![],
+        };
+        if !cues.is_empty() {
+            self._dispatchCaptionCues(cues);
+        }
+    }
+}
+
+// codecs parameter of ISO/IEC 14496-15 E.3 from the hvcC general profile fields, e.g. hvc1.1.6.L93.B0
+fn hevcCodecString(hvcc: &[u8]) -> String {
+    let profileSpace = ["", "A", "B", "C"][(hvcc[1] >> 6) as usize];
+    let profileIdc = hvcc[1] & 0x1F;
+    let tier = if hvcc[1] & 0x20 != 0 { "H" } else { "L" };
+    // general_profile_compatibility_flags, bit order reversed
+    let compatibility = u32::from_be_bytes([hvcc[2], hvcc[3], hvcc[4], hvcc[5]]).reverse_bits();
+    let mut codec = format!(
+        "hvc1.{}{}.{:X}.{}{}",
+        profileSpace, profileIdc, compatibility, tier, hvcc[12]
+    );
+
+    // general constraint indicator flags, trailing zero bytes omitted
+    let constraints = &hvcc[6..12];
+    let used = constraints
+        .iter()
+        .rposition(|&byte| byte != 0)
+        .map_or(0, |last| last + 1);
+    for byte in &constraints[..used] {
+        codec.push_str(&format!(".{:X}", byte));
+    }
+    codec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    thread_local! {
+        static AUDIO_DTS: RefCell<Vec<i64>> = RefCell::new(vec![]);
+    }
+
+    fn collectAudioDts(_: &MP4Remuxer, audioTrack: &mut AudioTrack, _: &mut VideoTrack) {
+        AUDIO_DTS.with(|dts| {
+            dts.borrow_mut()
+                .extend(audioTrack.samples.iter().map(|sample| sample.dts))
+        });
+    }
+
+    // Audio only FLV file of MP3 tags: header + PreviousTagSize0 + tags
+    fn mp3Stream(timestamps: &[u32]) -> Vec<u8> {
+        let mut stream = vec![0x46, 0x4C, 0x56, 0x01, 0x04, 0x00, 0x00, 0x00, 0x09];
+        stream.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        for &timestamp in timestamps {
+            // MP3 44.1kHz stereo + MPEG-1 Layer III 128kbps frame header
+            let data = [0x2F, 0xFF, 0xFB, 0x90, 0x64, 0x00, 0x00];
+            stream.extend_from_slice(&[0x08, 0x00, 0x00, data.len() as u8]);
+            stream.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+            stream.push((timestamp >> 24) as u8); // extended timestamp
+            stream.extend_from_slice(&[0x00, 0x00, 0x00]); // streamId
+            stream.extend_from_slice(&data);
+            stream.extend_from_slice(&(11 + data.len() as u32).to_be_bytes());
+        }
+        stream
+    }
+
+    #[wasm_bindgen_test]
+    fn dts_extends_across_the_timestamp_rollover() {
+        let stream = mp3Stream(&[0xFFFF_FFD8, 0xFFFF_FFEC, 0x0000_000A, 0x0000_001E]);
+        let probeData = FLVDemuxer::probe(&stream).unwrap();
+        let mut demuxer = FLVDemuxer::new(probeData, Config::default());
+        demuxer._onError = Some(Function::new_no_args(""));
+        demuxer._onMediaInfo = Some(Function::new_no_args(""));
+        demuxer._onTrackMetadata = Some(|_, _| {});
+        demuxer._onDataAvailable = Some(collectAudioDts);
+
+        assert_eq!(demuxer.parseChunks(&stream, 0), stream.len());
+        AUDIO_DTS.with(|dts| {
+            assert_eq!(
+                *dts.borrow(),
+                vec![0xFFFF_FFD8, 0xFFFF_FFEC, 0x1_0000_000A, 0x1_0000_001E]
+            )
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn hevc_codec_string_from_the_record() {
+        // Main, compatibility flag 1 and 2, Main tier level 3.1, progressive + frame only
+        let hvcc = [
+            0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5D,
+        ];
+        assert_eq!(hevcCodecString(&hvcc), "hvc1.1.6.L93.90");
+
+        // Main 10 in profile space 1, High tier level 5.1
+        let hvcc = [
+            0x01, 0x62, 0x40, 0x00, 0x00, 0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x99,
+        ];
+        assert_eq!(hevcCodecString(&hvcc), "hvc1.A2.2.H153.B0");
+    }
+
+    fn liveDemuxer(hasVideo: bool) -> FLVDemuxer {
+        let flags = if hasVideo { 0x05 } else { 0x04 };
+        let header = [0x46, 0x4C, 0x56, 0x01, flags, 0x00, 0x00, 0x00, 0x09];
+        let mut config = Config::default();
+        config.isLive = true;
+        config.enableLatencyChasing = true;
+        FLVDemuxer::new(FLVDemuxer::probe(&header).unwrap(), config)
+    }
+
+    fn tag(tagType: u8, data: &[u8]) -> flv_tags::FlvTag<'_> {
+        flv_tags::FlvTag {
+            tagType,
+            timestamp: 0,
+            position: 0,
+            data,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn latency_chase_drops_up_to_the_next_keyframe() {
+        let keyframe = [0x17, 0x01];
+        let interframe = [0x27, 0x01];
+        let avcSequenceHeader = [0x17, 0x00];
+        let aac = [0xAF, 0x01];
+        let mut demuxer = liveDemuxer(true);
+
+        // the earliest arrival relative to the DTS sets the live edge
+        assert!(!demuxer._chaseLiveLatency(&tag(9, &keyframe), 0, 1000.0));
+        assert!(!demuxer._chaseLiveLatency(&tag(8, &aac), 100, 1100.0));
+        assert!(!demuxer._chaseLiveLatency(&tag(9, &interframe), 200, 6200.0));
+        assert_eq!(demuxer.liveLatency(), 5000.0);
+
+        // past the threshold, the tag crossing it included
+        assert!(demuxer._chaseLiveLatency(&tag(9, &interframe), 300, 6400.0));
+        assert_eq!(demuxer.liveLatency(), 5100.0);
+        assert!(demuxer._chaseLiveLatency(&tag(8, &aac), 400, 6500.0));
+        assert!(!demuxer._chaseLiveLatency(&tag(9, &avcSequenceHeader), 500, 6600.0));
+        assert!(demuxer._chaseLiveLatency(&tag(9, &interframe), 500, 6600.0));
+
+        // resumed at the keyframe, the dropped span taken out of the timeline and the latency
+        assert!(!demuxer._chaseLiveLatency(&tag(9, &keyframe), 1100, 7200.0));
+        assert_eq!(demuxer._timestampBase, -800);
+        assert_eq!(demuxer.liveLatency(), 4300.0);
+        assert!(!demuxer._chaseLiveLatency(&tag(9, &interframe), 1200, 7300.0));
+        assert!(demuxer._latencyChasing.is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn latency_chase_on_audio_only_streams() {
+        let aac = [0xAF, 0x01];
+        let mut demuxer = liveDemuxer(false);
+
+        assert!(!demuxer._chaseLiveLatency(&tag(8, &aac), 0, 1000.0));
+        assert!(demuxer._chaseLiveLatency(&tag(8, &aac), 1000, 8000.0));
+        assert_eq!(demuxer.liveLatency(), 6000.0);
+
+        // no keyframe to wait for, resumed once latency - liveLatencyTarget is dropped
+        assert!(demuxer._chaseLiveLatency(&tag(8, &aac), 5400, 12400.0));
+        assert!(!demuxer._chaseLiveLatency(&tag(8, &aac), 5500, 12500.0));
+        assert_eq!(demuxer._timestampBase, -4500);
+        assert_eq!(demuxer.liveLatency(), 1500.0);
+    }
+
+    #[wasm_bindgen_test]
+    fn recommended_playback_rate_ramps_up_to_the_threshold() {
+        let mut demuxer = liveDemuxer(true);
+        let rateAt = |demuxer: &mut FLVDemuxer, latency: f64| {
+            demuxer._liveLatency = latency;
+            demuxer.recommendedPlaybackRate()
+        };
+
+        assert_eq!(rateAt(&mut demuxer, 0.0), 1.0);
+        assert_eq!(rateAt(&mut demuxer, 1500.0), 1.0);
+        assert!((rateAt(&mut demuxer, 3250.0) - 1.1).abs() < 1e-9);
+        assert!((rateAt(&mut demuxer, 5000.0) - 1.2).abs() < 1e-9);
+        assert!((rateAt(&mut demuxer, 9000.0) - 1.2).abs() < 1e-9);
+
+        // no ramp when the threshold is not above the target
+        demuxer._config.liveLatencyDropThreshold = 1000.0;
+        assert_eq!(rateAt(&mut demuxer, 3000.0), 1.0);
     }
 }
@@ -0,0 +1,158 @@
+// Synchronous FLV tag walker, for tools working on whole files instead of a live chunk stream
+
+pub struct FlvTag<'a> {
+    pub tagType: u8,
+    pub timestamp: u32,
+    // byte offset of the tag header, as referenced by keyframes.filepositions
+    pub position: usize,
+    pub data: &'a [u8],
+}
+
+pub fn readTag(input: &[u8], offset: usize) -> Option<FlvTag<'_>> {
+    if offset + 11 > input.len() {
+        return None;
+    }
+    let dataSize = ((input[offset + 1] as usize) << 16)
+        | ((input[offset + 2] as usize) << 8)
+        | input[offset + 3] as usize;
+    // 3 bytes timestamp + 1 byte extended timestamp
+    let timestamp = ((input[offset + 7] as u32) << 24)
+        | ((input[offset + 4] as u32) << 16)
+        | ((input[offset + 5] as u32) << 8)
+        | input[offset + 6] as u32;
+
+    let dataOffset = offset + 11;
+    if dataOffset + dataSize > input.len() {
+        return None;
+    }
+
+    Some(FlvTag {
+        tagType: input[offset],
+        timestamp,
+        position: offset,
+        data: &input[dataOffset..dataOffset + dataSize],
+    })
+}
+
+// tagBody + dataSize + prevTagSize
+pub fn nextTagOffset(tag: &FlvTag) -> usize {
+    tag.position + 11 + tag.data.len() + 4
+}
+
+pub fn isSequenceHeader(tag: &FlvTag) -> bool {
+    match tag.tagType {
+        // AVCDecoderConfigurationRecord
+        9 => tag.data.len() > 1 && (tag.data[0] & 0x0F) == 7 && tag.data[1] == 0,
+        // AudioSpecificConfig
+        8 => tag.data.len() > 1 && (tag.data[0] >> 4) == 10 && tag.data[1] == 0,
+        _ => false,
+    }
+}
+
+const TIMESTAMP_RANGE: i64 = 1 << 32;
+
+// FLV timestamps are 32-bit milliseconds, wrapping around every ~49.7 days on a 24/7 channel:
+// extends them to 64 bits, counting the wraparounds
+pub struct TimestampExtender {
+    _lastTimestamp: Option<i64>,
+}
+
+impl TimestampExtender {
+    pub fn new() -> TimestampExtender {
+        TimestampExtender {
+            _lastTimestamp: None,
+        }
+    }
+
+    // The 64-bit timestamp with these lower 32 bits closest to the previous tag, so that tags
+    // slightly out of order around a wraparound stay on their side of it
+    pub fn extend(&mut self, timestamp: u32) -> i64 {
+        let extended = match self._lastTimestamp {
+            None => timestamp as i64,
+            Some(last) => {
+                let extended = (last & !(TIMESTAMP_RANGE - 1)) | timestamp as i64;
+                if extended - last > TIMESTAMP_RANGE / 2 {
+                    extended - TIMESTAMP_RANGE
+                } else if last - extended > TIMESTAMP_RANGE / 2 {
+                    extended + TIMESTAMP_RANGE
+                } else {
+                    extended
+                }
+            }
+        };
+        self._lastTimestamp = Some(extended);
+        extended
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // An FLV tag with an empty body: header + previous tag size
+    fn flvTag(tagType: u8, timestamp: u32) -> Vec<u8> {
+        let mut tag = vec![tagType, 0x00, 0x00, 0x00];
+        tag.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        tag.push((timestamp >> 24) as u8); // extended timestamp
+        tag.extend_from_slice(&[0x00, 0x00, 0x00]); // streamId
+        tag.extend_from_slice(&11u32.to_be_bytes());
+        tag
+    }
+
+    #[wasm_bindgen_test]
+    fn rollover_mid_stream() {
+        // interleaved audio / video around the 32-bit wraparound, audio slightly late
+        let tags = [
+            (9, 0xFFFF_FF9Cu32),
+            (8, 0xFFFF_FF92),
+            (9, 0xFFFF_FFBA),
+            (8, 0xFFFF_FFB0),
+            (9, 0xFFFF_FFD8),
+            (8, 0xFFFF_FFCE),
+            (9, 0xFFFF_FFF6),
+            (8, 0xFFFF_FFEC),
+            (9, 0x0000_0014),
+            (8, 0x0000_000A),
+            (9, 0x0000_0032),
+            (8, 0x0000_0028),
+        ];
+        let stream: Vec<u8> = tags
+            .iter()
+            .flat_map(|&(tagType, timestamp)| flvTag(tagType, timestamp))
+            .collect();
+
+        let mut extender = TimestampExtender::new();
+        let mut offset = 0;
+        let mut extended = vec![];
+        while let Some(tag) = readTag(&stream, offset) {
+            extended.push(extender.extend(tag.timestamp));
+            offset = nextTagOffset(&tag);
+        }
+
+        let expected: Vec<i64> = tags
+            .iter()
+            .map(|&(_, timestamp)| {
+                if timestamp < 0x8000_0000 {
+                    timestamp as i64 + (1 << 32)
+                } else {
+                    timestamp as i64
+                }
+            })
+            .collect();
+        assert_eq!(extended, expected);
+    }
+
+    #[wasm_bindgen_test]
+    fn late_tag_after_rollover() {
+        let mut extender = TimestampExtender::new();
+        assert_eq!(extender.extend(0xFFFF_FFF0), 0xFFFF_FFF0);
+        assert_eq!(extender.extend(0x0000_0010), 0x1_0000_0010);
+        // a tag from before the wraparound stays before it
+        assert_eq!(extender.extend(0xFFFF_FFFA), 0xFFFF_FFFA);
+        assert_eq!(extender.extend(0x0000_0030), 0x1_0000_0030);
+        // and the second wraparound is counted as well
+        assert_eq!(extender.extend(0x8000_0030), 0x1_8000_0030);
+        assert_eq!(extender.extend(0x0000_0010), 0x2_0000_0010);
+    }
+}
@@ -0,0 +1,250 @@
+// HDR static metadata: mastering display colour volume (SMPTE ST 2086) and content light level,
+// from H.264 / HEVC SEI or the Enhanced RTMP colorInfo object
+
+use super::amf_parser::AMFValue;
+use super::sps_parser::ColorInfo;
+use std::collections::HashMap;
+
+pub const MASTERING_DISPLAY_COLOUR_VOLUME: u32 = 137;
+pub const CONTENT_LIGHT_LEVEL_INFO: u32 = 144;
+
+// transfer_characteristics of ITU-T H.273
+const TRANSFER_PQ: u32 = 16;
+const TRANSFER_HLG: u32 = 18;
+
+// Same units as the SEI message and the mdcv box: chromaticity in 0.00002,
+// luminance in 0.0001 cd/m2, primaries in G, B, R order
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MasteringDisplay {
+    pub displayPrimaries: [(u16, u16); 3],
+    pub whitePoint: (u16, u16),
+    pub maxLuminance: u32,
+    pub minLuminance: u32,
+}
+
+// in cd/m2
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ContentLightLevel {
+    pub maxCLL: u16,
+    pub maxFALL: u16,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct HdrInfo {
+    pub masteringDisplay: Option<MasteringDisplay>,
+    pub contentLightLevel: Option<ContentLightLevel>,
+}
+
+impl HdrInfo {
+    pub fn is_empty(&self) -> bool {
+        self.masteringDisplay.is_none() && self.contentLightLevel.is_none()
+    }
+}
+
+// "PQ" (HDR10) or "HLG" from the transfer characteristics, None for SDR
+pub fn hdrFormat(transferCharacteristics: u32) -> Option<&'static str> {
+    match transferCharacteristics {
+        TRANSFER_PQ => Some("PQ"),
+        TRANSFER_HLG => Some("HLG"),
+        _ => None,
+    }
+}
+
+fn u16At(data: &[u8], offset: usize) -> u16 {
+    (data[offset] as u16) << 8 | data[offset + 1] as u16
+}
+
+fn u32At(data: &[u8], offset: usize) -> u32 {
+    (u16At(data, offset) as u32) << 16 | u16At(data, offset + 2) as u32
+}
+
+// mastering_display_colour_volume SEI payload, H.264 D.1.29 / HEVC D.2.28
+pub fn parseMasteringDisplayColourVolume(payload: &[u8]) -> Option<MasteringDisplay> {
+    if payload.len() < 24 {
+        return None;
+    }
+    let mut displayPrimaries = [(0, 0); 3];
+    for (c, primary) in displayPrimaries.iter_mut().enumerate() {
+        *primary = (u16At(payload, c * 4), u16At(payload, c * 4 + 2));
+    }
+    Some(MasteringDisplay {
+        displayPrimaries,
+        whitePoint: (u16At(payload, 12), u16At(payload, 14)),
+        maxLuminance: u32At(payload, 16),
+        minLuminance: u32At(payload, 20),
+    })
+}
+
+// content_light_level_info SEI payload, H.264 D.1.31 / HEVC D.2.35
+pub fn parseContentLightLevelInfo(payload: &[u8]) -> Option<ContentLightLevel> {
+    if payload.len() < 4 {
+        return None;
+    }
+    Some(ContentLightLevel {
+        maxCLL: u16At(payload, 0),
+        maxFALL: u16At(payload, 2),
+    })
+}
+
+fn number(object: &HashMap<String, AMFValue>, key: &str) -> Option<f64> {
+    match object.get(key) {
+        Some(&AMFValue::Number(value)) => Some(value),
+        _ => None,
+    }
+}
+
+// Enhanced RTMP colorInfo: colorConfig, hdrCll and hdrMdcv, the latter in chromaticity
+// coordinates and cd/m2
+pub fn parseColorInfo(colorInfo: &HashMap<String, AMFValue>) -> (Option<ColorInfo>, HdrInfo) {
+    let mut color = None;
+    let mut hdr = HdrInfo::default();
+
+    if let Some(AMFValue::Object(colorConfig)) = colorInfo.get("colorConfig") {
+        let codePoint = |key: &str| number(colorConfig, key).map(|value| value as u32);
+        if let Some(transferCharacteristics) = codePoint("transferCharacteristics") {
+            color = Some(ColorInfo {
+                colour_primaries: codePoint("colorPrimaries").unwrap_or(2),
+                transfer_characteristics: transferCharacteristics,
+                matrix_coefficients: codePoint("matrixCoefficients").unwrap_or(2),
+                full_range: false,
+            });
+        }
+    }
+
+    if let Some(AMFValue::Object(hdrCll)) = colorInfo.get("hdrCll") {
+        let maxCLL = number(hdrCll, "maxCLL");
+        let maxFALL = number(hdrCll, "maxFall");
+        if let (Some(maxCLL), Some(maxFALL)) = (maxCLL, maxFALL) {
+            hdr.contentLightLevel = Some(ContentLightLevel {
+                maxCLL: maxCLL as u16,
+                maxFALL: maxFALL as u16,
+            });
+        }
+    }
+
+    if let Some(AMFValue::Object(hdrMdcv)) = colorInfo.get("hdrMdcv") {
+        // rounded: 0.046 * 50000.0 is 2299.99...
+        let chromaticity =
+            |key: &str| number(hdrMdcv, key).map(|value| (value * 50000.0).round() as u16);
+        let luminance =
+            |key: &str| number(hdrMdcv, key).map(|value| (value * 10000.0).round() as u32);
+        let primary = |x: &str, y: &str| Some((chromaticity(x)?, chromaticity(y)?));
+        let masteringDisplay = || {
+            Some(MasteringDisplay {
+                displayPrimaries: [
+                    primary("greenX", "greenY")?,
+                    primary("blueX", "blueY")?,
+                    primary("redX", "redY")?,
+                ],
+                whitePoint: primary("whitePointX", "whitePointY")?,
+                maxLuminance: luminance("maxLuminance")?,
+                minLuminance: luminance("minLuminance")?,
+            })
+        };
+        hdr.masteringDisplay = masteringDisplay();
+    }
+
+    (color, hdr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // BT.2020 primaries, D65, 1000 / 0.005 cd/m2
+    const MDCV_PAYLOAD: [u8; 24] = [
+        0x21, 0x34, 0x9B, 0xAA, 0x19, 0x96, 0x08, 0xFC, 0x8A, 0x48, 0x39, 0x08, 0x3D, 0x13, 0x40,
+        0x42, 0x00, 0x98, 0x96, 0x80, 0x00, 0x00, 0x00, 0x32,
+    ];
+
+    fn bt2020Display() -> MasteringDisplay {
+        MasteringDisplay {
+            displayPrimaries: [(8500, 39850), (6550, 2300), (35400, 14600)],
+            whitePoint: (15635, 16450),
+            maxLuminance: 10000000,
+            minLuminance: 50,
+        }
+    }
+
+    fn object(entries: &[(&str, f64)]) -> AMFValue {
+        let entries = entries
+            .iter()
+            .map(|&(key, value)| (key.to_string(), AMFValue::Number(value)));
+        AMFValue::Object(entries.collect())
+    }
+
+    #[wasm_bindgen_test]
+    fn mastering_display_colour_volume_sei() {
+        assert_eq!(
+            parseMasteringDisplayColourVolume(&MDCV_PAYLOAD),
+            Some(bt2020Display())
+        );
+        assert_eq!(parseMasteringDisplayColourVolume(&MDCV_PAYLOAD[..23]), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn content_light_level_info_sei() {
+        assert_eq!(
+            parseContentLightLevelInfo(&[0x03, 0xE8, 0x01, 0x90]),
+            Some(ContentLightLevel {
+                maxCLL: 1000,
+                maxFALL: 400,
+            })
+        );
+        assert_eq!(parseContentLightLevelInfo(&[0x03, 0xE8, 0x01]), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn color_info_object() {
+        let mut colorInfo = HashMap::new();
+        colorInfo.insert(
+            "colorConfig".to_string(),
+            object(&[
+                ("bitsPerComponent", 10.0),
+                ("transferCharacteristics", 16.0),
+                ("matrixCoefficients", 9.0),
+                ("colorPrimaries", 9.0),
+            ]),
+        );
+        colorInfo.insert(
+            "hdrCll".to_string(),
+            object(&[("maxFall", 400.0), ("maxCLL", 1000.0)]),
+        );
+        colorInfo.insert(
+            "hdrMdcv".to_string(),
+            object(&[
+                ("redX", 0.708),
+                ("redY", 0.292),
+                ("greenX", 0.170),
+                ("greenY", 0.797),
+                ("blueX", 0.131),
+                ("blueY", 0.046),
+                ("whitePointX", 0.3127),
+                ("whitePointY", 0.3290),
+                ("maxLuminance", 1000.0),
+                ("minLuminance", 0.005),
+            ]),
+        );
+
+        let (color, hdr) = parseColorInfo(&colorInfo);
+        let color = color.unwrap();
+        assert_eq!(color.colour_primaries, 9);
+        assert_eq!(color.transfer_characteristics, 16);
+        assert_eq!(color.matrix_coefficients, 9);
+        assert_eq!(hdrFormat(color.transfer_characteristics), Some("PQ"));
+        assert_eq!(hdr.masteringDisplay, Some(bt2020Display()));
+        assert_eq!(
+            hdr.contentLightLevel,
+            Some(ContentLightLevel {
+                maxCLL: 1000,
+                maxFALL: 400,
+            })
+        );
+
+        // an incomplete hdrMdcv is ignored
+        colorInfo.insert("hdrMdcv".to_string(), object(&[("redX", 0.708)]));
+        let (_, hdr) = parseColorInfo(&colorInfo);
+        assert_eq!(hdr.masteringDisplay, None);
+    }
+}
@@ -1,5 +1,14 @@
+pub mod aac_config;
 pub mod amf_parser;
+pub mod captions;
+mod cea608;
+mod cea708;
 pub mod demux_errors;
 mod exp_golomb;
 pub mod flv_demuxer;
+pub mod flv_tags;
+pub mod hdr;
+pub mod producer_reference;
+pub mod scte35;
+pub mod sei_parser;
 pub mod sps_parser;
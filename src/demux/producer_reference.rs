@@ -0,0 +1,132 @@
+// Wall clock references for prft boxes: the UTC time a media timestamp was produced, taken from
+// the tag arrival, an encoder SEI or an onFI script tag
+
+use super::amf_parser::AMFValue;
+use std::collections::HashMap;
+
+// seconds from the NTP epoch (1900-01-01) to the Unix one
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+pub struct ProducerReference {
+    // in milliseconds, on the same timeline as the audio / video dts
+    pub dts: i64,
+    // milliseconds since the Unix epoch, UTC
+    pub wallClock: f64,
+}
+
+// 64-bit NTP timestamp: seconds since 1900 in the upper 32 bits, fraction in the lower ones
+pub fn ntpTimestamp(wallClock: f64) -> u64 {
+    let wallClock = wallClock.max(0.0);
+    let seconds = (wallClock / 1000.0).floor();
+    let fraction = ((wallClock / 1000.0 - seconds) * 4294967296.0) as u64;
+    ((seconds as u64 + NTP_UNIX_OFFSET) << 32) | fraction.min(0xFFFF_FFFF)
+}
+
+pub fn wallClockFromNtp(ntpTimestamp: u64) -> Option<f64> {
+    let seconds = ntpTimestamp >> 32;
+    if seconds < NTP_UNIX_OFFSET {
+        return None;
+    }
+    let fraction = (ntpTimestamp & 0xFFFF_FFFF) as f64 / 4294967296.0;
+    Some(((seconds - NTP_UNIX_OFFSET) as f64 + fraction) * 1000.0)
+}
+
+// user_data_unregistered SEI payload, after the UUID: a 64-bit NTP timestamp
+pub fn parseSeiWallClock(payload: &[u8]) -> Option<f64> {
+    if payload.len() < 8 {
+        return None;
+    }
+    let mut ntpTimestamp = [0u8; 8];
+    ntpTimestamp.copy_from_slice(&payload[..8]);
+    wallClockFromNtp(u64::from_be_bytes(ntpTimestamp))
+}
+
+// onFI (frame information) as written by Flash Media Live Encoder and others: sd, the system
+// date "dd-mm-yyyy", and st, the system time "hh:mm:ss.sss", taken as UTC
+pub fn parseOnFI(onFI: &HashMap<String, AMFValue>) -> Option<f64> {
+    let (date, time) = match (onFI.get("sd"), onFI.get("st")) {
+        (Some(AMFValue::String(date)), Some(AMFValue::String(time))) => (date, time),
+        _ => return None,
+    };
+
+    let date: Vec<i64> = date
+        .split('-')
+        .map(|field| field.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    let time: Vec<f64> = time
+        .split(':')
+        .map(|field| field.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    if date.len() != 3 || time.len() != 3 {
+        return None;
+    }
+
+    let (day, month, year) = (date[0], date[1], date[2]);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let seconds = time[0] * 3600.0 + time[1] * 60.0 + time[2];
+    Some(daysFromCivil(year, month, day) as f64 * 86_400_000.0 + seconds * 1000.0)
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date
+fn daysFromCivil(year: i64, month: i64, day: i64) -> i64 {
+    // years starting in March, so that the leap day ends them
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yearOfEra = year - era * 400;
+    let dayOfYear = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let dayOfEra = yearOfEra * 365 + yearOfEra / 4 - yearOfEra / 100 + dayOfYear;
+    era * 146_097 + dayOfEra - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn onFI(date: &str, time: &str) -> HashMap<String, AMFValue> {
+        let mut onFI = HashMap::new();
+        onFI.insert("sd".to_string(), AMFValue::String(date.into()));
+        onFI.insert("st".to_string(), AMFValue::String(time.into()));
+        onFI
+    }
+
+    #[wasm_bindgen_test]
+    fn ntp_timestamp_round_trip() {
+        // 2026-10-18T12:34:56.500Z
+        let ntpTimestamp = ntpTimestamp(1792326896500.0);
+        assert_eq!(ntpTimestamp >> 32, 1792326896 + 2208988800);
+        assert_eq!(ntpTimestamp & 0xFFFF_FFFF, 0x8000_0000);
+        assert_eq!(wallClockFromNtp(ntpTimestamp), Some(1792326896500.0));
+        // before the Unix epoch
+        assert_eq!(wallClockFromNtp(0), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn sei_wall_clock() {
+        let payload = ntpTimestamp(1792326896500.0).to_be_bytes();
+        assert_eq!(parseSeiWallClock(&payload), Some(1792326896500.0));
+        assert_eq!(parseSeiWallClock(&payload[..7]), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn onfi_date_and_time() {
+        assert_eq!(
+            parseOnFI(&onFI("18-10-2026", "12:34:56.500")),
+            Some(1792326896500.0)
+        );
+        assert_eq!(
+            parseOnFI(&onFI("29-02-2000", "00:00:00")),
+            Some(951782400000.0)
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn onfi_malformed() {
+        assert_eq!(parseOnFI(&onFI("18-13-2026", "12:34:56")), None);
+        assert_eq!(parseOnFI(&onFI("18-10-2026", "12:34")), None);
+        assert_eq!(parseOnFI(&onFI("2026/10/18", "12:34:56")), None);
+        assert_eq!(parseOnFI(&HashMap::new()), None);
+    }
+}
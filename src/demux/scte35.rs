@@ -0,0 +1,588 @@
+// SCTE-35 splice_info_section parsing, for ad markers carried by onCuePoint script tags
+
+use super::amf_parser::{parseScriptData, AMFValue};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+pub const SCTE35_SCHEME_ID_URI: &'static str = "urn:scte:scte35:2013:bin";
+
+const SPLICE_INSERT: u8 = 0x05;
+const TIME_SIGNAL: u8 = 0x06;
+const SEGMENTATION_DESCRIPTOR: u8 = 0x02;
+// identifier of the SCTE-35 splice descriptors
+const CUEI: &[u8; 4] = b"CUEI";
+
+// segmentation_type_id values of SCTE-35 table 22 opening / closing a segment. The others
+// (content identification, breakaway, resumption, runover, blackout override) are neither
+const SEGMENT_STARTS: [u8; 20] = [
+    0x10, 0x17, 0x19, 0x20, 0x22, 0x24, 0x26, 0x30, 0x32, 0x34, 0x36, 0x38, 0x3A, 0x3C, 0x3E, 0x40,
+    0x42, 0x44, 0x46, 0x50,
+];
+const SEGMENT_ENDS: [u8; 19] = [
+    0x11, 0x12, 0x21, 0x23, 0x25, 0x27, 0x31, 0x33, 0x35, 0x37, 0x39, 0x3B, 0x3D, 0x3F, 0x41, 0x43,
+    0x45, 0x47, 0x51,
+];
+
+// splice times further than this from their cue tag are not on the FLV clock
+const MAX_SPLICE_DISTANCE_MS: i64 = 60_000;
+
+pub struct SpliceInsert {
+    pub spliceEventId: u32,
+    pub cancel: bool,
+    pub outOfNetwork: bool,
+    pub spliceImmediate: bool,
+    // 90kHz, pts_adjustment not applied
+    pub ptsTime: Option<u64>,
+    // 90kHz
+    pub breakDuration: Option<u64>,
+    pub autoReturn: bool,
+    pub uniqueProgramId: u16,
+    pub availNum: u8,
+    pub availsExpected: u8,
+}
+
+pub enum SpliceCommand {
+    Null,
+    Insert(SpliceInsert),
+    TimeSignal { ptsTime: Option<u64> },
+    // bandwidth_reservation, private_command, ...: kept raw in SpliceInfo
+    Other(u8),
+}
+
+pub struct SegmentationDescriptor {
+    pub segmentationEventId: u32,
+    pub cancel: bool,
+    // 90kHz
+    pub duration: Option<u64>,
+    pub upidType: u8,
+    pub upid: Vec<u8>,
+    pub segmentationTypeId: u8,
+    pub segmentNum: u8,
+    pub segmentsExpected: u8,
+}
+
+impl SegmentationDescriptor {
+    pub fn isStart(&self) -> bool {
+        SEGMENT_STARTS.contains(&self.segmentationTypeId)
+    }
+
+    pub fn isEnd(&self) -> bool {
+        SEGMENT_ENDS.contains(&self.segmentationTypeId)
+    }
+}
+
+pub struct SpliceInfo {
+    pub ptsAdjustment: u64,
+    pub tier: u16,
+    pub command: SpliceCommand,
+    pub segmentationDescriptors: Vec<SegmentationDescriptor>,
+    // the whole splice_info_section, as carried by emsg
+    pub raw: Vec<u8>,
+}
+
+impl SpliceInfo {
+    // in milliseconds, from break_duration or the first segmentation_duration
+    pub fn duration(&self) -> Option<u64> {
+        let duration = match &self.command {
+            SpliceCommand::Insert(insert) => insert.breakDuration,
+            _ => None,
+        };
+        duration
+            .or_else(|| {
+                self.segmentationDescriptors
+                    .iter()
+                    .find_map(|descriptor| descriptor.duration)
+            })
+            .map(|duration| duration / 90)
+    }
+
+    // entering an ad break / program, as opposed to returning from it
+    pub fn isOut(&self) -> bool {
+        match &self.command {
+            SpliceCommand::Insert(insert) => insert.outOfNetwork && !insert.cancel,
+            _ => self
+                .segmentationDescriptors
+                .iter()
+                .any(|descriptor| !descriptor.cancel && descriptor.isStart()),
+        }
+    }
+
+    pub fn isIn(&self) -> bool {
+        match &self.command {
+            SpliceCommand::Insert(insert) => !insert.outOfNetwork && !insert.cancel,
+            _ => self
+                .segmentationDescriptors
+                .iter()
+                .any(|descriptor| !descriptor.cancel && descriptor.isEnd()),
+        }
+    }
+
+    // 90kHz pts_time + pts_adjustment, None for splice_immediate / time_specified_flag 0
+    pub fn spliceTime(&self) -> Option<u64> {
+        let ptsTime = match &self.command {
+            SpliceCommand::Insert(insert) => insert.ptsTime,
+            SpliceCommand::TimeSignal { ptsTime } => *ptsTime,
+            _ => None,
+        };
+        ptsTime.map(|ptsTime| (ptsTime + self.ptsAdjustment) & 0x1_FFFF_FFFF)
+    }
+
+    // The splice time in ms on the FLV timeline, taking the splice pts on the tag timestamp
+    // clock at 90kHz, in the 33-bit period nearest to the cue tag. Immediate splices, and
+    // splice pts on another clock (too far from the tag), happen at the tag dts
+    pub fn spliceDts(&self, tagDts: i64) -> i64 {
+        let spliceTime = match self.spliceTime() {
+            Some(spliceTime) => spliceTime as i64,
+            None => return tagDts,
+        };
+        let period = 1i64 << 33;
+        let wraps = (tagDts * 90 - spliceTime + period / 2).div_euclid(period);
+        let spliceDts = (spliceTime + wraps * period) / 90;
+        if (spliceDts - tagDts).abs() > MAX_SPLICE_DISTANCE_MS {
+            return tagDts;
+        }
+        spliceDts
+    }
+
+    pub fn id(&self) -> u32 {
+        match &self.command {
+            SpliceCommand::Insert(insert) => insert.spliceEventId,
+            _ => self
+                .segmentationDescriptors
+                .first()
+                .map(|descriptor| descriptor.segmentationEventId)
+                .unwrap_or(0),
+        }
+    }
+
+    // EXT-X-DATERANGE tag for HLS playlists, startDate being the ISO 8601 date of the cue
+    pub fn toDateRange(&self, startDate: &str) -> String {
+        let mut tag = format!(
+            "#EXT-X-DATERANGE:ID=\"{}\",START-DATE=\"{}\"",
+            self.id(),
+            startDate
+        );
+        if let Some(duration) = self.duration() {
+            tag += &format!(",PLANNED-DURATION={:.3}", duration as f64 / 1000.0);
+        }
+        let attribute = if self.isOut() {
+            "SCTE35-OUT"
+        } else if self.isIn() {
+            "SCTE35-IN"
+        } else {
+            "SCTE35-CMD"
+        };
+        tag += &format!(",{}=0x{}", attribute, toHex(&self.raw));
+        tag
+    }
+}
+
+// Passed to onScte35Cue, for the EXT-X-DATERANGE tags of the application's playlists
+#[wasm_bindgen]
+pub struct Scte35Cue {
+    // splice time in milliseconds, on the media timeline
+    pub time: f64,
+    info: SpliceInfo,
+}
+
+#[wasm_bindgen]
+impl Scte35Cue {
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> u32 {
+        self.info.id()
+    }
+
+    // planned duration in milliseconds
+    #[wasm_bindgen(getter)]
+    pub fn duration(&self) -> Option<f64> {
+        self.info.duration().map(|duration| duration as f64)
+    }
+
+    // startDate: ISO 8601 date of `time`, as mapped by the playlist's EXT-X-PROGRAM-DATE-TIME
+    #[wasm_bindgen(js_name = "toDateRange")]
+    pub fn toDateRange(&self, startDate: &str) -> String {
+        self.info.toDateRange(startDate)
+    }
+}
+
+impl Scte35Cue {
+    pub fn new(time: f64, info: SpliceInfo) -> Scte35Cue {
+        Scte35Cue { time, info }
+    }
+}
+
+fn toHex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let value = *self.data.get(self.position)?;
+        self.position += 1;
+        Some(value)
+    }
+
+    fn uint(&mut self, bytes: usize) -> Option<u64> {
+        let mut value = 0u64;
+        for _ in 0..bytes {
+            value = (value << 8) | self.u8()? as u64;
+        }
+        Some(value)
+    }
+
+    fn bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position + length)?;
+        self.position += length;
+        Some(bytes)
+    }
+
+    // 7 reserved bits + 33 bits pts, the lowest bit of the first byte being pts bit 32
+    fn pts33(&mut self) -> Option<u64> {
+        Some(self.uint(5)? & 0x1_FFFF_FFFF)
+    }
+
+    // splice_time(): time_specified_flag + optional pts_time
+    fn spliceTime(&mut self) -> Option<Option<u64>> {
+        let flags = *self.data.get(self.position)?;
+        if flags & 0x80 == 0 {
+            self.position += 1;
+            return Some(None);
+        }
+        Some(Some(self.pts33()?))
+    }
+}
+
+// splice_info_section(), SCTE-35 9.6
+pub fn parseSpliceInfoSection(data: &[u8]) -> Option<SpliceInfo> {
+    let mut reader = Reader { data, position: 0 };
+
+    if reader.u8()? != 0xFC {
+        return None;
+    }
+    let sectionLength = (reader.uint(2)? & 0x0FFF) as usize;
+    if data.len() < 3 + sectionLength {
+        return None;
+    }
+    let _protocolVersion = reader.u8()?;
+    // encrypted_packet(1) + encryption_algorithm(6) + pts_adjustment(33)
+    let flags = *data.get(reader.position)?;
+    if flags & 0x80 != 0 {
+        // encrypted commands cannot be read, but can still be passed through
+        return None;
+    }
+    let ptsAdjustment = reader.pts33()?;
+    let _cwIndex = reader.u8()?;
+    // tier(12) + splice_command_length(12)
+    let tierAndLength = reader.uint(3)?;
+    let tier = (tierAndLength >> 12) as u16;
+    let commandLength = (tierAndLength & 0x0FFF) as usize;
+    let commandType = reader.u8()?;
+
+    let commandStart = reader.position;
+    let command = match commandType {
+        0x00 => SpliceCommand::Null,
+        SPLICE_INSERT => SpliceCommand::Insert(parseSpliceInsert(&mut reader)?),
+        TIME_SIGNAL => SpliceCommand::TimeSignal {
+            ptsTime: reader.spliceTime()?,
+        },
+        _ => SpliceCommand::Other(commandType),
+    };
+    // 0xFFF is the legacy "unknown" command length
+    if commandLength != 0x0FFF {
+        reader.position = commandStart + commandLength;
+    }
+
+    let descriptorLoopLength = reader.uint(2)? as usize;
+    let descriptors = reader.bytes(descriptorLoopLength)?;
+    let segmentationDescriptors = parseSegmentationDescriptors(descriptors);
+
+    Some(SpliceInfo {
+        ptsAdjustment,
+        tier,
+        command,
+        segmentationDescriptors,
+        raw: data[..3 + sectionLength].to_vec(),
+    })
+}
+
+fn parseSpliceInsert(reader: &mut Reader) -> Option<SpliceInsert> {
+    let spliceEventId = reader.uint(4)? as u32;
+    let cancel = reader.u8()? & 0x80 != 0;
+
+    let mut insert = SpliceInsert {
+        spliceEventId,
+        cancel,
+        outOfNetwork: false,
+        spliceImmediate: false,
+        ptsTime: None,
+        breakDuration: None,
+        autoReturn: false,
+        uniqueProgramId: 0,
+        availNum: 0,
+        availsExpected: 0,
+    };
+    if cancel {
+        return Some(insert);
+    }
+
+    let flags = reader.u8()?;
+    insert.outOfNetwork = flags & 0x80 != 0;
+    let programSplice = flags & 0x40 != 0;
+    let hasDuration = flags & 0x20 != 0;
+    insert.spliceImmediate = flags & 0x10 != 0;
+
+    if programSplice && !insert.spliceImmediate {
+        insert.ptsTime = reader.spliceTime()?;
+    }
+    if !programSplice {
+        let componentCount = reader.u8()?;
+        for _ in 0..componentCount {
+            let _componentTag = reader.u8()?;
+            if !insert.spliceImmediate {
+                reader.spliceTime()?;
+            }
+        }
+    }
+    if hasDuration {
+        // auto_return(1) + reserved(6) + duration(33)
+        insert.autoReturn = *reader.data.get(reader.position)? & 0x80 != 0;
+        insert.breakDuration = Some(reader.pts33()?);
+    }
+    insert.uniqueProgramId = reader.uint(2)? as u16;
+    insert.availNum = reader.u8()?;
+    insert.availsExpected = reader.u8()?;
+
+    Some(insert)
+}
+
+fn parseSegmentationDescriptors(data: &[u8]) -> Vec<SegmentationDescriptor> {
+    let mut descriptors = vec![];
+    let mut offset = 0;
+
+    while offset + 2 <= data.len() {
+        let tag = data[offset];
+        let length = data[offset + 1] as usize;
+        let end = offset + 2 + length;
+        if end > data.len() {
+            break;
+        }
+        // identifier "CUEI" precedes the descriptor body
+        if tag == SEGMENTATION_DESCRIPTOR && length >= 4 && &data[offset + 2..offset + 6] == CUEI {
+            let mut reader = Reader {
+                data: &data[offset + 6..end],
+                position: 0,
+            };
+            if let Some(descriptor) = parseSegmentationDescriptor(&mut reader) {
+                descriptors.push(descriptor);
+            }
+        }
+        offset = end;
+    }
+
+    descriptors
+}
+
+fn parseSegmentationDescriptor(reader: &mut Reader) -> Option<SegmentationDescriptor> {
+    let segmentationEventId = reader.uint(4)? as u32;
+    let cancel = reader.u8()? & 0x80 != 0;
+
+    let mut descriptor = SegmentationDescriptor {
+        segmentationEventId,
+        cancel,
+        duration: None,
+        upidType: 0,
+        upid: vec![],
+        segmentationTypeId: 0,
+        segmentNum: 0,
+        segmentsExpected: 0,
+    };
+    if cancel {
+        return Some(descriptor);
+    }
+
+    let flags = reader.u8()?;
+    let programSegmentation = flags & 0x80 != 0;
+    let hasDuration = flags & 0x40 != 0;
+    if !programSegmentation {
+        let componentCount = reader.u8()? as usize;
+        // component_tag(8) + reserved(7) + pts_offset(33)
+        reader.bytes(componentCount * 6)?;
+    }
+    if hasDuration {
+        descriptor.duration = Some(reader.uint(5)?);
+    }
+    descriptor.upidType = reader.u8()?;
+    let upidLength = reader.u8()? as usize;
+    descriptor.upid = reader.bytes(upidLength)?.to_vec();
+    descriptor.segmentationTypeId = reader.u8()?;
+    descriptor.segmentNum = reader.u8()?;
+    descriptor.segmentsExpected = reader.u8()?;
+
+    Some(descriptor)
+}
+
+// Looks for a base64 splice_info_section in the parameters of an onCuePoint tag body
+pub fn parseCuePoint(tagBody: &[u8]) -> Option<SpliceInfo> {
+    let scriptData = parseScriptData(tagBody, 0, tagBody.len());
+    let cuePoint = scriptData.get("onCuePoint")?;
+    let parameters = match cuePoint.get("parameters") {
+        Some(AMFValue::Object(parameters)) => parameters,
+        _ => cuePoint,
+    };
+    let payload = findScte35Payload(parameters)?;
+    parseSpliceInfoSection(&decodeBase64(payload)?)
+}
+
+fn findScte35Payload(parameters: &HashMap<String, AMFValue>) -> Option<&str> {
+    parameters.iter().find_map(|(key, value)| match value {
+        AMFValue::String(payload) if key.to_lowercase().contains("scte35") => {
+            Some(payload.as_str())
+        }
+        _ => None,
+    })
+}
+
+fn decodeBase64(input: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in input.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // SCTE-35 14.2: splice_insert, out of network with a 60.293s auto return break
+    const SPLICE_INSERT: &str = "fc302f000000000000fffff014054800008f7feffe7369c02efe0052ccf5\
+                                 00000000000a0008435545490000013562dba30a";
+
+    // SCTE-35 14.1: time_signal, provider placement opportunity start
+    const TIME_SIGNAL_BASE64: &str =
+        "/DA0AAAAAAAA///wBQb+cr0AUAAeAhxDVUVJSAAAjn/PAAGlmbAICAAAAAAsoKGKNAIAmsnRfg==";
+    const TIME_SIGNAL: &str = "fc3034000000000000fffff00506fe72bd0050001e021c435545494800008e\
+                               7fcf0001a599b00808000000002ca0a18a3402009ac9d17e";
+
+    #[wasm_bindgen_test]
+    fn splice_insert_vector() {
+        let spliceInfo = parseSpliceInfoSection(&hex(SPLICE_INSERT)).unwrap();
+        let insert = match &spliceInfo.command {
+            SpliceCommand::Insert(insert) => insert,
+            _ => panic!("not a splice_insert"),
+        };
+        assert_eq!(insert.spliceEventId, 0x4800_008F);
+        assert!(insert.outOfNetwork && insert.autoReturn && !insert.spliceImmediate);
+        assert_eq!(insert.ptsTime, Some(0x0_7369_C02E));
+        assert_eq!(insert.breakDuration, Some(0x52_CCF5));
+
+        assert_eq!(spliceInfo.spliceTime(), Some(0x0_7369_C02E));
+        assert_eq!(spliceInfo.duration(), Some(60293));
+        assert!(spliceInfo.isOut() && !spliceInfo.isIn());
+        assert_eq!(spliceInfo.raw.len(), 3 + 0x2F);
+        assert!(spliceInfo.segmentationDescriptors.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn time_signal_vector_with_segmentation_descriptor() {
+        let spliceInfo = parseSpliceInfoSection(&hex(TIME_SIGNAL)).unwrap();
+        match spliceInfo.command {
+            SpliceCommand::TimeSignal { ptsTime } => assert_eq!(ptsTime, Some(0x0_72BD_0050)),
+            _ => panic!("not a time_signal"),
+        }
+
+        let descriptor = &spliceInfo.segmentationDescriptors[0];
+        assert_eq!(descriptor.segmentationEventId, 0x4800_008E);
+        assert_eq!(descriptor.duration, Some(0x01_A599_B0));
+        assert_eq!(descriptor.upidType, 0x08);
+        assert_eq!(descriptor.upid, hex("000000002ca0a18a"));
+        assert_eq!(descriptor.segmentationTypeId, 0x34);
+        assert_eq!((descriptor.segmentNum, descriptor.segmentsExpected), (2, 0));
+
+        assert_eq!(spliceInfo.duration(), Some(307000));
+        assert!(spliceInfo.isOut() && !spliceInfo.isIn());
+        assert_eq!(spliceInfo.id(), 0x4800_008E);
+    }
+
+    #[wasm_bindgen_test]
+    fn decodes_base64_payloads() {
+        assert_eq!(decodeBase64(TIME_SIGNAL_BASE64).unwrap(), hex(TIME_SIGNAL));
+        // url-safe alphabet, no padding
+        assert_eq!(decodeBase64("_-8").unwrap(), vec![0xFF, 0xEF]);
+        assert!(decodeBase64("/DA*").is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn segmentation_types_follow_table_22() {
+        let mut spliceInfo = parseSpliceInfoSection(&hex(TIME_SIGNAL)).unwrap();
+        // program early termination closes a segment
+        spliceInfo.segmentationDescriptors[0].segmentationTypeId = 0x12;
+        assert!(spliceInfo.isIn() && !spliceInfo.isOut());
+        // content identification neither opens nor closes one
+        spliceInfo.segmentationDescriptors[0].segmentationTypeId = 0x01;
+        assert!(!spliceInfo.isIn() && !spliceInfo.isOut());
+    }
+
+    #[wasm_bindgen_test]
+    fn segmentation_descriptor_needs_the_cuei_identifier() {
+        let mut section = hex(TIME_SIGNAL);
+        // "CUEI" -> "CUEJ"
+        section[26] = b'J';
+        let spliceInfo = parseSpliceInfoSection(&section).unwrap();
+        assert!(spliceInfo.segmentationDescriptors.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn splice_dts_follows_the_splice_time() {
+        let spliceInfo = parseSpliceInfoSection(&hex(TIME_SIGNAL)).unwrap();
+        let spliceMs = 0x0_72BD_0050 / 90;
+        // cue 4s ahead of the splice
+        assert_eq!(spliceInfo.spliceDts(spliceMs - 4000), spliceMs);
+        // next 33-bit period
+        let period = (1i64 << 33) / 90;
+        assert_eq!(
+            spliceInfo.spliceDts(period + spliceMs - 4000),
+            (0x0_72BD_0050 + (1i64 << 33)) / 90
+        );
+        // a splice pts on another clock is taken at the tag
+        assert_eq!(spliceInfo.spliceDts(1000), 1000);
+
+        let mut section = hex(TIME_SIGNAL);
+        // pts_adjustment of 90000 ticks
+        section[6..9].copy_from_slice(&[0x01, 0x5F, 0x90]);
+        let spliceInfo = parseSpliceInfoSection(&section).unwrap();
+        assert_eq!(spliceInfo.spliceTime(), Some(0x0_72BD_0050 + 90000));
+    }
+}
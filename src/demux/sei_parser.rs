@@ -0,0 +1,262 @@
+// H.264 SEI (nal_unit_type 6) and HEVC prefix SEI (nal_unit_type 39) parsing,
+// ITU-T H.264 7.3.2.3 / H.265 7.3.5
+
+pub const USER_DATA_REGISTERED_ITU_T_T35: u32 = 4;
+pub const USER_DATA_UNREGISTERED: u32 = 5;
+
+pub struct SEIMessage {
+    pub payloadType: u32,
+    pub payload: Vec<u8>,
+}
+
+// Removes emulation_prevention_three_byte from a NALU payload
+pub fn ebsp2rbsp(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeroCount = 0;
+
+    for &byte in data {
+        if zeroCount >= 2 && byte == 0x03 {
+            zeroCount = 0;
+            continue;
+        }
+        zeroCount = if byte == 0 { zeroCount + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+
+    rbsp
+}
+
+// Inserts emulation_prevention_three_byte where the payload would mimic a start code
+pub fn rbsp2ebsp(data: &[u8]) -> Vec<u8> {
+    let mut ebsp = Vec::with_capacity(data.len() + data.len() / 64);
+    let mut zeroCount = 0;
+
+    for &byte in data {
+        if zeroCount >= 2 && byte <= 0x03 {
+            ebsp.push(0x03);
+            zeroCount = 0;
+        }
+        zeroCount = if byte == 0 { zeroCount + 1 } else { 0 };
+        ebsp.push(byte);
+    }
+
+    ebsp
+}
+
+// Splits an SEI NALU (without its length prefix, starting at the NAL header of headerSize
+// bytes) into messages
+pub fn parseSEI(nalu: &[u8], headerSize: usize) -> Vec<SEIMessage> {
+    let mut messages = vec![];
+    if nalu.len() <= headerSize {
+        return messages;
+    }
+    let rbsp = ebsp2rbsp(&nalu[headerSize..]);
+    let mut offset = 0;
+
+    // stop at rbsp_trailing_bits
+    while offset < rbsp.len() && rbsp[offset] != 0x80 {
+        let mut payloadType = 0u32;
+        while offset < rbsp.len() && rbsp[offset] == 0xFF {
+            payloadType += 255;
+            offset += 1;
+        }
+        if offset >= rbsp.len() {
+            break;
+        }
+        payloadType += rbsp[offset] as u32;
+        offset += 1;
+
+        let mut payloadSize = 0usize;
+        while offset < rbsp.len() && rbsp[offset] == 0xFF {
+            payloadSize += 255;
+            offset += 1;
+        }
+        if offset >= rbsp.len() {
+            break;
+        }
+        payloadSize += rbsp[offset] as usize;
+        offset += 1;
+
+        if offset + payloadSize > rbsp.len() {
+            break;
+        }
+        messages.push(SEIMessage {
+            payloadType,
+            payload: rbsp[offset..offset + payloadSize].to_vec(),
+        });
+        offset += payloadSize;
+    }
+
+    messages
+}
+
+// Serializes messages back into an SEI NALU, reusing the NAL header of the original one
+pub fn buildSEI(nalHeader: &[u8], messages: &[SEIMessage]) -> Vec<u8> {
+    let mut rbsp = vec![];
+    for message in messages {
+        for value in &[message.payloadType as usize, message.payload.len()] {
+            let mut value = *value;
+            while value >= 255 {
+                rbsp.push(0xFF);
+                value -= 255;
+            }
+            rbsp.push(value as u8);
+        }
+        rbsp.extend_from_slice(&message.payload);
+    }
+    // rbsp_trailing_bits
+    rbsp.push(0x80);
+
+    let mut nalu = nalHeader.to_vec();
+    nalu.extend(rbsp2ebsp(&rbsp));
+    nalu
+}
+
+// buildSEI with the NALU length prefix of an AVC sample, None when no message is left
+pub fn buildSEIUnit(
+    nalHeader: &[u8],
+    lengthSize: usize,
+    messages: &[SEIMessage],
+) -> Option<Vec<u8>> {
+    if messages.is_empty() {
+        return None;
+    }
+    let nalu = buildSEI(nalHeader, messages);
+    let naluSize = nalu.len();
+    let mut unit = Vec::with_capacity(lengthSize + naluSize);
+    for i in (0..lengthSize).rev() {
+        unit.push((naluSize >> (8 * i)) as u8);
+    }
+    unit.extend(nalu);
+    Some(unit)
+}
+
+// uuid_iso_iec_11578 + user_data_payload_byte of a user_data_unregistered payload
+pub fn parseUserDataUnregistered(payload: &[u8]) -> Option<([u8; 16], &[u8])> {
+    if payload.len() < 16 {
+        return None;
+    }
+    let mut uuid = [0u8; 16];
+    uuid.copy_from_slice(&payload[..16]);
+    Some((uuid, &payload[16..]))
+}
+
+// cc_data() triplets of an ATSC A/53 user_data_registered_itu_t_t35 payload:
+// (cc_type, cc_data_1, cc_data_2), invalid entries dropped
+pub fn parseCCData(payload: &[u8]) -> Vec<(u8, u8, u8)> {
+    let mut ccData = vec![];
+
+    // itu_t_t35_country_code: USA, itu_t_t35_provider_code: ATSC, user_identifier: "GA94",
+    // user_data_type_code: cc_data
+    if payload.len() < 10
+        || payload[0] != 0xB5
+        || payload[1] != 0x00
+        || payload[2] != 0x31
+        || &payload[3..7] != b"GA94"
+        || payload[7] != 0x03
+    {
+        return ccData;
+    }
+
+    // process_cc_data_flag(1) + additional_data_flag(1) + cc_count(5)
+    if payload[8] & 0x40 == 0 {
+        return ccData;
+    }
+    let ccCount = (payload[8] & 0x1F) as usize;
+    // em_data
+    let mut offset = 10;
+
+    for _ in 0..ccCount {
+        if offset + 3 > payload.len() {
+            break;
+        }
+        // marker_bits(5) + cc_valid(1) + cc_type(2)
+        let ccValid = payload[offset] & 0x04 != 0;
+        let ccType = payload[offset] & 0x03;
+        if ccValid {
+            ccData.push((ccType, payload[offset + 1], payload[offset + 2]));
+        }
+        offset += 3;
+    }
+
+    ccData
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // an SEI NALU as found in an AVC sample: 4 bytes length prefix + NAL header
+    fn seiUnit(messages: &[SEIMessage]) -> Vec<u8> {
+        buildSEIUnit(&[0x06], 4, messages).unwrap()
+    }
+
+    fn userDataUnregistered(uuid: u8, data: &[u8]) -> SEIMessage {
+        let mut payload = vec![uuid; 16];
+        payload.extend_from_slice(data);
+        SEIMessage {
+            payloadType: USER_DATA_UNREGISTERED,
+            payload,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn strip_one_of_two_messages() {
+        let unit = seiUnit(&[
+            userDataUnregistered(0xAA, &[0x01, 0x02]),
+            // zero runs that need emulation_prevention_three_byte
+            userDataUnregistered(0xBB, &[0x00, 0x00, 0x01, 0x00, 0x00, 0x00]),
+        ]);
+        let messages = parseSEI(&unit[4..], 1);
+        assert_eq!(messages.len(), 2);
+
+        let kept: Vec<SEIMessage> = messages
+            .into_iter()
+            .filter(|message| message.payload[0] != 0xAA)
+            .collect();
+        let stripped = buildSEIUnit(&unit[4..5], 4, &kept).unwrap();
+
+        // NAL header, payloadType, payloadSize, uuid, data with 0x03 inserted, trailing bits
+        let mut expected = vec![0x00, 0x00, 0x00, 1 + 2 + 16 + 8 + 1];
+        expected.extend_from_slice(&[0x06, 0x05, 22]);
+        expected.extend_from_slice(&[0xBB; 16]);
+        expected.extend_from_slice(&[0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00, 0x80]);
+        assert_eq!(stripped, expected);
+
+        let reparsed = parseSEI(&stripped[4..], 1);
+        assert_eq!(reparsed.len(), 1);
+        assert_eq!(reparsed[0].payload, kept[0].payload);
+    }
+
+    #[wasm_bindgen_test]
+    fn strip_every_message_drops_the_nalu() {
+        let unit = seiUnit(&[userDataUnregistered(0xAA, &[0x01])]);
+        let kept: Vec<SEIMessage> = parseSEI(&unit[4..], 1)
+            .into_iter()
+            .filter(|message| message.payloadType != USER_DATA_UNREGISTERED)
+            .collect();
+        assert!(buildSEIUnit(&unit[4..5], 4, &kept).is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn length_prefix_follows_length_size() {
+        let messages = [userDataUnregistered(0xAA, &[0x01; 300])];
+        let unit = buildSEIUnit(&[0x06], 2, &messages).unwrap();
+        let naluSize = (unit[0] as usize) << 8 | unit[1] as usize;
+        assert_eq!(naluSize, unit.len() - 2);
+        assert_eq!(naluSize, buildSEI(&[0x06], &messages).len());
+    }
+
+    #[wasm_bindgen_test]
+    fn hevc_prefix_sei_has_a_two_bytes_header() {
+        // nal_unit_type 39, content_light_level_info
+        let nalu = [0x4E, 0x01, 0x90, 0x04, 0x03, 0xE8, 0x01, 0x90, 0x80];
+        let messages = parseSEI(&nalu, 2);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payloadType, 144);
+        assert_eq!(messages[0].payload, [0x03, 0xE8, 0x01, 0x90]);
+
+        assert_eq!(buildSEI(&nalu[..2], &messages), nalu);
+    }
+}
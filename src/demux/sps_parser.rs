@@ -47,9 +47,19 @@ struct FrameInfo {
     fps_num: u32,
 }
 
-struct Size {
-    width: u32,
-    height: u32,
+#[derive(Copy, Clone)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+// VUI video_signal_type, code points of ITU-T H.273
+#[derive(Copy, Clone)]
+pub struct ColorInfo {
+    pub colour_primaries: u32,
+    pub transfer_characteristics: u32,
+    pub matrix_coefficients: u32,
+    pub full_range: bool,
 }
 
 struct SPSInfo {
@@ -63,6 +73,8 @@ struct SPSInfo {
     frame_rate: FrameInfo,
 
     sar_ratio: Size,
+    // None without video_signal_type in the VUI
+    color: Option<ColorInfo>,
 
     codec_size: Size,
 
@@ -168,6 +180,7 @@ pub fn parseSPS(uint8array: &[u8]) -> Result<SPSInfo, JsValue> {
     let fps_fixed = true;
     let fps_num = 0;
     let fps_den = 0;
+    let mut color = None;
 
     let vui_parameters_present_flag = gb.readBool();
     if (vui_parameters_present_flag) {
@@ -192,11 +205,24 @@ pub fn parseSPS(uint8array: &[u8]) -> Result<SPSInfo, JsValue> {
         }
         if (gb.readBool()) {
             // video_signal_type_present_flag
-            gb.readBits(4); // video_format & video_full_range_flag
+            gb.readBits(3)?; // video_format
+            let full_range = gb.readBool()?; // video_full_range_flag
+                                             // 2: unspecified
+            let mut colour_primaries = 2;
+            let mut transfer_characteristics = 2;
+            let mut matrix_coefficients = 2;
             if (gb.readBool()) {
                 // colour_description_present_flag
-                gb.readBits(24); // colour_primaries & transfer_characteristics & matrix_coefficients
+                colour_primaries = gb.readByte()?;
+                transfer_characteristics = gb.readByte()?;
+                matrix_coefficients = gb.readByte()?;
             }
+            color = Some(ColorInfo {
+                colour_primaries,
+                transfer_characteristics,
+                matrix_coefficients,
+                full_range,
+            });
         }
         if (gb.readBool()) {
             // chroma_loc_info_present_flag
@@ -262,6 +288,7 @@ pub fn parseSPS(uint8array: &[u8]) -> Result<SPSInfo, JsValue> {
             width: sar_width,
             height: sar_height,
         },
+        color,
 
         codec_size: Size {
             width: codec_width,
@@ -275,6 +302,94 @@ pub fn parseSPS(uint8array: &[u8]) -> Result<SPSInfo, JsValue> {
     };
 }
 
+pub struct HEVCSPSInfo {
+    pub bit_depth: u32,
+    pub chroma_format: u32, // 4:2:0, 4:2:2, ...
+    // cropped by the conformance window
+    pub codec_size: Size,
+    pub present_size: Size,
+}
+
+// H.265 seq_parameter_set_rbsp, up to the bit depths
+pub fn parseHEVCSPS(uint8array: &[u8]) -> Result<HEVCSPSInfo, JsValue> {
+    let rbsp = _ebsp2rbsp(uint8array);
+    let gb = ExpGolomb::new(rbsp);
+
+    gb.readBits(16)?; // nal_unit_header
+    gb.readBits(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = gb.readBits(3)?; // sps_max_sub_layers_minus1
+    gb.readBits(1)?; // sps_temporal_id_nesting_flag
+
+    // profile_tier_level: general profile (88 bits) + general_level_idc
+    gb.readBits(32)?;
+    gb.readBits(32)?;
+    gb.readBits(32)?;
+    let mut sub_layer_profile_present = vec![];
+    let mut sub_layer_level_present = vec![];
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layer_profile_present.push(gb.readBool()?);
+        sub_layer_level_present.push(gb.readBool()?);
+    }
+    if max_sub_layers_minus1 > 0 {
+        for _ in max_sub_layers_minus1..8 {
+            gb.readBits(2)?; // reserved_zero_2bits
+        }
+    }
+    for i in 0..max_sub_layers_minus1 as usize {
+        if sub_layer_profile_present[i] {
+            gb.readBits(32)?;
+            gb.readBits(32)?;
+            gb.readBits(24)?;
+        }
+        if sub_layer_level_present[i] {
+            gb.readBits(8)?; // sub_layer_level_idc
+        }
+    }
+
+    gb.readUEG()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = gb.readUEG()?;
+    let mut separate_colour_plane_flag = false;
+    if chroma_format_idc == 3 {
+        separate_colour_plane_flag = gb.readBool()?;
+    }
+    let mut codec_width = gb.readUEG()?; // pic_width_in_luma_samples
+    let mut codec_height = gb.readUEG()?; // pic_height_in_luma_samples
+
+    if gb.readBool()? {
+        // conformance_window_flag, offsets in chroma samples
+        // SubWidthC / SubHeightC
+        let (sub_wc, sub_hc) = match chroma_format_idc {
+            _ if separate_colour_plane_flag => (1, 1),
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        let left = gb.readUEG()?;
+        let right = gb.readUEG()?;
+        let top = gb.readUEG()?;
+        let bottom = gb.readUEG()?;
+        codec_width -= (left + right) * sub_wc;
+        codec_height -= (top + bottom) * sub_hc;
+    }
+    let bit_depth = gb.readUEG()? + 8; // bit_depth_luma_minus8
+
+    let chroma_format_table = [0, 420, 422, 444];
+
+    Ok(HEVCSPSInfo {
+        bit_depth,
+        chroma_format: chroma_format_table[chroma_format_idc.min(3) as usize],
+        codec_size: Size {
+            width: codec_width,
+            height: codec_height,
+        },
+        // no SAR parsed, square pixels
+        present_size: Size {
+            width: codec_width,
+            height: codec_height,
+        },
+    })
+}
+
 fn _skipScalingList(gb: ExpGolomb, count: u32) {
     let last_scale = 8;
     let next_scale = 8;
@@ -318,3 +433,47 @@ pub fn getChromaFormatString(chroma: u32) -> &'static str {
         _ => return "Unknown",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // High 10 4.0, 1920x1088 cropped to 1080, VUI: BT.2020 primaries, PQ, BT.2020 NCL, limited range
+    const AVC_SPS: [u8; 26] = [
+        0x67, 0x6E, 0x00, 0x28, 0xA6, 0xCD, 0x94, 0x07, 0x80, 0x22, 0x7E, 0x5C, 0x05, 0xA8, 0x48,
+        0x80, 0x4A, 0x00, 0x00, 0x07, 0xD2, 0x00, 0x01, 0xD4, 0xC1, 0x08,
+    ];
+
+    // Main 3.1, 4:2:0 8 bit, 1920x1088 coded with a 4 chroma rows conformance window
+    const HEVC_SPS: [u8; 26] = [
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+        0x03, 0x00, 0x5D, 0xA0, 0x03, 0xC0, 0x80, 0x11, 0x07, 0xCB, 0xC0,
+    ];
+
+    #[wasm_bindgen_test]
+    fn hevc_sps_size_is_cropped_by_the_conformance_window() {
+        let config = parseHEVCSPS(&HEVC_SPS).unwrap();
+        assert_eq!(
+            (config.codec_size.width, config.codec_size.height),
+            (1920, 1080)
+        );
+        assert_eq!(config.bit_depth, 8);
+        assert_eq!(config.chroma_format, 420);
+    }
+
+    #[wasm_bindgen_test]
+    fn sps_colour_description() {
+        let config = parseSPS(&AVC_SPS).unwrap();
+        assert_eq!(
+            (config.codec_size.width, config.codec_size.height),
+            (1920, 1080)
+        );
+        assert_eq!(config.bit_depth, 10);
+        let color = config.color.unwrap();
+        assert_eq!(color.colour_primaries, 9);
+        assert_eq!(color.transfer_characteristics, 16);
+        assert_eq!(color.matrix_coefficients, 9);
+        assert!(!color.full_range);
+    }
+}
@@ -5,6 +5,7 @@ extern crate serde_derive;
 
 use wasm_bindgen::prelude::*;
 
+mod config;
 mod io;
 mod core;
 mod demux;
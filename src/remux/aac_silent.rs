@@ -1,7 +1,6 @@
 /*
  * Copyright (C) 2016 Bilibili. All Rights Reserved.
  *
- * This file is modified from dailymotion's hls.js library (hls.js/src/helper/aac.js)
  * @author zheng qian <xqq@xqq.im>
  *
  * Licensed under the Apache License, Version 2.0 (the "License");
@@ -17,63 +16,215 @@
  * limitations under the License.
  */
 
-pub fn getSilentFrame(codec: &str, channelCount: usize) -> &'static [u8] {
-    if codec == "mp4a.40.2" {
-        // handle LC-AAC
-        if channelCount == 1 {
-            return &[0x00, 0xc8, 0x00, 0x80, 0x23, 0x80];
-        } else if channelCount == 2 {
-            return &[0x21, 0x00, 0x49, 0x90, 0x02, 0x19, 0x00, 0x23, 0x80];
-        } else if (channelCount == 3) {
-            return &[
-                0x00, 0xc8, 0x00, 0x80, 0x20, 0x84, 0x01, 0x26, 0x40, 0x08, 0x64, 0x00, 0x8e,
-            ];
-        } else if (channelCount == 4) {
-            return &[
-                0x00, 0xc8, 0x00, 0x80, 0x20, 0x84, 0x01, 0x26, 0x40, 0x08, 0x64, 0x00, 0x80, 0x2c,
-                0x80, 0x08, 0x02, 0x38,
-            ];
-        } else if (channelCount == 5) {
-            return &[
-                0x00, 0xc8, 0x00, 0x80, 0x20, 0x84, 0x01, 0x26, 0x40, 0x08, 0x64, 0x00, 0x82, 0x30,
-                0x04, 0x99, 0x00, 0x21, 0x90, 0x02, 0x38,
-            ];
-        } else if (channelCount == 6) {
-            return &[
-                0x00, 0xc8, 0x00, 0x80, 0x20, 0x84, 0x01, 0x26, 0x40, 0x08, 0x64, 0x00, 0x82, 0x30,
-                0x04, 0x99, 0x00, 0x21, 0x90, 0x02, 0x00, 0xb2, 0x00, 0x20, 0x08, 0xe0,
-            ];
+// Silent AAC frames: a raw_data_block (ISO/IEC 14496-3 4.4.2.1) with one element per channel
+// element of the layout, each of them without any scale factor band, so no spectral data.
+// With SBR / PS signalled, the frame carries no extension payload and decoders upsample the
+// silent core.
+
+use crate::demux::aac_config::{self, AudioSpecificConfig};
+use crate::utils::bit_writer::BitWriter;
+
+// syntactic element ids, ISO/IEC 14496-3 Table 4.85
+const ID_SCE: u32 = 0;
+const ID_CPE: u32 = 1;
+const ID_LFE: u32 = 3;
+const ID_END: u32 = 7;
+
+// any value does, there is nothing to scale
+const GLOBAL_GAIN: u32 = 100;
+
+// channel elements of each channelConfiguration, ISO/IEC 14496-3 Table 1.19
+fn defaultElements(channelConfiguration: u8) -> Option<&'static [u32]> {
+    let elements: &'static [u32] = match channelConfiguration {
+        1 => &[ID_SCE],
+        2 => &[ID_CPE],
+        3 => &[ID_SCE, ID_CPE],
+        4 => &[ID_SCE, ID_CPE, ID_SCE],
+        5 => &[ID_SCE, ID_CPE, ID_CPE],
+        6 => &[ID_SCE, ID_CPE, ID_CPE, ID_LFE],
+        7 | 12 => &[ID_SCE, ID_CPE, ID_CPE, ID_CPE, ID_LFE],
+        11 => &[ID_SCE, ID_CPE, ID_CPE, ID_SCE, ID_LFE],
+        13 => &[
+            ID_SCE, ID_CPE, ID_CPE, ID_CPE, ID_CPE, ID_SCE, ID_LFE, ID_LFE, ID_SCE, ID_CPE, ID_CPE,
+            ID_SCE, ID_CPE, ID_SCE, ID_SCE, ID_CPE,
+        ],
+        14 => &[ID_SCE, ID_CPE, ID_CPE, ID_LFE, ID_CPE],
+        _ => return None,
+    };
+    Some(elements)
+}
+
+// (id_syn_ele, element_instance_tag) of each element of a frame
+fn channelElements(config: &AudioSpecificConfig) -> Option<Vec<(u32, u8)>> {
+    if let Some(pce) = &config.programConfig {
+        let elements = pce.frontElements.iter();
+        let elements = elements.chain(pce.sideElements.iter());
+        let elements = elements.chain(pce.backElements.iter());
+        let mut channelElements: Vec<(u32, u8)> = elements
+            .map(|&(isCpe, tag)| (if isCpe { ID_CPE } else { ID_SCE }, tag))
+            .collect();
+        channelElements.extend(pce.lfeElements.iter().map(|&tag| (ID_LFE, tag)));
+        return Some(channelElements);
+    }
+
+    // instance tags count up per element type
+    let mut tags = [0u8; 4];
+    let elements = defaultElements(config.channelConfiguration)?;
+    Some(
+        elements
+            .iter()
+            .map(|&id| {
+                let tag = tags[id as usize];
+                tags[id as usize] += 1;
+                (id, tag)
+            })
+            .collect(),
+    )
+}
+
+fn writeIcsInfo(writer: &mut BitWriter) {
+    writer.write(0, 1); // ics_reserved_bit
+    writer.write(0, 2); // window_sequence: ONLY_LONG_SEQUENCE
+    writer.write(0, 1); // window_shape
+    writer.write(0, 6); // max_sfb: no section, no scale factor, no spectral data
+    writer.write(0, 1); // predictor_data_present / ltp_data_present
+}
+
+fn writeIndividualChannelStream(writer: &mut BitWriter, commonWindow: bool) {
+    writer.write(GLOBAL_GAIN, 8); // global_gain
+    if !commonWindow {
+        writeIcsInfo(writer);
+    }
+    // section_data() and scale_factor_data() are empty with max_sfb 0
+    writer.write(0, 1); // pulse_data_present
+    writer.write(0, 1); // tns_data_present
+    writer.write(0, 1); // gain_control_data_present
+}
+
+// A silent raw_data_block for the config, None for the object types without GA syntax
+// (ER AAC LD / ELD, USAC) or a reserved channel configuration
+pub fn silentFrame(config: &AudioSpecificConfig) -> Option<Vec<u8>> {
+    if !(aac_config::AOT_AAC_MAIN..=4).contains(&config.audioObjectType) {
+        return None;
+    }
+
+    let mut writer = BitWriter::new();
+    for (id, tag) in channelElements(config)? {
+        writer.write(id, 3); // id_syn_ele
+        writer.write(tag as u32, 4); // element_instance_tag
+        if id == ID_CPE {
+            writer.write(1, 1); // common_window
+            writeIcsInfo(&mut writer);
+            writer.write(0, 2); // ms_mask_present
+            writeIndividualChannelStream(&mut writer, true);
+            writeIndividualChannelStream(&mut writer, true);
+        } else {
+            writeIndividualChannelStream(&mut writer, false);
         }
-    } else {
-        // handle HE-AAC (mp4a.40.5 / mp4a.40.29)
-        if (channelCount == 1) {
-            // ffmpeg -y -f lavfi -i "aevalsrc=0:d=0.05" -c:a libfdk_aac -profile:a aac_he -b:a 4k output.aac && hexdump -v -e '16/1 "0x%x," "\n"' -v output.aac
-            return &[
-                0x1, 0x40, 0x22, 0x80, 0xa3, 0x4e, 0xe6, 0x80, 0xba, 0x8, 0x0, 0x0, 0x0, 0x1c, 0x6,
-                0xf1, 0xc1, 0xa, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
-                0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
-                0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
-                0x5a, 0x5e,
-            ];
-        } else if (channelCount == 2) {
-            // ffmpeg -y -f lavfi -i "aevalsrc=0|0:d=0.05" -c:a libfdk_aac -profile:a aac_he_v2 -b:a 4k output.aac && hexdump -v -e '16/1 "0x%x," "\n"' -v output.aac
-            return &[
-                0x1, 0x40, 0x22, 0x80, 0xa3, 0x5e, 0xe6, 0x80, 0xba, 0x8, 0x0, 0x0, 0x0, 0x0, 0x95,
-                0x0, 0x6, 0xf1, 0xa1, 0xa, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
-                0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
-                0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
-                0x5a, 0x5e,
-            ];
-        } else if (channelCount == 3) {
-            // ffmpeg -y -f lavfi -i "aevalsrc=0|0|0:d=0.05" -c:a libfdk_aac -profile:a aac_he_v2 -b:a 4k output.aac && hexdump -v -e '16/1 "0x%x," "\n"' -v output.aac
-            return &[
-                0x1, 0x40, 0x22, 0x80, 0xa3, 0x5e, 0xe6, 0x80, 0xba, 0x8, 0x0, 0x0, 0x0, 0x0, 0x95,
-                0x0, 0x6, 0xf1, 0xa1, 0xa, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
-                0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
-                0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
-                0x5a, 0x5e,
-            ];
+    }
+    writer.write(ID_END, 3);
+    Some(writer.finish())
+}
+
+pub fn getSilentFrame(audioSpecificConfig: &[u8]) -> Option<Vec<u8>> {
+    let config = aac_config::parseAudioSpecificConfig(audioSpecificConfig).ok()?;
+    silentFrame(&config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // MSB-first reader over a frame, returns the next `bits` bits
+    fn readBits(frame: &[u8], position: &mut usize, bits: usize) -> u32 {
+        let mut value = 0;
+        for _ in 0..bits {
+            let bit = (frame[*position / 8] >> (7 - *position % 8)) & 0x01;
+            value = (value << 1) | bit as u32;
+            *position += 1;
+        }
+        value
+    }
+
+    // Walks a silent raw_data_block and returns its channel count, asserting that every
+    // individual_channel_stream has no scale factor band and that the frame ends after ID_END
+    fn decodeSilentFrame(frame: &[u8]) -> usize {
+        let mut position = 0;
+        let read = |position: &mut usize, bits: usize| readBits(frame, position, bits);
+        let readIcsInfo = |position: &mut usize| {
+            assert_eq!(read(position, 1), 0); // ics_reserved_bit
+            assert_eq!(read(position, 2), 0); // window_sequence: ONLY_LONG_SEQUENCE
+            read(position, 1); // window_shape
+            assert_eq!(read(position, 6), 0); // max_sfb
+            assert_eq!(read(position, 1), 0); // predictor_data_present
+        };
+        let readIcs = |position: &mut usize, commonWindow: bool| {
+            read(position, 8); // global_gain
+            if !commonWindow {
+                readIcsInfo(position);
+            }
+            assert_eq!(read(position, 3), 0); // pulse / tns / gain_control_data_present
+        };
+
+        let mut channels = 0;
+        loop {
+            match read(&mut position, 3) {
+                // SCE, LFE
+                0 | 3 => {
+                    read(&mut position, 4); // element_instance_tag
+                    readIcs(&mut position, false);
+                    channels += 1;
+                }
+                // CPE
+                1 => {
+                    read(&mut position, 4); // element_instance_tag
+                    assert_eq!(read(&mut position, 1), 1); // common_window
+                    readIcsInfo(&mut position);
+                    assert_eq!(read(&mut position, 2), 0); // ms_mask_present
+                    readIcs(&mut position, true);
+                    readIcs(&mut position, true);
+                    channels += 2;
+                }
+                7 => break,
+                id => panic!("unexpected id_syn_ele {}", id),
+            }
         }
+        assert_eq!((position + 7) / 8, frame.len());
+        channels
+    }
+
+    #[wasm_bindgen_test]
+    fn channel_configurations() {
+        // AAC LC 44.1kHz stereo
+        let frame = getSilentFrame(&[0x12, 0x10]).unwrap();
+        assert_eq!(decodeSilentFrame(&frame), 2);
+        // AAC LC 48kHz 7.1
+        let frame = getSilentFrame(&[0x11, 0xB8]).unwrap();
+        assert_eq!(decodeSilentFrame(&frame), 8);
+        // AAC LC with an escape-coded 50kHz sampling frequency, stereo
+        let frame = getSilentFrame(&[0x17, 0x80, 0x61, 0xA8, 0x10]).unwrap();
+        assert_eq!(decodeSilentFrame(&frame), 2);
+    }
+
+    #[wasm_bindgen_test]
+    fn explicit_he_aac_v2() {
+        // HE-AACv2, 24kHz mono core: PS is decoded from the single channel
+        let frame = getSilentFrame(&[0xEB, 0x09, 0x88, 0x00]).unwrap();
+        assert_eq!(decodeSilentFrame(&frame), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn program_config_element() {
+        // AAC LC 48kHz, 5.1 from a PCE: front SCE + CPE, back CPE, LFE
+        let asc = [0x11, 0x80, 0x04, 0xC8, 0x05, 0x00, 0x01, 0x19, 0x18, 0x00];
+        let frame = getSilentFrame(&asc).unwrap();
+        assert_eq!(decodeSilentFrame(&frame), 6);
+    }
+
+    #[wasm_bindgen_test]
+    fn unsupported_object_type() {
+        // ER AAC ELD has no GA raw_data_block
+        assert!(getSilentFrame(&[0xF8, 0xE6, 0x50, 0x00]).is_none());
     }
-    return &[];
 }
@@ -0,0 +1,147 @@
+use crate::utils::logger::Log;
+
+const TAG: &'static str = "ADTS";
+
+const ADTS_HEADER_SIZE: usize = 7;
+// frame_length is a 13 bits field, including the header itself
+const ADTS_MAX_FRAME_LENGTH: usize = (1 << 13) - 1;
+
+// The fields of an AudioSpecificConfig that an ADTS header can carry
+#[derive(Copy, Clone)]
+pub struct AdtsConfig {
+    // ADTS profile: audioObjectType - 1, AAC Main / LC / SSR / LTP only
+    pub profile: u8,
+    pub samplingIndex: u8,
+    pub channelConfig: u8,
+}
+
+pub fn parseAdtsConfig(audioSpecificConfig: &[u8]) -> Option<AdtsConfig> {
+    if audioSpecificConfig.len() < 2 {
+        return None;
+    }
+    let asc = audioSpecificConfig;
+
+    // 5 bits audioObjectType, 4 bits samplingFrequencyIndex, 4 bits channelConfiguration
+    let audioObjectType = asc[0] >> 3;
+    let samplingIndex = ((asc[0] & 0x07) << 1) | (asc[1] >> 7);
+    let channelConfig = (asc[1] >> 3) & 0x0F;
+
+    let profile = match audioObjectType {
+        1..=4 => audioObjectType - 1,
+        // HE-AAC / HE-AACv2 with explicit signalling: samplingIndex is the core rate,
+        // ADTS carries the LC core and the decoder finds SBR / PS implicitly
+        5 | 29 => 1,
+        _ => {
+            Log::w(
+                TAG,
+                &format!(
+                    "audioObjectType {} cannot be carried by ADTS",
+                    audioObjectType
+                ),
+            );
+            return None;
+        }
+    };
+
+    if samplingIndex >= 13 {
+        // 0x0F: explicit 24-bit sampling frequency, not expressible in ADTS
+        Log::w(TAG, "Explicit sampling frequency cannot be carried by ADTS");
+        return None;
+    }
+
+    if channelConfig == 0 {
+        Log::w(
+            TAG,
+            "channelConfiguration 0: decoder relies on an in-band PCE",
+        );
+    }
+
+    Some(AdtsConfig {
+        profile,
+        samplingIndex,
+        channelConfig,
+    })
+}
+
+pub fn adtsHeader(config: &AdtsConfig, payloadLength: usize) -> [u8; ADTS_HEADER_SIZE] {
+    let frameLength = ADTS_HEADER_SIZE + payloadLength;
+
+    [
+        0xFF, // syncword
+        0xF1, // syncword + ID: MPEG-4 + layer: 0 + protection_absent: 1
+        (config.profile << 6) | (config.samplingIndex << 2) | ((config.channelConfig >> 2) & 0x01),
+        ((config.channelConfig & 0x03) << 6) | ((frameLength >> 11) & 0x03) as u8,
+        ((frameLength >> 3) & 0xFF) as u8,
+        (((frameLength & 0x07) << 5) as u8) | 0x1F, // adts_buffer_fullness: 0x7FF (VBR)
+        0xFC, // adts_buffer_fullness + number_of_raw_data_blocks_in_frame: 0
+    ]
+}
+
+// Wrap raw AAC frames from _parseAACAudioData into ADTS frames
+pub fn wrapAdts(config: &AdtsConfig, payload: &[u8]) -> Option<Vec<u8>> {
+    if ADTS_HEADER_SIZE + payload.len() > ADTS_MAX_FRAME_LENGTH {
+        Log::w(TAG, "AAC frame too large for an ADTS header, dropped");
+        return None;
+    }
+
+    let mut frame = Vec::with_capacity(ADTS_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&adtsHeader(config, payload.len()));
+    frame.extend_from_slice(payload);
+    Some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn aac_lc_44100_stereo_header() {
+        // audioObjectType 2, samplingFrequencyIndex 4, channelConfiguration 2
+        let config = parseAdtsConfig(&[0x12, 0x10]).unwrap();
+        assert_eq!(
+            (config.profile, config.samplingIndex, config.channelConfig),
+            (1, 4, 2)
+        );
+
+        // frame_length: 7 + 100 = 107
+        assert_eq!(
+            adtsHeader(&config, 100),
+            [0xFF, 0xF1, 0x50, 0x80, 0x0D, 0x7F, 0xFC]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn he_aac_carries_the_lc_core() {
+        // audioObjectType 5, samplingFrequencyIndex 6 (24kHz core), channelConfiguration 1
+        let config = parseAdtsConfig(&[0x2B, 0x08]).unwrap();
+        assert_eq!(
+            (config.profile, config.samplingIndex, config.channelConfig),
+            (1, 6, 1)
+        );
+        // channelConfiguration 7: its high bit lands in the third byte
+        let config = parseAdtsConfig(&[0x12, 0x38]).unwrap();
+        assert_eq!(adtsHeader(&config, 0)[2], 0x51);
+        assert_eq!(adtsHeader(&config, 0)[3] >> 6, 0x03);
+    }
+
+    #[wasm_bindgen_test]
+    fn rejects_what_adts_cannot_carry() {
+        // audioObjectType 23 (ER AAC LD)
+        assert!(parseAdtsConfig(&[0xBA, 0x10]).is_none());
+        // explicit sampling frequency
+        assert!(parseAdtsConfig(&[0x17, 0x80]).is_none());
+        assert!(parseAdtsConfig(&[0x12]).is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn wraps_frames_up_to_the_13_bit_length() {
+        let config = parseAdtsConfig(&[0x12, 0x10]).unwrap();
+        let frame = wrapAdts(&config, &[0xAB; 16]).unwrap();
+        assert_eq!(frame.len(), 23);
+        assert_eq!(&frame[7..], &[0xAB; 16]);
+
+        assert!(wrapAdts(&config, &vec![0; ADTS_MAX_FRAME_LENGTH - 7]).is_some());
+        assert!(wrapAdts(&config, &vec![0; ADTS_MAX_FRAME_LENGTH - 6]).is_none());
+    }
+}
@@ -0,0 +1,392 @@
+use crate::demux::flv_demuxer::Unit;
+use crate::utils::logger::Log;
+
+const START_CODE: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
+
+// access_unit_delimiter: primary_pic_type = 7 (any slice type) + rbsp_stop_one_bit
+const AVC_AUD: [u8; 2] = [0x09, 0xF0];
+// nal_unit_type = 35, nuh_layer_id = 0, nuh_temporal_id_plus1 = 1, pic_type = 2 + rbsp_stop_one_bit
+const HEVC_AUD: [u8; 3] = [0x46, 0x01, 0x50];
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum NaluCodec {
+    H264,
+    H265,
+}
+
+impl NaluCodec {
+    pub(crate) fn naluType(&self, header: u8) -> u8 {
+        match self {
+            NaluCodec::H264 => header & 0x1F,
+            NaluCodec::H265 => (header >> 1) & 0x3F,
+        }
+    }
+
+    // coded slice NALUs, the only ones carrying picture data
+    pub(super) fn isVCL(&self, naluType: u8) -> bool {
+        match self {
+            NaluCodec::H264 => naluType >= 1 && naluType <= 5,
+            NaluCodec::H265 => naluType <= 31,
+        }
+    }
+
+    // bytes of the NALU header
+    pub(crate) fn headerSize(&self) -> usize {
+        match self {
+            NaluCodec::H264 => 1,
+            NaluCodec::H265 => 2,
+        }
+    }
+
+    // SEI, prefix SEI for HEVC: the one carrying HDR metadata and captions
+    pub(crate) fn isSEI(&self, naluType: u8) -> bool {
+        match self {
+            NaluCodec::H264 => naluType == 6,
+            NaluCodec::H265 => naluType == 39,
+        }
+    }
+
+    fn isAUD(&self, naluType: u8) -> bool {
+        match self {
+            NaluCodec::H264 => naluType == 9,
+            NaluCodec::H265 => naluType == 35,
+        }
+    }
+
+    // SPS / PPS (and VPS for HEVC)
+    fn isParameterSet(&self, naluType: u8) -> bool {
+        match self {
+            NaluCodec::H264 => naluType == 7 || naluType == 8,
+            NaluCodec::H265 => naluType >= 32 && naluType <= 34,
+        }
+    }
+
+    // IDR for AVC, IRAP (BLA / IDR / CRA) for HEVC
+    pub(crate) fn isRandomAccess(&self, naluType: u8) -> bool {
+        match self {
+            NaluCodec::H264 => naluType == 5,
+            NaluCodec::H265 => naluType >= 16 && naluType <= 23,
+        }
+    }
+
+    fn aud(&self) -> &'static [u8] {
+        match self {
+            NaluCodec::H264 => &AVC_AUD,
+            NaluCodec::H265 => &HEVC_AUD,
+        }
+    }
+}
+
+// Parameter sets extracted from avcC / hvcC, in the order they must precede a random access point
+pub struct ParameterSets {
+    pub naluLengthSize: usize,
+    pub nalus: Vec<Vec<u8>>,
+}
+
+// AVCDecoderConfigurationRecord, ISO/IEC 14496-15 5.3.3.1
+pub fn parseAVCDecoderConfigurationRecord(avcc: &[u8]) -> Option<ParameterSets> {
+    if avcc.len() < 7 || avcc[0] != 1 {
+        return None;
+    }
+
+    let naluLengthSize = ((avcc[4] & 3) + 1) as usize;
+    let mut nalus = Vec::new();
+    let mut offset = 5;
+
+    let spsCount = (avcc[offset] & 0x1F) as usize;
+    offset += 1;
+    for _ in 0..spsCount {
+        let (nalu, next) = readParameterSet(avcc, offset)?;
+        nalus.push(nalu);
+        offset = next;
+    }
+
+    let ppsCount = *avcc.get(offset)? as usize;
+    offset += 1;
+    for _ in 0..ppsCount {
+        let (nalu, next) = readParameterSet(avcc, offset)?;
+        nalus.push(nalu);
+        offset = next;
+    }
+
+    Some(ParameterSets {
+        naluLengthSize,
+        nalus,
+    })
+}
+
+// HEVCDecoderConfigurationRecord, ISO/IEC 14496-15 8.3.3.1
+pub fn parseHEVCDecoderConfigurationRecord(hvcc: &[u8]) -> Option<ParameterSets> {
+    if hvcc.len() < 23 || hvcc[0] != 1 {
+        return None;
+    }
+
+    let naluLengthSize = ((hvcc[21] & 3) + 1) as usize;
+    let mut arrays: Vec<(u8, Vec<Vec<u8>>)> = Vec::new();
+    let mut offset = 23;
+
+    let numOfArrays = hvcc[22] as usize;
+    for _ in 0..numOfArrays {
+        let naluType = *hvcc.get(offset)? & 0x3F;
+        let numNalus = ((*hvcc.get(offset + 1)? as usize) << 8) | *hvcc.get(offset + 2)? as usize;
+        offset += 3;
+
+        let mut nalus = Vec::with_capacity(numNalus);
+        for _ in 0..numNalus {
+            let (nalu, next) = readParameterSet(hvcc, offset)?;
+            nalus.push(nalu);
+            offset = next;
+        }
+        arrays.push((naluType, nalus));
+    }
+
+    // VPS(32) -> SPS(33) -> PPS(34) -> SEI, regardless of the order in the record
+    arrays.sort_by_key(|(naluType, _)| *naluType);
+
+    Some(ParameterSets {
+        naluLengthSize,
+        nalus: arrays.into_iter().flat_map(|(_, nalus)| nalus).collect(),
+    })
+}
+
+fn readParameterSet(record: &[u8], offset: usize) -> Option<(Vec<u8>, usize)> {
+    let length = ((*record.get(offset)? as usize) << 8) | *record.get(offset + 1)? as usize;
+    let begin = offset + 2;
+    let end = begin + length;
+    if end > record.len() {
+        return None;
+    }
+    Some((Vec::from(&record[begin..end]), end))
+}
+
+// Converts length-prefixed (AVCC / HVCC) samples into an Annex-B byte stream
+pub struct AnnexBExporter {
+    TAG: &'static str,
+    _codec: NaluCodec,
+    _parameterSets: Option<ParameterSets>,
+}
+
+impl AnnexBExporter {
+    pub fn new(codec: NaluCodec) -> AnnexBExporter {
+        AnnexBExporter {
+            TAG: "AnnexBExporter",
+            _codec: codec,
+            _parameterSets: None,
+        }
+    }
+
+    pub fn setDecoderConfigurationRecord(&mut self, record: &[u8]) -> bool {
+        let parameterSets = match self._codec {
+            NaluCodec::H264 => parseAVCDecoderConfigurationRecord(record),
+            NaluCodec::H265 => parseHEVCDecoderConfigurationRecord(record),
+        };
+
+        if parameterSets.is_none() {
+            Log::w(
+                self.TAG,
+                "Invalid decoder configuration record, parameter sets unavailable",
+            );
+        }
+        self._parameterSets = parameterSets;
+        self._parameterSets.is_some()
+    }
+
+    // Emit one access unit: AUD, then parameter sets before random access points, then the sample NALUs
+    pub fn exportSample(&self, units: &[Unit], isKeyframe: bool) -> Vec<u8> {
+        let parameterSets = match &self._parameterSets {
+            Some(parameterSets) => parameterSets,
+            None => {
+                Log::w(
+                    self.TAG,
+                    "Sample arrived before decoder configuration record, dropped",
+                );
+                return Vec::new();
+            }
+        };
+        let lengthSize = parameterSets.naluLengthSize;
+        let codec = self._codec;
+
+        let payloads: Vec<&[u8]> = units
+            .iter()
+            .filter(|unit| unit.data.len() > lengthSize)
+            .map(|unit| &unit.data[lengthSize..])
+            .collect();
+
+        let isRandomAccess = isKeyframe
+            || payloads
+                .iter()
+                .any(|nalu| codec.isRandomAccess(codec.naluType(nalu[0])));
+        let hasParameterSets = payloads
+            .iter()
+            .any(|nalu| codec.isParameterSet(codec.naluType(nalu[0])));
+
+        let mut out = Vec::with_capacity(
+            units
+                .iter()
+                .map(|unit| unit.data.len() + START_CODE.len())
+                .sum::<usize>()
+                + 64,
+        );
+
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(codec.aud());
+
+        if isRandomAccess && !hasParameterSets {
+            for nalu in parameterSets.nalus.iter() {
+                out.extend_from_slice(&START_CODE);
+                out.extend_from_slice(nalu);
+            }
+        }
+
+        for nalu in payloads {
+            if codec.isAUD(codec.naluType(nalu[0])) {
+                // already emitted our own delimiter
+                continue;
+            }
+            out.extend_from_slice(&START_CODE);
+            out.extend_from_slice(nalu);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    // avcC: version 1, High 4.0, lengthSizeMinusOne 3, one SPS, one PPS
+    const AVCC: [u8; 19] = [
+        0x01, 0x64, 0x00, 0x28, // configurationVersion, profile, compatibility, level
+        0xFF, 0xE1, // lengthSizeMinusOne: 3, numOfSequenceParameterSets: 1
+        0x00, 0x04, 0x67, 0x64, 0x00, 0x28, // SPS
+        0x01, // numOfPictureParameterSets
+        0x00, 0x04, 0x68, 0xEE, 0x3C, 0x80, // PPS
+    ];
+
+    // hvcC: version 1, Main 3.1, lengthSizeMinusOne 3, arrays stored as PPS, VPS, SPS
+    const HVCC: [u8; 47] = [
+        0x01, 0x01, 0x60, 0x00, 0x00, 0x00, // configurationVersion, profile, compatibility
+        0x90, 0x00, 0x00, 0x00, 0x00, 0x00, // constraint_indicator_flags
+        0x5D, 0xF0, 0x00, 0xFC, 0xFD, 0xF8, 0xF8, 0x00, 0x00, // level 93 .. avgFrameRate
+        0x0F, 0x03, // lengthSizeMinusOne: 3, numOfArrays: 3
+        0xA2, 0x00, 0x01, 0x00, 0x03, 0x44, 0x01, 0xC1, // PPS
+        0xA0, 0x00, 0x01, 0x00, 0x03, 0x40, 0x01, 0x0C, // VPS
+        0xA1, 0x00, 0x01, 0x00, 0x03, 0x42, 0x01, 0x01, // SPS
+    ];
+
+    fn unit(nalu: &[u8]) -> Unit {
+        let mut data = (nalu.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(nalu);
+        Unit { _type: 0, data }
+    }
+
+    #[wasm_bindgen_test]
+    fn parses_avc_decoder_configuration_record() {
+        let parameterSets = parseAVCDecoderConfigurationRecord(&AVCC).unwrap();
+        assert_eq!(parameterSets.naluLengthSize, 4);
+        assert_eq!(
+            parameterSets.nalus,
+            vec![vec![0x67, 0x64, 0x00, 0x28], vec![0x68, 0xEE, 0x3C, 0x80]]
+        );
+
+        // PPS length running past the record
+        assert!(parseAVCDecoderConfigurationRecord(&AVCC[..17]).is_none());
+        assert!(parseAVCDecoderConfigurationRecord(&[0x00; 7]).is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn parses_hevc_decoder_configuration_record_in_vps_sps_pps_order() {
+        let parameterSets = parseHEVCDecoderConfigurationRecord(&HVCC).unwrap();
+        assert_eq!(parameterSets.naluLengthSize, 4);
+        assert_eq!(
+            parameterSets.nalus,
+            vec![
+                vec![0x40, 0x01, 0x0C],
+                vec![0x42, 0x01, 0x01],
+                vec![0x44, 0x01, 0xC1]
+            ]
+        );
+
+        assert!(parseHEVCDecoderConfigurationRecord(&HVCC[..45]).is_none());
+        assert!(parseHEVCDecoderConfigurationRecord(&HVCC[..22]).is_none());
+    }
+
+    #[wasm_bindgen_test]
+    fn keyframe_gets_aud_and_parameter_sets() {
+        let mut exporter = AnnexBExporter::new(NaluCodec::H264);
+        assert!(exporter.setDecoderConfigurationRecord(&AVCC));
+
+        let out = exporter.exportSample(&[unit(&[0x65, 0x88, 0x84])], true);
+        assert_eq!(
+            out,
+            vec![
+                0x00, 0x00, 0x00, 0x01, 0x09, 0xF0, // AUD
+                0x00, 0x00, 0x00, 0x01, 0x67, 0x64, 0x00, 0x28, // SPS
+                0x00, 0x00, 0x00, 0x01, 0x68, 0xEE, 0x3C, 0x80, // PPS
+                0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84, // IDR slice
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn hevc_keyframe_gets_aud_and_vps_sps_pps() {
+        let mut exporter = AnnexBExporter::new(NaluCodec::H265);
+        assert!(exporter.setDecoderConfigurationRecord(&HVCC));
+
+        let out = exporter.exportSample(&[unit(&[0x26, 0x01, 0xAF])], true);
+        assert_eq!(
+            out,
+            vec![
+                0x00, 0x00, 0x00, 0x01, 0x46, 0x01, 0x50, // AUD
+                0x00, 0x00, 0x00, 0x01, 0x40, 0x01, 0x0C, // VPS
+                0x00, 0x00, 0x00, 0x01, 0x42, 0x01, 0x01, // SPS
+                0x00, 0x00, 0x00, 0x01, 0x44, 0x01, 0xC1, // PPS
+                0x00, 0x00, 0x00, 0x01, 0x26, 0x01, 0xAF, // IDR_W_RADL slice
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn hevc_cra_gets_parameter_sets_without_keyframe_flag() {
+        let mut exporter = AnnexBExporter::new(NaluCodec::H265);
+        exporter.setDecoderConfigurationRecord(&HVCC);
+
+        // the sample's own AUD is replaced by ours
+        let out = exporter.exportSample(
+            &[unit(&[0x46, 0x01, 0x10]), unit(&[0x2A, 0x01, 0xAF])],
+            false,
+        );
+        assert_eq!(out.len(), 7 + 3 * 7 + 7);
+        assert_eq!(&out[..7], &[0x00, 0x00, 0x00, 0x01, 0x46, 0x01, 0x50]);
+        assert_eq!(
+            &out[out.len() - 7..],
+            &[0x00, 0x00, 0x00, 0x01, 0x2A, 0x01, 0xAF]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn non_keyframe_keeps_its_nalus_only() {
+        let mut exporter = AnnexBExporter::new(NaluCodec::H264);
+        exporter.setDecoderConfigurationRecord(&AVCC);
+
+        // the sample's own AUD is replaced by ours
+        let out = exporter.exportSample(&[unit(&[0x09, 0x30]), unit(&[0x41, 0x9A])], false);
+        assert_eq!(
+            out,
+            vec![
+                0x00, 0x00, 0x00, 0x01, 0x09, 0xF0, // AUD
+                0x00, 0x00, 0x00, 0x01, 0x41, 0x9A, // non-IDR slice
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn sample_before_configuration_record_is_dropped() {
+        let exporter = AnnexBExporter::new(NaluCodec::H264);
+        assert!(exporter
+            .exportSample(&[unit(&[0x65, 0x88])], true)
+            .is_empty());
+    }
+}
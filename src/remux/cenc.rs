@@ -0,0 +1,318 @@
+// Common Encryption (ISO/IEC 23001-7) of remuxed samples, 'cenc' and 'cbcs' schemes
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use wasm_bindgen::prelude::*;
+
+use super::annexb::NaluCodec;
+use crate::config::{EncryptionConfig, EncryptionScheme};
+use crate::demux::flv_demuxer::Unit;
+
+const BLOCK_SIZE: usize = 16;
+
+// cbcs video pattern: encrypt 1 block, skip 9
+const CBCS_CRYPT_BYTE_BLOCK: u8 = 1;
+const CBCS_SKIP_BYTE_BLOCK: u8 = 9;
+
+pub struct SubsampleEntry {
+    pub clearBytes: u16,
+    pub protectedBytes: u32,
+}
+
+// Sample auxiliary information of one encrypted sample, written into senc
+pub struct SampleEncryption {
+    pub iv: Vec<u8>,
+    pub subsamples: Vec<SubsampleEntry>,
+}
+
+impl SampleEncryption {
+    // size of the entry in senc, as listed in saiz
+    pub fn size(&self) -> usize {
+        if self.subsamples.is_empty() {
+            self.iv.len()
+        } else {
+            self.iv.len() + 2 + 6 * self.subsamples.len()
+        }
+    }
+}
+
+// Encrypts the samples of one track in place
+pub struct CencEncryptor {
+    _cipher: Aes128,
+    _scheme: EncryptionScheme,
+    _iv: Vec<u8>,
+    // None for audio: whole samples are encrypted
+    _codec: Option<NaluCodec>,
+    _naluLengthSize: usize,
+}
+
+impl CencEncryptor {
+    fn new(
+        config: &EncryptionConfig,
+        codec: Option<NaluCodec>,
+        naluLengthSize: usize,
+    ) -> Result<CencEncryptor, JsValue> {
+        if config.key.len() != 16 {
+            return Err(JsValue::from("CencEncryptor: key must be 16 bytes"));
+        }
+        if config.kid.len() != 16 {
+            return Err(JsValue::from("CencEncryptor: KID must be 16 bytes"));
+        }
+        let ivSize = match config.scheme {
+            EncryptionScheme::Cenc => 8,
+            EncryptionScheme::Cbcs => 16,
+        };
+        if config.iv.len() != ivSize {
+            return Err(JsValue::from(format!(
+                "CencEncryptor: IV must be {} bytes for this scheme",
+                ivSize
+            )));
+        }
+
+        Ok(CencEncryptor {
+            _cipher: Aes128::new(GenericArray::from_slice(&config.key)),
+            _scheme: config.scheme,
+            _iv: config.iv.clone(),
+            _codec: codec,
+            _naluLengthSize: naluLengthSize,
+        })
+    }
+
+    pub fn forVideo(
+        config: &EncryptionConfig,
+        codec: NaluCodec,
+        naluLengthSize: usize,
+    ) -> Result<CencEncryptor, JsValue> {
+        CencEncryptor::new(config, Some(codec), naluLengthSize)
+    }
+
+    pub fn forAudio(config: &EncryptionConfig) -> Result<CencEncryptor, JsValue> {
+        CencEncryptor::new(config, None, 0)
+    }
+
+    // Encrypts a video sample made of length prefixed NALUs, leaving the NAL headers
+    // and the non-VCL NALUs in clear
+    pub fn encryptVideoSample(&mut self, units: &mut [Unit]) -> SampleEncryption {
+        let codec = match self._codec {
+            Some(codec) => codec,
+            None => panic!("CencEncryptor: not a video track encryptor"),
+        };
+
+        let mut subsamples = vec![];
+        let mut clearBytes = 0;
+        let mut ranges: Vec<(usize, usize, usize)> = vec![];
+
+        for (index, unit) in units.iter().enumerate() {
+            let headerSize = self._naluLengthSize + codec.headerSize();
+            let length = unit.data.len();
+            let isVCL =
+                length > headerSize && codec.isVCL(codec.naluType(unit.data[self._naluLengthSize]));
+
+            // keep the protected range block aligned, the remainder stays in clear
+            let protectedBytes = if isVCL {
+                (length - headerSize) / BLOCK_SIZE * BLOCK_SIZE
+            } else {
+                0
+            };
+            clearBytes += length - protectedBytes;
+            if protectedBytes == 0 {
+                continue;
+            }
+
+            pushSubsample(&mut subsamples, clearBytes, protectedBytes);
+            ranges.push((index, length - protectedBytes, length));
+            clearBytes = 0;
+        }
+        if clearBytes > 0 {
+            pushSubsample(&mut subsamples, clearBytes, 0);
+        }
+
+        let iv = match self._scheme {
+            EncryptionScheme::Cenc => {
+                // one key stream running across all the protected ranges of the sample
+                let mut keyStream = CtrKeyStream::new(&self._cipher, &self._iv);
+                for &(index, start, end) in &ranges {
+                    keyStream.apply(&mut units[index].data[start..end]);
+                }
+                self._nextIv()
+            }
+            EncryptionScheme::Cbcs => {
+                // the CBC chain restarts from the constant IV on every subsample
+                for &(index, start, end) in &ranges {
+                    cbcPattern(
+                        &self._cipher,
+                        &self._iv,
+                        &mut units[index].data[start..end],
+                        CBCS_CRYPT_BYTE_BLOCK,
+                        CBCS_SKIP_BYTE_BLOCK,
+                    );
+                }
+                vec![]
+            }
+        };
+
+        SampleEncryption { iv, subsamples }
+    }
+
+    // Encrypts a whole audio sample
+    pub fn encryptAudioSample(&mut self, data: &mut [u8]) -> SampleEncryption {
+        let iv = match self._scheme {
+            EncryptionScheme::Cenc => {
+                CtrKeyStream::new(&self._cipher, &self._iv).apply(data);
+                self._nextIv()
+            }
+            EncryptionScheme::Cbcs => {
+                cbcPattern(&self._cipher, &self._iv, data, 0, 0);
+                vec![]
+            }
+        };
+
+        SampleEncryption {
+            iv,
+            subsamples: vec![],
+        }
+    }
+
+    // Returns the IV of the sample just encrypted and counts it up for the next one.
+    // The block counter lives in the lower 8 bytes, so samples never share a key stream.
+    fn _nextIv(&mut self) -> Vec<u8> {
+        let iv = self._iv.clone();
+        for byte in self._iv.iter_mut().rev() {
+            *byte = byte.wrapping_add(1);
+            if *byte != 0 {
+                break;
+            }
+        }
+        iv
+    }
+}
+
+// clear bytes are a 16 bits field: split long clear runs into several entries
+fn pushSubsample(subsamples: &mut Vec<SubsampleEntry>, clearBytes: usize, protectedBytes: usize) {
+    let mut clearBytes = clearBytes;
+    while clearBytes > 0xFFFF {
+        subsamples.push(SubsampleEntry {
+            clearBytes: 0xFFFF,
+            protectedBytes: 0,
+        });
+        clearBytes -= 0xFFFF;
+    }
+    subsamples.push(SubsampleEntry {
+        clearBytes: clearBytes as u16,
+        protectedBytes: protectedBytes as u32,
+    });
+}
+
+struct CtrKeyStream<'a> {
+    cipher: &'a Aes128,
+    counter: [u8; 16],
+    block: [u8; 16],
+    // bytes of the current key stream block already used
+    used: usize,
+}
+
+impl<'a> CtrKeyStream<'a> {
+    // 8 bytes IVs are padded with a zero block counter
+    fn new(cipher: &'a Aes128, iv: &[u8]) -> CtrKeyStream<'a> {
+        let mut counter = [0u8; 16];
+        counter[..iv.len()].copy_from_slice(iv);
+        CtrKeyStream {
+            cipher,
+            counter,
+            block: [0u8; 16],
+            used: BLOCK_SIZE,
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            if self.used == BLOCK_SIZE {
+                let mut block = GenericArray::clone_from_slice(&self.counter);
+                self.cipher.encrypt_block(&mut block);
+                self.block.copy_from_slice(&block);
+                self.used = 0;
+
+                // only the lower 64 bits count, as in ISO/IEC 23001-7 9.5.2
+                for counterByte in self.counter[8..].iter_mut().rev() {
+                    *counterByte = counterByte.wrapping_add(1);
+                    if *counterByte != 0 {
+                        break;
+                    }
+                }
+            }
+            *byte ^= self.block[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+// AES-CBC over the whole blocks of data, encrypting cryptByteBlock blocks out of every
+// cryptByteBlock + skipByteBlock. A 0:0 pattern encrypts every block. The trailing partial
+// block is left in clear.
+fn cbcPattern(cipher: &Aes128, iv: &[u8], data: &mut [u8], cryptByteBlock: u8, skipByteBlock: u8) {
+    let cryptByteBlock = cryptByteBlock as usize;
+    let period = cryptByteBlock + skipByteBlock as usize;
+    let mut chain = [0u8; 16];
+    chain.copy_from_slice(iv);
+
+    for (index, block) in data.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+        if period > 0 && index % period >= cryptByteBlock {
+            continue;
+        }
+        for i in 0..BLOCK_SIZE {
+            block[i] ^= chain[i];
+        }
+        let mut encrypted = GenericArray::clone_from_slice(block);
+        cipher.encrypt_block(&mut encrypted);
+        block.copy_from_slice(&encrypted);
+        chain.copy_from_slice(block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // NIST SP 800-38A F.5.1
+    #[wasm_bindgen_test]
+    fn aes_ctr_vector() {
+        let mut data = hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let cipher = Aes128::new(GenericArray::from_slice(&hex(
+            "2b7e151628aed2a6abf7158809cf4f3c",
+        )));
+        CtrKeyStream::new(&cipher, &hex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff")).apply(&mut data);
+        assert_eq!(
+            data,
+            hex("874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff")
+        );
+    }
+
+    // NIST SP 800-38A F.2.1, with a 0:0 pattern encrypting every block
+    #[wasm_bindgen_test]
+    fn aes_cbc_vector() {
+        let mut data = hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let cipher = Aes128::new(GenericArray::from_slice(&hex(
+            "2b7e151628aed2a6abf7158809cf4f3c",
+        )));
+        cbcPattern(
+            &cipher,
+            &hex("000102030405060708090a0b0c0d0e0f"),
+            &mut data,
+            0,
+            0,
+        );
+        assert_eq!(
+            data,
+            hex("7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2")
+        );
+    }
+}
@@ -0,0 +1,689 @@
+use super::adts;
+use super::annexb::parseAVCDecoderConfigurationRecord;
+use super::mp4_generator::{self, EditListEntry, Meta};
+use super::mp4_muxer::{Flag, Mp4Sample};
+use crate::demux::amf_parser::{parseScriptData, AMFValue};
+use crate::demux::flv_demuxer::{parseKeyframesIndex, FLVDemuxer, KeyframesIndex, VideoTrack};
+use crate::demux::flv_tags::{isSequenceHeader, nextTagOffset, readTag, FlvTag};
+use crate::demux::hdr::HdrInfo;
+use crate::demux::sps_parser::parseSPS;
+use crate::utils::logger::Log;
+use wasm_bindgen::prelude::*;
+
+const TAG: &'static str = "ClipExtractor";
+
+// audio and video tags are interleaved by dts, but muxers drift a little
+const INTERLEAVE_TOLERANCE_MS: i64 = 1000;
+
+const flvSoundRateTable: &[u32] = &[5500, 11025, 22050, 44100, 48000];
+
+const mpegSamplingRates: &[u32] = &[
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+struct ClipSample<'a> {
+    dts: i64,
+    cts: i64,
+    isKeyframe: bool,
+    tag: &'a FlvTag<'a>,
+}
+
+// Everything needed to write a clip, collected by a single pass over the tags
+struct Clip<'a> {
+    scriptTag: Option<FlvTag<'a>>,
+    videoConfigTag: Option<FlvTag<'a>>,
+    audioConfigTag: Option<FlvTag<'a>>,
+    tags: Vec<FlvTag<'a>>,
+}
+
+/*
+ * Extract [startMs, endMs) of an FLV file into a standalone clip.
+ * container: "mp4" or "flv"
+ *
+ * Video starts at the keyframe at or before startMs, or at the first keyframe after it when
+ * there is none. For mp4, an edit list trims the leading frames so that playback begins
+ * exactly at startMs, or holds the lead-in before a later keyframe with an empty edit; FLV
+ * has no such mechanism, so a flv clip begins at that keyframe. Audio is cut at the frame
+ * nearest to each bound.
+ */
+#[wasm_bindgen]
+pub fn extract(
+    input: &[u8],
+    startMs: u32,
+    endMs: u32,
+    container: &str,
+) -> Result<Vec<u8>, JsValue> {
+    if endMs <= startMs {
+        return Err(JsValue::from(
+            "ClipExtractor: end must be greater than start",
+        ));
+    }
+    if container != "mp4" && container != "flv" {
+        return Err(JsValue::from(format!(
+            "ClipExtractor: Unsupported container {}",
+            container
+        )));
+    }
+
+    let probeData = match FLVDemuxer::probe(input) {
+        Some(probeData) => probeData,
+        None => return Err(JsValue::from("ClipExtractor: Invalid FLV header")),
+    };
+
+    let clip = collectTags(
+        input,
+        probeData.dataOffset + 4,
+        startMs as i64,
+        endMs as i64,
+    )?;
+    let (videoSamples, audioSamples) = selectSamples(&clip, startMs as i64, endMs as i64);
+
+    if videoSamples.is_empty() && audioSamples.is_empty() {
+        return Err(JsValue::from(
+            "ClipExtractor: No sample in the requested range",
+        ));
+    }
+
+    if container == "flv" {
+        return Ok(writeFlvClip(input, &clip, &videoSamples, &audioSamples));
+    }
+    writeMp4Clip(
+        &clip,
+        &videoSamples,
+        &audioSamples,
+        startMs as i64,
+        endMs as i64,
+    )
+}
+
+fn collectTags(
+    input: &[u8],
+    firstTagOffset: usize,
+    startMs: i64,
+    endMs: i64,
+) -> Result<Clip<'_>, JsValue> {
+    let mut clip = Clip {
+        scriptTag: None,
+        videoConfigTag: None,
+        audioConfigTag: None,
+        tags: vec![],
+    };
+    let mut keyframesIndex: Option<KeyframesIndex> = None;
+
+    // Header part: onMetaData and sequence headers, until the first coded frame
+    let mut offset = firstTagOffset;
+    while let Some(tag) = readTag(input, offset) {
+        if tag.tagType == 18 {
+            if let Some(onMetaData) = parseScriptData(tag.data, 0, tag.data.len()).get("onMetaData")
+            {
+                if let Some(AMFValue::Object(keyframes)) = onMetaData.get("keyframes") {
+                    keyframesIndex = parseKeyframesIndex(keyframes, 0);
+                }
+            }
+            offset = nextTagOffset(&tag);
+            clip.scriptTag = Some(tag);
+        } else if isSequenceHeader(&tag) {
+            offset = nextTagOffset(&tag);
+            if tag.tagType == 9 {
+                clip.videoConfigTag = Some(tag);
+            } else {
+                clip.audioConfigTag = Some(tag);
+            }
+        } else {
+            break;
+        }
+    }
+
+    // Jump to the closest indexed keyframe before start, instead of scanning from the beginning
+    if let Some(keyframesIndex) = keyframesIndex {
+        let position = keyframesIndex
+            .times
+            .iter()
+            .zip(keyframesIndex.filepositions.iter())
+            .filter(|(time, _)| **time as i64 <= startMs)
+            .map(|(_, fileposition)| *fileposition as usize)
+            .last();
+        if let Some(position) = position {
+            if position > offset && position < input.len() {
+                Log::v(
+                    TAG,
+                    &format!("Seek to keyframe at fileposition {}", position),
+                );
+                offset = position;
+            }
+        }
+    }
+
+    while let Some(tag) = readTag(input, offset) {
+        if tag.timestamp as i64 > endMs + INTERLEAVE_TOLERANCE_MS {
+            break;
+        }
+        offset = nextTagOffset(&tag);
+        if tag.tagType == 8 || tag.tagType == 9 {
+            if isSequenceHeader(&tag) {
+                Log::w(
+                    TAG,
+                    "Codec configuration changed mid-stream, kept the first one",
+                );
+                continue;
+            }
+            clip.tags.push(tag);
+        }
+    }
+
+    Ok(clip)
+}
+
+fn selectSamples<'a>(
+    clip: &'a Clip<'a>,
+    startMs: i64,
+    endMs: i64,
+) -> (Vec<ClipSample<'a>>, Vec<ClipSample<'a>>) {
+    let mut videoSamples: Vec<ClipSample> = vec![];
+    let mut audioSamples: Vec<ClipSample> = vec![];
+    // AAC frame at 44.1kHz until two frames have been seen
+    let mut audioFrameDuration: i64 = 23;
+    let mut lastAudioDts: Option<i64> = None;
+
+    for tag in clip.tags.iter() {
+        let dts = tag.timestamp as i64;
+
+        if tag.tagType == 9 {
+            if tag.data.len() < 5 || tag.data[1] != 1 {
+                continue;
+            }
+            let isKeyframe = (tag.data[0] >> 4) == 1;
+            let cts = (((tag.data[2] as i32) << 24
+                | (tag.data[3] as i32) << 16
+                | (tag.data[4] as i32) << 8)
+                >> 8) as i64; // SI24
+
+            if isKeyframe && dts <= startMs {
+                // a later keyframe is still at or before start, restart from it
+                videoSamples.clear();
+            }
+            // without a keyframe at or before start, the first one after it opens the clip
+            if videoSamples.is_empty() && !isKeyframe {
+                continue;
+            }
+            if dts >= endMs {
+                continue;
+            }
+            videoSamples.push(ClipSample {
+                dts,
+                cts,
+                isKeyframe,
+                tag,
+            });
+        } else {
+            // an empty tag carries no SoundSpec, nothing to play
+            if tag.data.is_empty() {
+                continue;
+            }
+            // the payload offset of every frame follows from the first SoundFormat
+            if let Some(first) = audioSamples.first() {
+                if (first.tag.data[0] >> 4) != (tag.data[0] >> 4) {
+                    continue;
+                }
+            }
+            let isAAC = (tag.data[0] >> 4) == 10;
+            if isAAC && (tag.data.len() < 2 || tag.data[1] != 1) {
+                continue;
+            }
+            if let Some(lastDts) = lastAudioDts {
+                if dts > lastDts {
+                    audioFrameDuration = dts - lastDts;
+                }
+            }
+            lastAudioDts = Some(dts);
+
+            // keep the frames whose middle falls into the range, i.e. cut at the nearest frame
+            let middle = dts + audioFrameDuration / 2;
+            if middle >= startMs && middle < endMs {
+                audioSamples.push(ClipSample {
+                    dts,
+                    cts: 0,
+                    isKeyframe: true,
+                    tag,
+                });
+            }
+        }
+    }
+
+    (videoSamples, audioSamples)
+}
+
+pub(super) fn writeFlvTag(out: &mut Vec<u8>, tag: &FlvTag, timestamp: u32) {
+    let dataSize = tag.data.len();
+    out.extend_from_slice(&[
+        tag.tagType,
+        (dataSize >> 16) as u8,
+        (dataSize >> 8) as u8,
+        dataSize as u8,
+        (timestamp >> 16) as u8,
+        (timestamp >> 8) as u8,
+        timestamp as u8,
+        (timestamp >> 24) as u8, // TimestampExtended
+        0x00,
+        0x00,
+        0x00, // StreamID
+    ]);
+    out.extend_from_slice(tag.data);
+    let prevTagSize = (11 + dataSize) as u32;
+    out.extend_from_slice(&prevTagSize.to_be_bytes());
+}
+
+fn writeFlvClip(
+    input: &[u8],
+    clip: &Clip,
+    videoSamples: &[ClipSample],
+    audioSamples: &[ClipSample],
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 4);
+
+    // "F L V version" + type flags + DataOffset
+    out.extend_from_slice(&[0x46, 0x4C, 0x56, 0x01]);
+    let flags = (if audioSamples.is_empty() { 0 } else { 4 })
+        | (if videoSamples.is_empty() { 0 } else { 1 });
+    out.push(flags);
+    out.extend_from_slice(&[0x00, 0x00, 0x00, 0x09]);
+    out.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]); // PreviousTagSize0
+
+    // The onMetaData duration / keyframes of the source no longer match, leave it out
+    for configTag in [&clip.videoConfigTag, &clip.audioConfigTag].iter() {
+        if let Some(tag) = configTag {
+            writeFlvTag(&mut out, tag, 0);
+        }
+    }
+
+    let base = match (videoSamples.first(), audioSamples.first()) {
+        (Some(video), Some(audio)) => video.dts.min(audio.dts),
+        (Some(video), None) => video.dts,
+        (None, Some(audio)) => audio.dts,
+        (None, None) => 0,
+    };
+
+    // keep the original interleaving
+    let mut samples: Vec<&ClipSample> = videoSamples.iter().chain(audioSamples.iter()).collect();
+    samples.sort_by_key(|sample| sample.tag.position);
+    for sample in samples {
+        writeFlvTag(&mut out, sample.tag, (sample.dts - base) as u32);
+    }
+
+    out
+}
+
+pub(super) fn videoMeta(configTag: &FlvTag, duration: u32) -> Result<Meta, JsValue> {
+    // FrameType + CodecID + AVCPacketType + CompositionTime, then at least
+    // configurationVersion, AVCProfileIndication, profile_compatibility, AVCLevelIndication
+    if configTag.data.len() < 5 + 4 {
+        return Err(JsValue::from(
+            "ClipExtractor: Truncated AVCDecoderConfigurationRecord",
+        ));
+    }
+    let avcc = &configTag.data[5..];
+    let parameterSets = parseAVCDecoderConfigurationRecord(avcc)
+        .ok_or_else(|| JsValue::from("ClipExtractor: Invalid AVCDecoderConfigurationRecord"))?;
+    let sps = parameterSets.nalus.first().ok_or_else(|| {
+        JsValue::from("ClipExtractor: Invalid AVCDecoderConfigurationRecord: No SPS")
+    })?;
+    let config = parseSPS(sps)?;
+
+    Ok(Meta {
+        id: 1,
+        _type: "video".into(),
+        codec: format!("avc1.{:02x}{:02x}{:02x}", avcc[1], avcc[2], avcc[3]),
+        timescale: 1000,
+        duration: duration as u64,
+        codecWidth: config.codec_size.width,
+        codecHeight: config.codec_size.height,
+        presentWidth: config.present_size.width,
+        presentHeight: config.present_size.height,
+        sarNum: config.sar_ratio.width,
+        sarDen: config.sar_ratio.height,
+        color: config.color,
+        hdr: HdrInfo::default(),
+        rotation: 0,
+        mirror: false,
+        channelCount: 0,
+        audioSampleRate: 0,
+        config: vec![],
+        avcc: Vec::from(avcc),
+        editList: vec![],
+        encryption: None,
+    })
+}
+
+pub(super) fn audioMeta(
+    soundSpec: u8,
+    configTag: Option<&FlvTag>,
+    duration: u32,
+) -> Result<Meta, JsValue> {
+    let mut meta = Meta {
+        id: 2,
+        _type: "audio".into(),
+        codec: "mp3".into(),
+        timescale: 1000,
+        duration: duration as u64,
+        codecWidth: 0,
+        codecHeight: 0,
+        presentWidth: 0,
+        presentHeight: 0,
+        sarNum: 0,
+        sarDen: 0,
+        color: None,
+        hdr: HdrInfo::default(),
+        rotation: 0,
+        mirror: false,
+        channelCount: if soundSpec & 1 == 0 { 1 } else { 2 },
+        audioSampleRate: flvSoundRateTable[((soundSpec & 0b00001100) >> 2) as usize],
+        config: vec![],
+        avcc: vec![],
+        editList: vec![],
+        encryption: None,
+    };
+
+    if (soundSpec >> 4) == 10 {
+        let configTag = configTag
+            .ok_or_else(|| JsValue::from("ClipExtractor: AAC without AudioSpecificConfig"))?;
+        let config = Vec::from(&configTag.data[2..]);
+        let adtsConfig = adts::parseAdtsConfig(&config)
+            .ok_or_else(|| JsValue::from("ClipExtractor: Invalid AudioSpecificConfig"))?;
+        meta.codec = format!("mp4a.40.{}", config[0] >> 3);
+        meta.channelCount = adtsConfig.channelConfig as u32;
+        meta.audioSampleRate = mpegSamplingRates[adtsConfig.samplingIndex as usize];
+        meta.config = config;
+    }
+
+    Ok(meta)
+}
+
+pub(super) fn mp4Fragment(
+    id: i32,
+    sequenceNumber: i32,
+    samples: Vec<Mp4Sample>,
+    mdatData: Vec<u8>,
+) -> Vec<u8> {
+    let baseMediaDecodeTime = samples[0].dts as u64;
+    let track = VideoTrack {
+        _type: if id == 1 { "video" } else { "audio" },
+        id,
+        sequenceNumber,
+        length: mdatData.len() as i32,
+        samples,
+    };
+    let mut fragment = mp4_generator::moof(&track, baseMediaDecodeTime);
+    fragment.extend(mp4_generator::mdat(&mdatData));
+    fragment
+}
+
+// Sample durations from the dts delta, the last one repeats the previous duration
+fn sampleDurations(samples: &[ClipSample], fallback: i64) -> Vec<i64> {
+    let mut durations: Vec<i64> = samples
+        .windows(2)
+        .map(|pair| pair[1].dts - pair[0].dts)
+        .collect();
+    durations.push(durations.last().copied().unwrap_or(fallback));
+    durations
+}
+
+// Edit list starting the track at mediaStart: the leading frames are trimmed, or when the first
+// frame is presented after start, the gap is held with an empty edit
+fn editList(firstPts: i64, mediaStart: i64, clipDuration: u32) -> Vec<EditListEntry> {
+    if firstPts > mediaStart {
+        let gap = (firstPts - mediaStart) as u32;
+        vec![
+            EditListEntry {
+                segmentDuration: gap,
+                mediaTime: -1,
+            },
+            EditListEntry {
+                segmentDuration: clipDuration.saturating_sub(gap),
+                mediaTime: firstPts as i32,
+            },
+        ]
+    } else {
+        vec![EditListEntry {
+            segmentDuration: clipDuration,
+            mediaTime: mediaStart as i32,
+        }]
+    }
+}
+
+fn writeMp4Clip(
+    clip: &Clip,
+    videoSamples: &[ClipSample],
+    audioSamples: &[ClipSample],
+    startMs: i64,
+    endMs: i64,
+) -> Result<Vec<u8>, JsValue> {
+    // Both media timelines share the same origin, so that a single start offset applies to both
+    let base = match (videoSamples.first(), audioSamples.first()) {
+        (Some(video), Some(audio)) => video.dts.min(audio.dts),
+        (Some(video), None) => video.dts,
+        (None, Some(audio)) => audio.dts,
+        (None, None) => 0,
+    };
+    let mediaStart = (startMs - base).max(0);
+    let clipDuration = (endMs - startMs) as u32;
+
+    let mut metas = vec![];
+    let mut fragments = vec![];
+
+    if !videoSamples.is_empty() {
+        let configTag = clip.videoConfigTag.as_ref().ok_or_else(|| {
+            JsValue::from("ClipExtractor: AVC without AVCDecoderConfigurationRecord")
+        })?;
+        let mut meta = videoMeta(configTag, clipDuration)?;
+        // the keyframe is presented at dts + cts, after start with B-frames
+        let firstVideoPts = videoSamples[0].dts - base + videoSamples[0].cts;
+        meta.editList = editList(firstVideoPts, mediaStart, clipDuration);
+        metas.push(meta);
+
+        let durations = sampleDurations(videoSamples, 40);
+        let mut mdatData = vec![];
+        let mut mp4Samples = vec![];
+        for (sample, duration) in videoSamples.iter().zip(durations) {
+            // FrameType + CodecID + AVCPacketType + CompositionTime
+            let payload = &sample.tag.data[5..];
+            mdatData.extend_from_slice(payload);
+            mp4Samples.push(Mp4Sample {
+                dts: sample.dts - base,
+                pts: sample.dts - base + sample.cts,
+                cts: sample.cts,
+                duration,
+                originalDts: sample.dts,
+                size: payload.len(),
+                isKeyframe: sample.isKeyframe,
+                unit: vec![],
+                units: vec![],
+                flags: Flag {
+                    isLeading: 0,
+                    dependsOn: if sample.isKeyframe { 2 } else { 1 },
+                    isDependedOn: if sample.isKeyframe { 1 } else { 0 },
+                    hasRedundancy: 0,
+                    isNonSync: if sample.isKeyframe { 0 } else { 1 },
+                },
+            });
+        }
+        fragments.push(mp4Fragment(1, 1, mp4Samples, mdatData));
+    }
+
+    if !audioSamples.is_empty() {
+        let soundSpec = audioSamples[0].tag.data[0];
+        let mut meta = audioMeta(soundSpec, clip.audioConfigTag.as_ref(), clipDuration)?;
+        let firstAudioDts = audioSamples[0].dts - base;
+        meta.editList = editList(firstAudioDts, mediaStart, clipDuration);
+
+        // AAC: SoundSpec + AACPacketType, MP3: SoundSpec
+        let headerSize = if (soundSpec >> 4) == 10 { 2 } else { 1 };
+        let durations = sampleDurations(audioSamples, 23);
+        let mut mdatData = vec![];
+        let mut mp4Samples = vec![];
+        for (sample, duration) in audioSamples.iter().zip(durations) {
+            let payload = &sample.tag.data[headerSize..];
+            mdatData.extend_from_slice(payload);
+            mp4Samples.push(Mp4Sample {
+                dts: sample.dts - base,
+                pts: sample.dts - base,
+                cts: 0,
+                duration,
+                originalDts: sample.dts,
+                size: payload.len(),
+                isKeyframe: true,
+                unit: vec![],
+                units: vec![],
+                flags: Flag {
+                    isLeading: 0,
+                    dependsOn: 1,
+                    isDependedOn: 0,
+                    hasRedundancy: 0,
+                    isNonSync: 0,
+                },
+            });
+        }
+        metas.push(meta);
+        fragments.push(mp4Fragment(2, 1, mp4Samples, mdatData));
+    }
+
+    let mut out = mp4_generator::generateInitSegmentForTracks(&metas);
+    for fragment in fragments {
+        out.extend(fragment);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn flvFile(tags: &[(u8, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut input = vec![0x46, 0x4C, 0x56, 0x01, 0x05, 0x00, 0x00, 0x00, 0x09];
+        input.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]); // PreviousTagSize0
+        for (tagType, timestamp, data) in tags {
+            let tag = FlvTag {
+                tagType: *tagType,
+                timestamp: *timestamp,
+                position: 0,
+                data,
+            };
+            writeFlvTag(&mut input, &tag, *timestamp);
+        }
+        input
+    }
+
+    fn avcFrame(isKeyframe: bool, cts: i32) -> Vec<u8> {
+        let frameType = if isKeyframe { 0x17 } else { 0x27 };
+        let cts = cts.to_be_bytes();
+        vec![
+            frameType, 0x01, cts[1], cts[2], cts[3], 0x00, 0x00, 0x00, 0x01, 0x65,
+        ]
+    }
+
+    fn mp3Frame() -> Vec<u8> {
+        vec![0x2F, 0xFF, 0xFB]
+    }
+
+    fn select(input: &[u8], startMs: i64, endMs: i64) -> (Vec<i64>, Vec<i64>) {
+        let clip = collectTags(input, 13, startMs, endMs).unwrap();
+        let (videoSamples, audioSamples) = selectSamples(&clip, startMs, endMs);
+        (
+            videoSamples.iter().map(|sample| sample.dts).collect(),
+            audioSamples.iter().map(|sample| sample.dts).collect(),
+        )
+    }
+
+    #[wasm_bindgen_test]
+    fn video_starts_at_the_keyframe_before_start() {
+        let mut tags = vec![];
+        for i in 0..6 {
+            tags.push((9, i * 40, avcFrame(i % 2 == 0, 0)));
+            tags.push((8, i * 40, mp3Frame()));
+            tags.push((8, i * 40 + 20, mp3Frame()));
+        }
+        let (video, audio) = select(&flvFile(&tags), 100, 170);
+        assert_eq!(video, vec![80, 120, 160]);
+        // cut at the frame whose middle is nearest to each bound
+        assert_eq!(audio, vec![100, 120, 140]);
+    }
+
+    #[wasm_bindgen_test]
+    fn empty_and_short_tags_are_skipped() {
+        let tags = vec![
+            (9, 0, avcFrame(true, 0)),
+            (8, 0, vec![]),
+            (9, 40, vec![]),
+            (9, 40, vec![0x27, 0x01]),
+            (8, 40, vec![0xAF]),
+            (8, 40, mp3Frame()),
+            (9, 80, avcFrame(false, 0)),
+        ];
+        let (video, audio) = select(&flvFile(&tags), 0, 100);
+        assert_eq!(video, vec![0, 80]);
+        assert_eq!(audio, vec![40]);
+    }
+
+    #[wasm_bindgen_test]
+    fn mixed_sound_formats_keep_the_first() {
+        let tags = vec![
+            (8, 0, mp3Frame()),
+            (8, 20, vec![0xAF, 0x01]),
+            (8, 40, mp3Frame()),
+        ];
+        let (_, audio) = select(&flvFile(&tags), 0, 100);
+        assert_eq!(audio, vec![0, 40]);
+    }
+
+    #[wasm_bindgen_test]
+    fn video_starts_at_the_first_keyframe_after_start() {
+        let mut tags = vec![];
+        for i in 0..4 {
+            tags.push((8, i * 40, mp3Frame()));
+            tags.push((9, i * 40 + 33, avcFrame(i == 0, 0)));
+        }
+        let (video, audio) = select(&flvFile(&tags), 0, 150);
+        assert_eq!(video, vec![33, 73, 113]);
+        assert_eq!(audio, vec![0, 40, 80, 120]);
+
+        // the 33ms before the keyframe are held with an empty edit
+        let entries: Vec<(u32, i32)> = editList(33, 0, 150)
+            .iter()
+            .map(|entry| (entry.segmentDuration, entry.mediaTime))
+            .collect();
+        assert_eq!(entries, vec![(33, -1), (117, 33)]);
+    }
+
+    #[wasm_bindgen_test]
+    fn edit_list_trims_to_start() {
+        let entries = editList(0, 60, 1000);
+        let entries: Vec<(u32, i32)> = entries
+            .iter()
+            .map(|entry| (entry.segmentDuration, entry.mediaTime))
+            .collect();
+        assert_eq!(entries, vec![(1000, 60)]);
+    }
+
+    #[wasm_bindgen_test]
+    fn edit_list_holds_a_late_first_frame() {
+        // keyframe at start, presented 80ms later because of B-frames
+        let entries = editList(80, 0, 1000);
+        let entries: Vec<(u32, i32)> = entries
+            .iter()
+            .map(|entry| (entry.segmentDuration, entry.mediaTime))
+            .collect();
+        assert_eq!(entries, vec![(80, -1), (920, 80)]);
+    }
+
+    #[wasm_bindgen_test]
+    fn truncated_sequence_header() {
+        let data = [0x17, 0x00, 0x00, 0x00, 0x00, 0x01];
+        let tag = FlvTag {
+            tagType: 9,
+            timestamp: 0,
+            position: 0,
+            data: &data,
+        };
+        assert!(videoMeta(&tag, 0).is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use super::aac_silent::silentFrame;
+use super::clip_extractor::{audioMeta, mp4Fragment, videoMeta, writeFlvTag};
+use super::mp4_generator;
+use super::mp4_muxer::{Flag, Mp4Sample};
+use crate::demux::aac_config;
+use crate::demux::flv_demuxer::FLVDemuxer;
+use crate::demux::flv_tags::{isSequenceHeader, nextTagOffset, readTag, FlvTag};
+use crate::utils::logger::Log;
+use wasm_bindgen::prelude::*;
+
+// Reference durations used until the stream tells us better, in milliseconds
+const DEFAULT_VIDEO_FRAME_DURATION: i64 = 40;
+const DEFAULT_AUDIO_FRAME_DURATION: i64 = 23;
+
+/*
+ * Joins several FLV files into one continuous fMP4 or FLV stream.
+ *
+ * Every input is rebased right after the end of the previous one, so DTS keeps increasing
+ * across files. Init segments (or FLV sequence headers) are only written again when the
+ * AVCDecoderConfigurationRecord / AudioSpecificConfig really changed, and audio that starts
+ * later than the rebased timeline is padded with silent AAC frames.
+ */
+#[wasm_bindgen]
+pub struct Concatenator {
+    TAG: &'static str,
+    _container: String,
+
+    _videoConfig: Option<Vec<u8>>,
+    _audioConfig: Option<Vec<u8>>,
+
+    // rebased timeline, in milliseconds
+    _videoNextDts: Option<i64>,
+    _audioNextDts: Option<i64>,
+    _videoFrameDuration: i64,
+    _audioFrameDuration: i64,
+
+    _sequenceNumber: i32,
+    _headerWritten: bool,
+}
+
+struct PendingSample {
+    dts: i64,
+    cts: i64,
+    isKeyframe: bool,
+    payload: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Concatenator {
+    // container: "mp4" (fragmented) or "flv"
+    #[wasm_bindgen(constructor)]
+    pub fn new(container: &str) -> Concatenator {
+        Concatenator {
+            TAG: "Concatenator",
+            _container: container.into(),
+
+            _videoConfig: None,
+            _audioConfig: None,
+
+            _videoNextDts: None,
+            _audioNextDts: None,
+            _videoFrameDuration: DEFAULT_VIDEO_FRAME_DURATION,
+            _audioFrameDuration: DEFAULT_AUDIO_FRAME_DURATION,
+
+            _sequenceNumber: 0,
+            _headerWritten: false,
+        }
+    }
+
+    // Append the next FLV file, returns the bytes to add to the output
+    pub fn append(&mut self, input: &[u8]) -> Result<Vec<u8>, JsValue> {
+        let probeData = match FLVDemuxer::probe(input) {
+            Some(probeData) => probeData,
+            None => return Err(JsValue::from("Concatenator: Invalid FLV header")),
+        };
+
+        let mut tags = vec![];
+        let mut offset = probeData.dataOffset + 4; // skip PreviousTagSize0
+        while let Some(tag) = readTag(input, offset) {
+            offset = nextTagOffset(&tag);
+            if tag.tagType == 8 || tag.tagType == 9 {
+                tags.push(tag);
+            }
+        }
+
+        let fileFirstDts = tags
+            .iter()
+            .filter(|tag| !isSequenceHeader(tag))
+            .map(|tag| tag.timestamp as i64)
+            .min();
+        let fileFirstDts = match fileFirstDts {
+            Some(dts) => dts,
+            None => {
+                Log::w(self.TAG, "Input without any audio / video frame, skipped");
+                return Ok(vec![]);
+            }
+        };
+        // continue right after whichever track ended last
+        let timelineOffset = match (self._videoNextDts, self._audioNextDts) {
+            (Some(video), Some(audio)) => video.max(audio),
+            (Some(video), None) => video,
+            (None, Some(audio)) => audio,
+            (None, None) => 0,
+        };
+        let rebase = |tag: &FlvTag| tag.timestamp as i64 - fileFirstDts + timelineOffset;
+
+        if self._container == "flv" {
+            return Ok(self._appendFlv(&tags, rebase));
+        }
+        self._appendMp4(&tags, rebase)
+    }
+}
+
+impl Concatenator {
+    // Returns true when the sequence header differs from the one in use
+    fn _updateConfig(&mut self, tag: &FlvTag) -> bool {
+        // FrameType + CodecID + AVCPacketType + CompositionTime, SoundSpec + AACPacketType
+        let headerSize = if tag.tagType == 9 { 5 } else { 2 };
+        if tag.data.len() <= headerSize {
+            Log::w(self.TAG, "Empty decoder configuration, ignored");
+            return false;
+        }
+        let config = &tag.data[headerSize..];
+        if tag.tagType == 9 {
+            if self._videoConfig.as_deref() == Some(config) {
+                return false;
+            }
+            self._videoConfig = Some(Vec::from(config));
+        } else {
+            if self._audioConfig.as_deref() == Some(config) {
+                return false;
+            }
+            self._audioConfig = Some(Vec::from(config));
+        }
+        Log::v(
+            self.TAG,
+            "Codec parameters changed, emit new decoder configuration",
+        );
+        true
+    }
+
+    // Silent AAC frames covering [from, to), empty when the config is not supported
+    fn _silentFrames(&self, from: i64, to: i64) -> Vec<PendingSample> {
+        let config = self._audioConfig.as_deref().unwrap_or_default();
+        let silent = aac_config::parseAudioSpecificConfig(config)
+            .ok()
+            .and_then(|config| Some((silentFrame(&config)?, config)));
+        let (silentUnit, config) = match silent {
+            Some(silent) => silent,
+            None => {
+                Log::w(
+                    self.TAG,
+                    "No silent frame for this AudioSpecificConfig, audio gap kept",
+                );
+                return vec![];
+            }
+        };
+
+        // e.g. 1024 samples at 44.1kHz: 23.22ms, stepping by whole milliseconds would drift
+        let frameDuration = config.frameLength as f64 * 1000.0 / config.samplingFrequency as f64;
+        let frameCount = ((to - from) as f64 / frameDuration) as i64;
+        Log::v(
+            self.TAG,
+            &format!("Pad {} silent audio frames from dts {}", frameCount, from),
+        );
+        (0..frameCount)
+            .map(|i| PendingSample {
+                dts: from + (i as f64 * frameDuration).round() as i64,
+                cts: 0,
+                isKeyframe: true,
+                payload: silentUnit.clone(),
+            })
+            .collect()
+    }
+
+    // Rebased audio / video frames of one input, with silence padded in front of late audio
+    fn _rebaseSamples(
+        &mut self,
+        tags: &[FlvTag],
+        rebase: impl Fn(&FlvTag) -> i64,
+    ) -> (Vec<PendingSample>, Vec<PendingSample>) {
+        let mut videoSamples: Vec<PendingSample> = vec![];
+        let mut audioSamples: Vec<PendingSample> = vec![];
+        // where this input begins on the rebased timeline
+        let firstDts = tags
+            .iter()
+            .filter(|tag| !isSequenceHeader(tag))
+            .map(|tag| rebase(tag))
+            .min()
+            .unwrap_or(0);
+
+        for tag in tags {
+            if isSequenceHeader(tag) {
+                continue;
+            }
+            let dts = rebase(tag);
+
+            if tag.tagType == 9 {
+                if tag.data.len() < 5 || tag.data[1] != 1 {
+                    continue;
+                }
+                let cts = (((tag.data[2] as i32) << 24
+                    | (tag.data[3] as i32) << 16
+                    | (tag.data[4] as i32) << 8)
+                    >> 8) as i64; // SI24
+                if let Some(last) = videoSamples.last() {
+                    if dts > last.dts {
+                        self._videoFrameDuration = dts - last.dts;
+                    }
+                }
+                videoSamples.push(PendingSample {
+                    dts,
+                    cts,
+                    isKeyframe: (tag.data[0] >> 4) == 1,
+                    payload: Vec::from(&tag.data[5..]),
+                });
+            } else {
+                if tag.data.is_empty() {
+                    continue;
+                }
+                let isAAC = (tag.data[0] >> 4) == 10;
+                if isAAC && (tag.data.len() < 2 || tag.data[1] != 1) {
+                    continue;
+                }
+                let audioNextDts = match audioSamples.last() {
+                    Some(last) => {
+                        if dts > last.dts {
+                            self._audioFrameDuration = dts - last.dts;
+                        }
+                        last.dts + self._audioFrameDuration
+                    }
+                    None => self._audioNextDts.unwrap_or(firstDts),
+                };
+
+                if dts < audioNextDts - self._audioFrameDuration / 2 {
+                    Log::w(
+                        self.TAG,
+                        &format!("Dropping 1 audio frame overlapping at dts {}", dts),
+                    );
+                    continue;
+                }
+                if isAAC && dts - audioNextDts >= self._audioFrameDuration {
+                    audioSamples.extend(self._silentFrames(audioNextDts, dts));
+                }
+                audioSamples.push(PendingSample {
+                    dts,
+                    cts: 0,
+                    isKeyframe: true,
+                    payload: Vec::from(&tag.data[if isAAC { 2 } else { 1 }..]),
+                });
+            }
+        }
+
+        if let Some(last) = videoSamples.last() {
+            self._videoNextDts = Some(last.dts + self._videoFrameDuration);
+        }
+        if let Some(last) = audioSamples.last() {
+            self._audioNextDts = Some(last.dts + self._audioFrameDuration);
+        }
+
+        (videoSamples, audioSamples)
+    }
+
+    fn _appendMp4(
+        &mut self,
+        tags: &[FlvTag],
+        rebase: impl Fn(&FlvTag) -> i64,
+    ) -> Result<Vec<u8>, JsValue> {
+        let mut out = vec![];
+
+        let mut configChanged = false;
+        let mut videoConfigTag = None;
+        let mut audioConfigTag = None;
+        for tag in tags.iter().filter(|tag| isSequenceHeader(tag)) {
+            configChanged |= self._updateConfig(tag);
+            if tag.tagType == 9 {
+                videoConfigTag = Some(tag);
+            } else {
+                audioConfigTag = Some(tag);
+            }
+        }
+        let audioSoundSpec = tags
+            .iter()
+            .find(|tag| tag.tagType == 8 && !tag.data.is_empty())
+            .map(|tag| tag.data[0]);
+
+        if configChanged || !self._headerWritten {
+            let mut metas = vec![];
+            if let Some(tag) = videoConfigTag {
+                metas.push(videoMeta(tag, 0)?);
+            }
+            if let Some(soundSpec) = audioSoundSpec {
+                metas.push(audioMeta(soundSpec, audioConfigTag, 0)?);
+            }
+            out.extend(mp4_generator::generateInitSegmentForTracks(&metas));
+            self._headerWritten = true;
+        }
+
+        let (videoSamples, audioSamples) = self._rebaseSamples(tags, rebase);
+        let frameDurations = [self._videoFrameDuration, self._audioFrameDuration];
+
+        for (id, samples) in [(1, videoSamples), (2, audioSamples)].iter_mut() {
+            if samples.is_empty() {
+                continue;
+            }
+            let fallbackDuration = frameDurations[(*id - 1) as usize];
+            let mut mdatData = vec![];
+            let mut mp4Samples = vec![];
+            for i in 0..samples.len() {
+                let sample = &samples[i];
+                let duration = match samples.get(i + 1) {
+                    Some(next) => next.dts - sample.dts,
+                    None => fallbackDuration,
+                };
+                mdatData.extend_from_slice(&sample.payload);
+                mp4Samples.push(Mp4Sample {
+                    dts: sample.dts,
+                    pts: sample.dts + sample.cts,
+                    cts: sample.cts,
+                    duration,
+                    originalDts: sample.dts,
+                    size: sample.payload.len(),
+                    isKeyframe: sample.isKeyframe,
+                    unit: vec![],
+                    units: vec![],
+                    flags: Flag {
+                        isLeading: 0,
+                        dependsOn: if sample.isKeyframe { 2 } else { 1 },
+                        isDependedOn: if sample.isKeyframe { 1 } else { 0 },
+                        hasRedundancy: 0,
+                        isNonSync: if sample.isKeyframe { 0 } else { 1 },
+                    },
+                });
+            }
+            self._sequenceNumber += 1;
+            out.extend(mp4Fragment(*id, self._sequenceNumber, mp4Samples, mdatData));
+        }
+
+        Ok(out)
+    }
+
+    fn _appendFlv(&mut self, tags: &[FlvTag], rebase: impl Fn(&FlvTag) -> i64) -> Vec<u8> {
+        let mut out = vec![];
+
+        if !self._headerWritten {
+            let hasAudio = tags.iter().any(|tag| tag.tagType == 8);
+            let hasVideo = tags.iter().any(|tag| tag.tagType == 9);
+            // "F L V version" + type flags + DataOffset + PreviousTagSize0
+            out.extend_from_slice(&[0x46, 0x4C, 0x56, 0x01]);
+            out.push((if hasAudio { 4 } else { 0 }) | (if hasVideo { 1 } else { 0 }));
+            out.extend_from_slice(&[0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00]);
+            self._headerWritten = true;
+        }
+
+        let timelineStart = self
+            ._videoNextDts
+            .max(self._audioNextDts)
+            .unwrap_or(0)
+            .max(0) as u32;
+        for tag in tags.iter().filter(|tag| isSequenceHeader(tag)) {
+            if self._updateConfig(tag) {
+                writeFlvTag(&mut out, tag, timelineStart);
+            }
+        }
+
+        let audioSoundSpec = tags
+            .iter()
+            .find(|tag| tag.tagType == 8 && !tag.data.is_empty())
+            .map(|tag| tag.data[0]);
+        let (videoSamples, audioSamples) = self._rebaseSamples(tags, rebase);
+
+        // interleave again by dts, video first on ties
+        let mut samples: Vec<(u8, &PendingSample)> = videoSamples
+            .iter()
+            .map(|sample| (9, sample))
+            .chain(audioSamples.iter().map(|sample| (8, sample)))
+            .collect();
+        samples.sort_by_key(|(tagType, sample)| (sample.dts, *tagType == 8));
+
+        for (tagType, sample) in samples {
+            let mut data = vec![];
+            if tagType == 9 {
+                let cts = sample.cts as i32;
+                data.push(if sample.isKeyframe { 0x17 } else { 0x27 });
+                // AVCPacketType: NALU + CompositionTime
+                data.extend_from_slice(&[0x01, (cts >> 16) as u8, (cts >> 8) as u8, cts as u8]);
+            } else {
+                let soundSpec = audioSoundSpec.unwrap();
+                data.push(soundSpec);
+                if (soundSpec >> 4) == 10 {
+                    data.push(0x01); // AACPacketType: raw
+                }
+            }
+            data.extend_from_slice(&sample.payload);
+
+            let tag = FlvTag {
+                tagType,
+                timestamp: sample.dts.max(0) as u32,
+                position: 0,
+                data: &data,
+            };
+            writeFlvTag(&mut out, &tag, tag.timestamp);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn flvTag(tagType: u8, timestamp: u32, data: &[u8]) -> FlvTag<'_> {
+        FlvTag {
+            tagType,
+            timestamp,
+            position: 0,
+            data,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn truncated_sequence_headers_are_ignored() {
+        let mut concatenator = Concatenator::new("mp4");
+        assert!(!concatenator._updateConfig(&flvTag(9, 0, &[0x17, 0x00, 0x00])));
+        assert!(!concatenator._updateConfig(&flvTag(8, 0, &[0xAF, 0x00])));
+        assert_eq!(concatenator._videoConfig, None);
+        assert_eq!(concatenator._audioConfig, None);
+
+        assert!(concatenator._updateConfig(&flvTag(8, 0, &[0xAF, 0x00, 0x12, 0x10])));
+        assert!(!concatenator._updateConfig(&flvTag(8, 0, &[0xAF, 0x00, 0x12, 0x10])));
+        assert_eq!(concatenator._audioConfig, Some(vec![0x12, 0x10]));
+    }
+
+    #[wasm_bindgen_test]
+    fn empty_audio_tags_are_skipped() {
+        let mut concatenator = Concatenator::new("mp4");
+        let tags = [
+            flvTag(8, 0, &[0x2F, 0xFF, 0xFB]),
+            flvTag(8, 26, &[]),
+            flvTag(8, 26, &[0x2F, 0xFF, 0xFB]),
+        ];
+        let (videoSamples, audioSamples) =
+            concatenator._rebaseSamples(&tags, |tag| tag.timestamp as i64);
+        assert!(videoSamples.is_empty());
+        let dts: Vec<i64> = audioSamples.iter().map(|sample| sample.dts).collect();
+        assert_eq!(dts, vec![0, 26]);
+    }
+
+    #[wasm_bindgen_test]
+    fn silent_frames_follow_the_sampling_frequency() {
+        let mut concatenator = Concatenator::new("mp4");
+        // AAC-LC 48kHz stereo: 21.33ms frames
+        concatenator._audioConfig = Some(vec![0x11, 0x90]);
+        let samples = concatenator._silentFrames(1000, 2000);
+        let dts: Vec<i64> = samples.iter().map(|sample| sample.dts).collect();
+        assert_eq!(dts.len(), 46);
+        assert_eq!(&dts[..4], &[1000, 1021, 1043, 1064]);
+        assert_eq!(dts[45], 1960);
+    }
+
+    #[wasm_bindgen_test]
+    fn no_silent_frames_without_a_config() {
+        let concatenator = Concatenator::new("mp4");
+        assert!(concatenator._silentFrames(0, 1000).is_empty());
+    }
+}
@@ -1,3 +1,14 @@
-mod aac_silent;
-mod mp4_generator;
-pub mod mp4_muxer;
\ No newline at end of file
+pub mod aac_silent;
+pub mod adts;
+pub mod annexb;
+pub mod cenc;
+pub mod clip_extractor;
+pub mod concatenator;
+pub mod mp3_silent;
+pub mod mp4_generator;
+pub mod mp4_muxer;
+pub mod opus_silent;
+mod segment_index;
+mod text_track;
+mod timestamp_sanitizer;
+pub mod webcodecs;
\ No newline at end of file
@@ -0,0 +1,135 @@
+// Silent MPEG-1 / 2 / 2.5 Layer III frames: a header with the stream's version, sampling rate
+// and channel mode at the lowest bitrate, then an all-zero side info and main data, so no
+// granule carries a single spectral value.
+
+const MPEG_VERSION_25: u8 = 0;
+const MPEG_VERSION_1: u8 = 3;
+const LAYER_3: u8 = 1;
+const CHANNEL_MODE_MONO: u8 = 3;
+
+const MPEG_AUDIO_V10_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+const MPEG_AUDIO_V20_SAMPLE_RATES: [u32; 3] = [22050, 24000, 16000];
+const MPEG_AUDIO_V25_SAMPLE_RATES: [u32; 3] = [11025, 12000, 8000];
+
+// bitrate_index 1, in bits per second
+const MPEG_AUDIO_V10_L3_MIN_BITRATE: u32 = 32000;
+const MPEG_AUDIO_V20_L3_MIN_BITRATE: u32 = 8000;
+
+// The fields of the first frame header a silent frame has to match
+#[derive(Copy, Clone)]
+pub struct Mp3FrameHeader {
+    // 0: MPEG 2.5, 2: MPEG 2, 3: MPEG 1
+    pub version: u8,
+    pub samplingIndex: u8,
+    pub channelMode: u8,
+}
+
+impl Mp3FrameHeader {
+    pub fn samplingRate(&self) -> u32 {
+        let rates = match self.version {
+            MPEG_VERSION_1 => &MPEG_AUDIO_V10_SAMPLE_RATES,
+            MPEG_VERSION_25 => &MPEG_AUDIO_V25_SAMPLE_RATES,
+            _ => &MPEG_AUDIO_V20_SAMPLE_RATES,
+        };
+        rates[self.samplingIndex as usize]
+    }
+
+    // PCM samples per Layer III frame
+    pub fn frameLength(&self) -> u32 {
+        if self.version == MPEG_VERSION_1 {
+            1152
+        } else {
+            576
+        }
+    }
+
+    fn sideInfoSize(&self) -> usize {
+        let mono = self.channelMode == CHANNEL_MODE_MONO;
+        match (self.version == MPEG_VERSION_1, mono) {
+            (true, false) => 32,
+            (true, true) => 17,
+            (false, false) => 17,
+            (false, true) => 9,
+        }
+    }
+}
+
+pub fn parseMp3FrameHeader(header: &[u8]) -> Option<Mp3FrameHeader> {
+    if header.len() < 4 || header[0] != 0xFF || (header[1] & 0xE0) != 0xE0 {
+        return None;
+    }
+
+    let version = (header[1] >> 3) & 0x03;
+    let layer = (header[1] >> 1) & 0x03;
+    let samplingIndex = (header[2] >> 2) & 0x03;
+    let channelMode = (header[3] >> 6) & 0x03;
+
+    // version 1 and sampling index 3 are reserved, Layer I / II frames are not generated
+    if version == 1 || layer != LAYER_3 || samplingIndex == 3 {
+        return None;
+    }
+
+    Some(Mp3FrameHeader {
+        version,
+        samplingIndex,
+        channelMode,
+    })
+}
+
+pub fn silentFrame(header: &Mp3FrameHeader) -> Vec<u8> {
+    let bitRate = if header.version == MPEG_VERSION_1 {
+        MPEG_AUDIO_V10_L3_MIN_BITRATE
+    } else {
+        MPEG_AUDIO_V20_L3_MIN_BITRATE
+    };
+    // frame size in bytes: frameLength / 8 * bitRate / samplingRate, without padding
+    let frameSize = (header.frameLength() / 8 * bitRate / header.samplingRate()) as usize;
+
+    let mut frame = vec![0u8; frameSize.max(4 + header.sideInfoSize())];
+    // syncword
+    frame[0] = 0xFF;
+    // syncword + version + layer: III + protection_absent: 1
+    frame[1] = 0xE0 | (header.version << 3) | (LAYER_3 << 1) | 0x01;
+    // bitrate_index: 1 + sampling_frequency + padding: 0 + private: 0
+    frame[2] = (1 << 4) | (header.samplingIndex << 2);
+    // channel_mode + mode_extension: 0 + copyright: 0 + original: 0 + emphasis: none
+    frame[3] = header.channelMode << 6;
+    frame
+}
+
+// A silent frame for the first frame header of the stream, None for Layer I / II
+pub fn getSilentFrame(frameHeader: &[u8]) -> Option<Vec<u8>> {
+    parseMp3FrameHeader(frameHeader).map(|header| silentFrame(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn mpeg1_layer3() {
+        // 44.1kHz 128kbps joint stereo: 32kbps, same channel mode, no padding
+        let frame = getSilentFrame(&[0xFF, 0xFB, 0x90, 0x64]).unwrap();
+        assert_eq!(&frame[..4], &[0xFF, 0xFB, 0x10, 0x40]);
+        assert_eq!(frame.len(), 104);
+        assert!(frame[4..].iter().all(|&byte| byte == 0));
+    }
+
+    #[wasm_bindgen_test]
+    fn mpeg2_and_mpeg2_5_layer3() {
+        // MPEG-2 24kHz mono: 8kbps
+        let frame = getSilentFrame(&[0xFF, 0xF3, 0x84, 0xC4]).unwrap();
+        assert_eq!(&frame[..4], &[0xFF, 0xF3, 0x14, 0xC0]);
+        assert_eq!(frame.len(), 24);
+        // MPEG-2.5 8kHz stereo
+        let frame = getSilentFrame(&[0xFF, 0xE3, 0x08, 0x00]).unwrap();
+        assert_eq!(frame.len(), 72);
+    }
+
+    #[wasm_bindgen_test]
+    fn other_layers() {
+        // Layer II
+        assert!(getSilentFrame(&[0xFF, 0xFD, 0x90, 0x64]).is_none());
+    }
+}
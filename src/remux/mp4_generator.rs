@@ -17,7 +17,11 @@
  * limitations under the License.
  */
 
+use super::cenc::SampleEncryption;
+use crate::config::{EncryptionConfig, EncryptionScheme, PsshData};
 use crate::demux::flv_demuxer::{TrackMetaData, VideoTrack};
+use crate::demux::hdr::{ContentLightLevel, HdrInfo, MasteringDisplay};
+use crate::demux::sps_parser::ColorInfo;
 
 mod constants {
     pub const FTYP: [u8] = [
@@ -63,6 +67,15 @@ mod constants {
         0x6E, 0x64, 0x6C, 0x65, 0x72, 0x00, // name: SoundHandler
     ];
 
+    pub const HDLR_TEXT: [u8] = [
+        0x00, 0x00, 0x00, 0x00, // version(0) + flags
+        0x00, 0x00, 0x00, 0x00, // pre_defined
+        0x74, 0x65, 0x78, 0x74, // handler_type: "text"
+        0x00, 0x00, 0x00, 0x00, // reserved: 3 * 4 bytes
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x54, 0x65, 0x78, 0x74, 0x48, 0x61, 0x6E,
+        0x64, 0x6C, 0x65, 0x72, 0x00, // name: TextHandler
+    ];
+
     pub const DREF: [u8] = [
         0x00, 0x00, 0x00, 0x00, // version(0) + flags
         0x00, 0x00, 0x00, 0x01, // entry_count
@@ -77,6 +90,11 @@ mod constants {
         0x00, 0x00, 0x00, 0x00, // balance(2) + reserved(2)
     ];
 
+    // null media header, for text tracks
+    pub const NMHD: [u8] = [
+        0x00, 0x00, 0x00, 0x00, // version(0) + flags
+    ];
+
     // video media header
     pub const VMHD: [u8] = [
         0x00, 0x00, 0x00, 0x01, // version(0) + flags
@@ -86,9 +104,95 @@ mod constants {
     ];
 }
 
-struct Meta {
-    id: u32,
-    _type: String,
+pub struct Meta {
+    pub id: u32,
+    pub _type: String,
+    pub codec: String,
+    pub timescale: u32,
+    pub duration: u64,
+    pub codecWidth: u32,
+    pub codecHeight: u32,
+    pub presentWidth: u32,
+    pub presentHeight: u32,
+    // pixel aspect ratio, written as pasp
+    pub sarNum: u32,
+    pub sarDen: u32,
+    // written as colr (nclx) when set
+    pub color: Option<ColorInfo>,
+    // written as mdcv / clli
+    pub hdr: HdrInfo,
+    // clockwise degrees (0 / 90 / 180 / 270) and horizontal flip, written as the tkhd matrix
+    pub rotation: u32,
+    pub mirror: bool,
+    pub channelCount: u32,
+    pub audioSampleRate: u32,
+    // AudioSpecificConfig
+    pub config: Vec<u8>,
+    pub avcc: Vec<u8>,
+    // written as edts / elst when not empty
+    pub editList: Vec<EditListEntry>,
+    // protected sample entry (encv / enca) when set
+    pub encryption: Option<EncryptionConfig>,
+}
+
+impl From<&TrackMetaData> for Meta {
+    fn from(metadata: &TrackMetaData) -> Meta {
+        match metadata {
+            TrackMetaData::Video(meta) => Meta {
+                id: meta.id,
+                _type: "video".into(),
+                codec: meta.codec.clone(),
+                timescale: meta.timescale,
+                duration: meta.duration as u64,
+                codecWidth: meta.codecWidth,
+                codecHeight: meta.codecHeight,
+                presentWidth: meta.presentWidth,
+                presentHeight: meta.presentHeight,
+                sarNum: meta.sarRatio.width,
+                sarDen: meta.sarRatio.height,
+                color: meta.color,
+                hdr: meta.hdr,
+                rotation: meta.rotation,
+                mirror: meta.mirror,
+                channelCount: 0,
+                audioSampleRate: 0,
+                config: vec![],
+                avcc: meta.avcc.clone(),
+                editList: vec![],
+                encryption: None,
+            },
+            TrackMetaData::Audio(meta) => Meta {
+                id: meta.id as u32,
+                _type: "audio".into(),
+                codec: meta.codec.clone(),
+                timescale: meta.timescale,
+                duration: meta.duration as u64,
+                codecWidth: 0,
+                codecHeight: 0,
+                presentWidth: 0,
+                presentHeight: 0,
+                sarNum: 0,
+                sarDen: 0,
+                color: None,
+                hdr: HdrInfo::default(),
+                rotation: 0,
+                mirror: false,
+                channelCount: meta.channelCount as u32,
+                audioSampleRate: meta.audioSampleRate as u32,
+                config: meta.config.clone(),
+                avcc: vec![],
+                editList: vec![],
+                encryption: None,
+            },
+        }
+    }
+}
+
+pub struct EditListEntry {
+    // in movie timescale
+    pub segmentDuration: u32,
+    // in media timescale, -1 for an empty edit
+    pub mediaTime: i32,
 }
 
 // Generate a box
@@ -117,134 +221,248 @@ fn genBox(boxType: &[u8], datas: &[&[u8]]) -> Vec<u8> {
 }
 
 // emit ftyp & moov
-fn generateInitSegment(meta: Meta) -> Vec<u8> {
+pub fn generateInitSegment(meta: Meta) -> Vec<u8> {
     let ftyp = genBox(b"ftyp", &[constants::FTYP]);
     let moov = moov(meta);
     return ftyp.extend(moov);
 }
 
+// emit ftyp & moov holding all the tracks, for standalone files
+pub fn generateInitSegmentForTracks(metas: &[Meta]) -> Vec<u8> {
+    let timescale = metas
+        .iter()
+        .map(|meta| meta.timescale)
+        .max()
+        .unwrap_or(1000);
+    let duration = metas.iter().map(|meta| meta.duration).max().unwrap_or(0);
+
+    let mut boxes = vec![mvhd(timescale, duration)];
+    if let Some(encryption) = metas.iter().find_map(|meta| meta.encryption.as_ref()) {
+        boxes.extend(
+            encryption
+                .pssh
+                .iter()
+                .map(|pssh| psshBox(pssh, &encryption.kid)),
+        );
+    }
+    for meta in metas {
+        boxes.push(trak(meta));
+    }
+    let trexs: Vec<Vec<u8>> = metas.iter().map(|meta| trex(meta)).collect();
+    let trexs: Vec<&[u8]> = trexs.iter().map(|trex| trex.as_slice()).collect();
+    boxes.push(genBox(b"mvex", &trexs));
+
+    let boxes: Vec<&[u8]> = boxes.iter().map(|b| b.as_slice()).collect();
+    let mut result = genBox(b"ftyp", &[&constants::FTYP]);
+    result.extend(genBox(b"moov", &boxes));
+    return result;
+}
+
 // Movie metadata box
 fn moov(meta: Meta) -> Vec<u8> {
     let mvhd = mvhd(meta.timescale, meta.duration);
     let trak = trak(meta);
     let mvex = mvex(meta);
+    if let Some(encryption) = &meta.encryption {
+        let psshs: Vec<Vec<u8>> = encryption
+            .pssh
+            .iter()
+            .map(|pssh| psshBox(pssh, &encryption.kid))
+            .collect();
+        let mut boxes: Vec<&[u8]> = vec![&mvhd];
+        boxes.extend(psshs.iter().map(|pssh| pssh.as_slice()));
+        boxes.push(&trak);
+        boxes.push(&mvex);
+        return genBox(b"moov", &boxes);
+    }
     return genBox(b"moov", mvhd, trak, mvex);
 }
 
+// Protection system specific header box, version 1 listing the KID
+fn psshBox(pssh: &PsshData, kid: &[u8]) -> Vec<u8> {
+    let dataSize = pssh.data.len();
+    let mut data = vec![
+        0x01, 0x00, 0x00, 0x00, // version(1) + flags
+    ];
+    data.extend_from_slice(&pssh.systemId); // SystemID: 16 bytes
+    data.extend_from_slice(&[
+        0x00, 0x00, 0x00, 0x01, // KID_count
+    ]);
+    data.extend_from_slice(kid); // KID: 16 bytes
+    data.extend_from_slice(&[
+        (dataSize >> 24) & 0xFF, // DataSize
+        (dataSize >> 16) & 0xFF,
+        (dataSize >> 8) & 0xFF,
+        (dataSize) & 0xFF,
+    ]);
+    data.extend_from_slice(&pssh.data);
+    return genBox(b"pssh", &[&data]);
+}
+
+// mvhd / tkhd / mdhd: version 1 with 64-bit times once the duration does not fit in 32 bits
+fn headerVersion(duration: u64) -> u8 {
+    if duration > u32::MAX as u64 {
+        1
+    } else {
+        0
+    }
+}
+
+// creation_time, modification_time or duration: 4 bytes in version 0, 8 bytes in version 1
+fn timeField(version: u8, value: u64) -> Vec<u8> {
+    if version == 1 {
+        value.to_be_bytes().to_vec()
+    } else {
+        (value as u32).to_be_bytes().to_vec()
+    }
+}
+
 // Movie header box
-fn mvhd(timescale: u32, duration: u32) -> Vec<u8> {
+fn mvhd(timescale: u32, duration: u64) -> Vec<u8> {
+    let version = headerVersion(duration);
     return genBox(
         b"mvhd",
-        &[&[
-            0x00,
-            0x00,
-            0x00,
-            0x00, // version(0) + flags
-            0x00,
-            0x00,
-            0x00,
-            0x00, // creation_time
-            0x00,
-            0x00,
-            0x00,
-            0x00,                     // modification_time
-            (timescale >> 24) & 0xFF, // timescale: 4 bytes
-            (timescale >> 16) & 0xFF,
-            (timescale >> 8) & 0xFF,
-            (timescale) & 0xFF,
-            (duration >> 24) & 0xFF, // duration: 4 bytes
-            (duration >> 16) & 0xFF,
-            (duration >> 8) & 0xFF,
-            (duration) & 0xFF,
-            0x00,
-            0x01,
-            0x00,
-            0x00, // Preferred rate: 1.0
-            0x01,
-            0x00,
-            0x00,
-            0x00, // PreferredVolume(1.0, 2bytes) + reserved(2bytes)
-            0x00,
-            0x00,
-            0x00,
-            0x00, // reserved: 4 + 4 bytes
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x01,
-            0x00,
-            0x00, // ----begin composition matrix----
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x01,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x40,
-            0x00,
-            0x00,
-            0x00, // ----end composition matrix----
-            0x00,
-            0x00,
-            0x00,
-            0x00, // ----begin pre_defined 6 * 4 bytes----
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00, // ----end pre_defined 6 * 4 bytes----
-            0xFF,
-            0xFF,
-            0xFF,
-            0xFF, // next_track_ID
-        ]],
+        &[
+            &[
+                version,
+                0x00,
+                0x00,
+                0x00, // version + flags
+            ],
+            &timeField(version, 0),        // creation_time
+            &timeField(version, 0),        // modification_time
+            &timescale.to_be_bytes(),      // timescale: 4 bytes
+            &timeField(version, duration), // duration: 4 / 8 bytes
+            &[
+                0x00,
+                0x01,
+                0x00,
+                0x00, // Preferred rate: 1.0
+                0x01,
+                0x00,
+                0x00,
+                0x00, // PreferredVolume(1.0, 2bytes) + reserved(2bytes)
+                0x00,
+                0x00,
+                0x00,
+                0x00, // reserved: 4 + 4 bytes
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x01,
+                0x00,
+                0x00, // ----begin composition matrix----
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x01,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x40,
+                0x00,
+                0x00,
+                0x00, // ----end composition matrix----
+                0x00,
+                0x00,
+                0x00,
+                0x00, // ----begin pre_defined 6 * 4 bytes----
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00, // ----end pre_defined 6 * 4 bytes----
+                0xFF,
+                0xFF,
+                0xFF,
+                0xFF, // next_track_ID
+            ],
+        ],
     );
 }
 
 // Track box
 fn trak(meta: Meta) -> Vec<u8> {
+    if !meta.editList.is_empty() {
+        return genBox(b"trak", &[&tkhd(meta), &edts(&meta.editList), &mdia(meta)]);
+    }
     return genBox(b"trak", &[tkhd(meta), mdia(meta)]);
 }
 
+// Edit box
+fn edts(editList: &[EditListEntry]) -> Vec<u8> {
+    let entryCount = editList.len();
+    let mut data = vec![
+        0x00,
+        0x00,
+        0x00,
+        0x00,                      // version(0) + flags
+        (entryCount >> 24) & 0xFF, // entry_count
+        (entryCount >> 16) & 0xFF,
+        (entryCount >> 8) & 0xFF,
+        (entryCount) & 0xFF,
+    ];
+
+    for entry in editList {
+        let segmentDuration = entry.segmentDuration;
+        let mediaTime = entry.mediaTime;
+        data.extend_from_slice(&[
+            (segmentDuration >> 24) & 0xFF, // segment_duration
+            (segmentDuration >> 16) & 0xFF,
+            (segmentDuration >> 8) & 0xFF,
+            (segmentDuration) & 0xFF,
+            (mediaTime >> 24) & 0xFF, // media_time
+            (mediaTime >> 16) & 0xFF,
+            (mediaTime >> 8) & 0xFF,
+            (mediaTime) & 0xFF,
+            0x00,
+            0x01,
+            0x00,
+            0x00, // media_rate_integer(1) + media_rate_fraction(0)
+        ]);
+    }
+
+    return genBox(b"edts", &[&genBox(b"elst", &[&data])]);
+}
+
 // Track header box
 fn tkhd(meta: Meta) -> Vec<u8> {
     let trackId = meta.id;
@@ -252,95 +470,92 @@ fn tkhd(meta: Meta) -> Vec<u8> {
     let width = meta.presentWidth;
     let height = meta.presentHeight;
 
-    return genBox(
-        b"tkhd",
-        &[&[
-            0x00,
-            0x00,
-            0x00,
-            0x07, // version(0) + flags
-            0x00,
-            0x00,
-            0x00,
-            0x00, // creation_time
-            0x00,
-            0x00,
-            0x00,
-            0x00,                   // modification_time
-            (trackId >> 24) & 0xFF, // track_ID: 4 bytes
-            (trackId >> 16) & 0xFF,
-            (trackId >> 8) & 0xFF,
-            (trackId) & 0xFF,
-            0x00,
-            0x00,
-            0x00,
-            0x00,                    // reserved: 4 bytes
-            (duration >> 24) & 0xFF, // duration: 4 bytes
-            (duration >> 16) & 0xFF,
-            (duration >> 8) & 0xFF,
-            (duration) & 0xFF,
-            0x00,
-            0x00,
-            0x00,
-            0x00, // reserved: 2 * 4 bytes
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00, // layer(2bytes) + alternate_group(2bytes)
-            0x00,
-            0x00,
-            0x00,
-            0x00, // volume(2bytes) + reserved(2bytes)
-            0x00,
-            0x01,
-            0x00,
-            0x00, // ----begin composition matrix----
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x01,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x00,
-            0x40,
-            0x00,
-            0x00,
-            0x00,                // ----end composition matrix----
-            (width >> 8) & 0xFF, // width and height
-            (width) & 0xFF,
-            0x00,
-            0x00,
-            (height >> 8) & 0xFF,
-            (height) & 0xFF,
-            0x00,
-            0x00,
-        ]],
-    );
+    let version = headerVersion(duration);
+
+    let mut data = vec![
+        version,
+        0x00,
+        0x00,
+        0x07, // version + flags
+    ];
+    data.extend(timeField(version, 0)); // creation_time
+    data.extend(timeField(version, 0)); // modification_time
+    data.extend_from_slice(&[
+        (trackId >> 24) & 0xFF, // track_ID: 4 bytes
+        (trackId >> 16) & 0xFF,
+        (trackId >> 8) & 0xFF,
+        (trackId) & 0xFF,
+        0x00,
+        0x00,
+        0x00,
+        0x00, // reserved: 4 bytes
+    ]);
+    data.extend(timeField(version, duration)); // duration: 4 / 8 bytes
+    data.extend_from_slice(&[
+        0x00,
+        0x00,
+        0x00,
+        0x00, // reserved: 2 * 4 bytes
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00, // layer(2bytes) + alternate_group(2bytes)
+        0x00,
+        0x00,
+        0x00,
+        0x00, // volume(2bytes) + reserved(2bytes)
+    ]);
+    // composition matrix
+    for value in displayMatrix(meta.rotation, meta.mirror, width, height).iter() {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.extend_from_slice(&[
+        (width >> 8) & 0xFF, // width and height
+        (width) & 0xFF,
+        0x00,
+        0x00,
+        (height >> 8) & 0xFF,
+        (height) & 0xFF,
+        0x00,
+        0x00,
+    ]);
+
+    return genBox(b"tkhd", &[&data]);
+}
+
+// tkhd transformation matrix {a, b, u, c, d, v, x, y, w}: the picture is mirrored horizontally
+// first, then rotated clockwise. a, b, c, d, x, y are 16.16 fixed point, u, v, w 2.30
+fn displayMatrix(rotation: u32, mirror: bool, width: u32, height: u32) -> [i32; 9] {
+    // row vector convention: [x' y' 1] = [x y 1] * {a b 0, c d 0, x y 1}
+    let (w, h) = (width as i32, height as i32);
+    let (mut a, mut b, c, d, mut x, mut y) = match rotation {
+        90 => (0, 1, -1, 0, h, 0),
+        180 => (-1, 0, 0, -1, w, h),
+        270 => (0, -1, 1, 0, 0, w),
+        _ => (1, 0, 0, 1, 0, 0),
+    };
+    if mirror {
+        // {-1 0 0, 0 1 0, w 0 1} applied before the rotation
+        x += w * a;
+        y += w * b;
+        a = -a;
+        b = -b;
+    }
+    [
+        a << 16,
+        b << 16,
+        0,
+        c << 16,
+        d << 16,
+        0,
+        x << 16,
+        y << 16,
+        1 << 30,
+    ]
 }
 
 // Media Box
@@ -350,35 +565,26 @@ fn mdia(meta: Meta) -> Vec<u8> {
 
 // Media header box
 fn mdhd(meta: Meta) -> Vec<u8> {
-    let timescale = meta.timescale;
-    let duration = meta.duration;
+    let version = headerVersion(meta.duration);
     return genBox(
         b"mdhd",
-        [
-            0x00,
-            0x00,
-            0x00,
-            0x00, // version(0) + flags
-            0x00,
-            0x00,
-            0x00,
-            0x00, // creation_time
-            0x00,
-            0x00,
-            0x00,
-            0x00,                     // modification_time
-            (timescale >> 24) & 0xFF, // timescale: 4 bytes
-            (timescale >> 16) & 0xFF,
-            (timescale >> 8) & 0xFF,
-            (timescale) & 0xFF,
-            (duration >> 24) & 0xFF, // duration: 4 bytes
-            (duration >> 16) & 0xFF,
-            (duration >> 8) & 0xFF,
-            (duration) & 0xFF,
-            0x55,
-            0xC4, // language: und (undetermined)
-            0x00,
-            0x00, // pre_defined = 0
+        &[
+            &[
+                version,
+                0x00,
+                0x00,
+                0x00, // version + flags
+            ],
+            &timeField(version, 0),             // creation_time
+            &timeField(version, 0),             // modification_time
+            &meta.timescale.to_be_bytes(),      // timescale: 4 bytes
+            &timeField(version, meta.duration), // duration: 4 / 8 bytes
+            &[
+                0x55,
+                0xC4, // language: und (undetermined)
+                0x00,
+                0x00, // pre_defined = 0
+            ],
         ],
     );
 }
@@ -388,6 +594,8 @@ fn hdlr(meta: Meta) -> Vec<u8> {
     let data;
     if (meta._type == "audio") {
         data = constants::HDLR_AUDIO;
+    } else if meta._type == "text" {
+        data = constants::HDLR_TEXT;
     } else {
         data = constants::HDLR_VIDEO;
     }
@@ -399,6 +607,8 @@ fn minf(meta: Meta) -> Vec<u8> {
     let xmhd;
     if (meta._type == "audio") {
         xmhd = genBox(b"smhd", &[&constants::SMHD]);
+    } else if meta._type == "text" {
+        xmhd = genBox(b"nmhd", &[&constants::NMHD]);
     } else {
         xmhd = genBox(b"vmhd", &[&constants::VMHD]);
     }
@@ -426,6 +636,23 @@ fn stbl(meta: Meta) -> Vec<u8> {
 
 // Sample description box
 fn stsd(meta: Meta) -> Vec<u8> {
+    if let Some(encryption) = meta.encryption.clone() {
+        let isVideo = meta._type == "video";
+        let sampleEntry = if !isVideo {
+            if meta.codec == "mp3" {
+                mp3(meta)
+            } else {
+                mp4a(meta)
+            }
+        } else {
+            avc1(meta)
+        };
+        let entry = protectedSampleEntry(sampleEntry, &encryption, isVideo);
+        return genBox(b"stsd", &[&constants::STSD_PREFIX, &entry]);
+    }
+    if meta._type == "text" {
+        return genBox(b"stsd", &[&constants::STSD_PREFIX, &wvtt()]);
+    }
     if (meta._type == "audio") {
         if (meta.codec == "mp3") {
             return genBox(b"stsd", constants::STSD_PREFIX, mp3(meta));
@@ -637,7 +864,172 @@ fn avc1(meta: Meta) -> Vec<u8> {
         0xFF,
         0xFF, // pre_defined = -1
     ];
-    return genBox(b"avc1", &[&data, &genBox(b"avcC", avcc)]);
+    // hvc1 shares the VisualSampleEntry layout, with the hvcC record in place of avcC
+    let (entryType, configType): (&[u8; 4], &[u8; 4]) = if meta.codec.starts_with("hvc1") {
+        (b"hvc1", b"hvcC")
+    } else {
+        (b"avc1", b"avcC")
+    };
+    let avcC = genBox(configType, avcc);
+    let colr = match meta.color {
+        Some(color) => colr(color),
+        None => vec![],
+    };
+    let pasp = if meta.sarNum > 0 && meta.sarDen > 0 {
+        pasp(meta.sarNum, meta.sarDen)
+    } else {
+        vec![]
+    };
+    let mdcv = match meta.hdr.masteringDisplay {
+        Some(masteringDisplay) => mdcv(masteringDisplay),
+        None => vec![],
+    };
+    let clli = match meta.hdr.contentLightLevel {
+        Some(contentLightLevel) => clli(contentLightLevel),
+        None => vec![],
+    };
+    return genBox(entryType, &[&data, &avcC, &colr, &pasp, &mdcv, &clli]);
+}
+
+// Colour information box, nclx: ITU-T H.273 code points
+fn colr(color: ColorInfo) -> Vec<u8> {
+    let data = [
+        0x6E,
+        0x63,
+        0x6C,
+        0x78,                                         // colour_type: "nclx"
+        ((color.colour_primaries >> 8) & 0xFF) as u8, // colour_primaries
+        (color.colour_primaries & 0xFF) as u8,
+        ((color.transfer_characteristics >> 8) & 0xFF) as u8, // transfer_characteristics
+        (color.transfer_characteristics & 0xFF) as u8,
+        ((color.matrix_coefficients >> 8) & 0xFF) as u8, // matrix_coefficients
+        (color.matrix_coefficients & 0xFF) as u8,
+        if color.full_range { 0x80 } else { 0x00 }, // full_range_flag(1) + reserved(7)
+    ];
+    return genBox(b"colr", &[&data]);
+}
+
+// Pixel aspect ratio box
+fn pasp(hSpacing: u32, vSpacing: u32) -> Vec<u8> {
+    let data = [
+        ((hSpacing >> 24) & 0xFF) as u8, // hSpacing
+        ((hSpacing >> 16) & 0xFF) as u8,
+        ((hSpacing >> 8) & 0xFF) as u8,
+        (hSpacing & 0xFF) as u8,
+        ((vSpacing >> 24) & 0xFF) as u8, // vSpacing
+        ((vSpacing >> 16) & 0xFF) as u8,
+        ((vSpacing >> 8) & 0xFF) as u8,
+        (vSpacing & 0xFF) as u8,
+    ];
+    return genBox(b"pasp", &[&data]);
+}
+
+// Mastering display colour volume box, SMPTE ST 2086
+fn mdcv(masteringDisplay: MasteringDisplay) -> Vec<u8> {
+    let mut data = Vec::with_capacity(24);
+    for &(x, y) in masteringDisplay.displayPrimaries.iter() {
+        data.extend_from_slice(&x.to_be_bytes()); // display_primaries_x
+        data.extend_from_slice(&y.to_be_bytes()); // display_primaries_y
+    }
+    let (whitePointX, whitePointY) = masteringDisplay.whitePoint;
+    data.extend_from_slice(&whitePointX.to_be_bytes()); // white_point_x
+    data.extend_from_slice(&whitePointY.to_be_bytes()); // white_point_y
+    data.extend_from_slice(&masteringDisplay.maxLuminance.to_be_bytes()); // max_display_mastering_luminance
+    data.extend_from_slice(&masteringDisplay.minLuminance.to_be_bytes()); // min_display_mastering_luminance
+    return genBox(b"mdcv", &[&data]);
+}
+
+// Content light level box
+fn clli(contentLightLevel: ContentLightLevel) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4);
+    data.extend_from_slice(&contentLightLevel.maxCLL.to_be_bytes()); // max_content_light_level
+    data.extend_from_slice(&contentLightLevel.maxFALL.to_be_bytes()); // max_pic_average_light_level
+    return genBox(b"clli", &[&data]);
+}
+
+// Turn a sample entry into encv / enca, keeping the original format in sinf / frma
+fn protectedSampleEntry(
+    sampleEntry: Vec<u8>,
+    encryption: &EncryptionConfig,
+    isVideo: bool,
+) -> Vec<u8> {
+    let originalFormat = &sampleEntry[4..8];
+    let frma = genBox(b"frma", &[originalFormat]);
+    let schm = schm(encryption.scheme);
+    let schi = genBox(b"schi", &[&tenc(encryption, isVideo)]);
+    let sinf = genBox(b"sinf", &[&frma, &schm, &schi]);
+
+    let boxType: &[u8] = if isVideo { b"encv" } else { b"enca" };
+    return genBox(boxType, &[&sampleEntry[8..], &sinf]);
+}
+
+// Scheme type box
+fn schm(scheme: EncryptionScheme) -> Vec<u8> {
+    let schemeType: &[u8; 4] = match scheme {
+        EncryptionScheme::Cenc => b"cenc",
+        EncryptionScheme::Cbcs => b"cbcs",
+    };
+    let mut data = vec![
+        0x00, 0x00, 0x00, 0x00, // version(0) + flags
+    ];
+    data.extend_from_slice(schemeType); // scheme_type
+    data.extend_from_slice(&[
+        0x00, 0x01, 0x00, 0x00, // scheme_version: 1.0
+    ]);
+    return genBox(b"schm", &[&data]);
+}
+
+// Track encryption box
+fn tenc(encryption: &EncryptionConfig, isVideo: bool) -> Vec<u8> {
+    let mut data = match encryption.scheme {
+        // per-sample IVs in senc
+        EncryptionScheme::Cenc => vec![
+            0x00, 0x00, 0x00, 0x00, // version(0) + flags
+            0x00, // reserved
+            0x00, // reserved
+            0x01, // default_isProtected
+            0x08, // default_Per_Sample_IV_Size
+        ],
+        // 1:9 pattern on video, whole samples on audio, constant IV
+        EncryptionScheme::Cbcs => vec![
+            0x01,
+            0x00,
+            0x00,
+            0x00,                              // version(1) + flags
+            0x00,                              // reserved
+            if isVideo { 0x19 } else { 0x00 }, // default_crypt_byte_block(4) + default_skip_byte_block(4)
+            0x01,                              // default_isProtected
+            0x00,                              // default_Per_Sample_IV_Size
+        ],
+    };
+    data.extend_from_slice(&encryption.kid); // default_KID: 16 bytes
+    if encryption.scheme == EncryptionScheme::Cbcs {
+        data.push(encryption.iv.len() as u8); // default_constant_IV_size
+        data.extend_from_slice(&encryption.iv); // default_constant_IV
+    }
+    return genBox(b"tenc", &[&data]);
+}
+
+// WebVTT sample entry, ISO/IEC 14496-30
+fn wvtt() -> Vec<u8> {
+    let data = [
+        0x00, 0x00, 0x00, 0x00, // reserved(4)
+        0x00, 0x00, 0x00, 0x01, // reserved(2) + data_reference_index(2)
+    ];
+    // WebVTT file header, without any region or style block
+    let vttC = genBox(b"vttC", &[b"WEBVTT"]);
+    return genBox(b"wvtt", &[&data, &vttC]);
+}
+
+// WebVTT cue sample: the cue payload
+pub fn vttc(text: &str) -> Vec<u8> {
+    let payl = genBox(b"payl", &[text.as_bytes()]);
+    return genBox(b"vttc", &[&payl]);
+}
+
+// WebVTT empty sample, for the time without cue
+pub fn vtte() -> Vec<u8> {
+    return genBox(b"vtte", &[]);
 }
 
 // Movie Extends box
@@ -678,12 +1070,27 @@ fn trex(meta: Meta) -> Vec<u8> {
 }
 
 // Movie fragment box
-fn moof(track: &VideoTrack, baseMediaDecodeTime: u32) -> Vec<u8> {
+pub fn moof(track: &VideoTrack, baseMediaDecodeTime: u64) -> Vec<u8> {
     return genBox(
         b"moof",
         &[
             &mfhd(track.sequenceNumber),
-            &traf(track, baseMediaDecodeTime),
+            &traf(track, baseMediaDecodeTime, &[]),
+        ],
+    );
+}
+
+// Movie fragment box of an encrypted track, carrying the sample auxiliary information
+pub fn encryptedMoof(
+    track: &VideoTrack,
+    baseMediaDecodeTime: u64,
+    sampleEncryptions: &[SampleEncryption],
+) -> Vec<u8> {
+    return genBox(
+        b"moof",
+        &[
+            &mfhd(track.sequenceNumber),
+            &traf(track, baseMediaDecodeTime, sampleEncryptions),
         ],
     );
 }
@@ -703,7 +1110,11 @@ fn mfhd(sequenceNumber: u32) -> Vec<u8> {
 }
 
 // Track fragment box
-fn traf(track: &VideoTrack, baseMediaDecodeTime: u32) -> Vec<u8> {
+fn traf(
+    track: &VideoTrack,
+    baseMediaDecodeTime: u64,
+    sampleEncryptions: &[SampleEncryption],
+) -> Vec<u8> {
     let trackId = track.id;
 
     // Track fragment header box
@@ -720,26 +1131,237 @@ fn traf(track: &VideoTrack, baseMediaDecodeTime: u32) -> Vec<u8> {
             (trackId) & 0xFF,
         ],
     );
-    // Track Fragment Decode Time
-    let tfdt = genBox(
-        b"tfdt",
-        vec![
-            0x00,
-            0x00,
-            0x00,
-            0x00,                               // version(0) & flags
-            (baseMediaDecodeTime >> 24) & 0xFF, // baseMediaDecodeTime: int32
-            (baseMediaDecodeTime >> 16) & 0xFF,
-            (baseMediaDecodeTime >> 8) & 0xFF,
-            (baseMediaDecodeTime) & 0xFF,
-        ],
-    );
+    let tfdt = tfdt(baseMediaDecodeTime);
     let sdtp = sdtp(track);
-    let trun = trun(track, sdtp.byteLength + 16 + 16 + 8 + 16 + 8 + 8);
+
+    // nothing to signal for whole-sample cbcs: constant IV and no subsamples
+    if sampleEncryptions.iter().any(|sample| sample.size() > 0) {
+        let saiz = saiz(sampleEncryptions);
+        let senc = senc(sampleEncryptions);
+        // saio points to the first senc entry, from the start of moof:
+        // moof header + mfhd + traf header + tfhd + tfdt + trun + sdtp + saiz + saio + senc header
+        let trunSize = 8 + 12 + 16 * track.samples.len();
+        let sencOffset =
+            8 + 16 + 8 + 16 + tfdt.len() + trunSize + sdtp.len() + saiz.len() + 20 + 16;
+        let saio = saio(sencOffset);
+        let encryptionBytes = saiz.len() + saio.len() + senc.len();
+        let trun = trun(
+            track,
+            sdtp.byteLength + encryptionBytes + 16 + tfdt.len() + 8 + 16 + 8 + 8,
+        );
+
+        return genBox(b"traf", &[&tfhd, &tfdt, &trun, &sdtp, &saiz, &saio, &senc]);
+    }
+
+    let trun = trun(track, sdtp.byteLength + 16 + tfdt.len() + 8 + 16 + 8 + 8);
 
     return genBox(b"traf", &[&tfhd, &tfdt, &trun, &sdtp]);
 }
 
+// Track fragment decode time box, version 1 with a 64-bit baseMediaDecodeTime once it does not
+// fit in 32 bits
+fn tfdt(baseMediaDecodeTime: u64) -> Vec<u8> {
+    let mut data = vec![];
+    if baseMediaDecodeTime > u32::MAX as u64 {
+        data.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]); // version(1) & flags
+        data.extend_from_slice(&baseMediaDecodeTime.to_be_bytes()); // baseMediaDecodeTime: int64
+    } else {
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]); // version(0) & flags
+        data.extend_from_slice(&(baseMediaDecodeTime as u32).to_be_bytes()); // baseMediaDecodeTime: int32
+    }
+    return genBox(b"tfdt", &[&data]);
+}
+
+// Producer reference time box: the UTC time, as a 64-bit NTP timestamp, at which mediaTime of
+// the reference track was produced. Version 1 with a 64-bit media_time once it does not fit
+// in 32 bits. flags: 0 encoder input, 4 written by this muxer (ISO/IEC 14496-12 8.16.5)
+pub fn prft(referenceTrackId: u32, flags: u8, ntpTimestamp: u64, mediaTime: u64) -> Vec<u8> {
+    let mut data = vec![];
+    let version = if mediaTime > u32::MAX as u64 { 1 } else { 0 };
+    data.extend_from_slice(&[version, 0x00, 0x00, flags]); // version + flags
+    data.extend_from_slice(&referenceTrackId.to_be_bytes()); // reference_track_ID
+    data.extend_from_slice(&ntpTimestamp.to_be_bytes()); // ntp_timestamp
+    if version == 1 {
+        data.extend_from_slice(&mediaTime.to_be_bytes()); // media_time: int64
+    } else {
+        data.extend_from_slice(&(mediaTime as u32).to_be_bytes()); // media_time: int32
+    }
+    return genBox(b"prft", &[&data]);
+}
+
+pub struct SegmentReference {
+    // bytes of the subsegment, from its first box (emsg or moof) to the end of its mdat
+    pub size: u32,
+    // in timescale
+    pub duration: u32,
+    pub startsWithSAP: bool,
+}
+
+// Segment index box, one reference per fragment, the first one right after the sidx.
+// Version 1 with a 64-bit earliest_presentation_time once it does not fit in 32 bits
+pub fn sidx(
+    referenceId: u32,
+    timescale: u32,
+    earliestPresentationTime: u64,
+    references: &[SegmentReference],
+) -> Vec<u8> {
+    let mut data = vec![];
+    let version1 = earliestPresentationTime > u32::MAX as u64;
+    if version1 {
+        data.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]); // version(1) + flags
+    } else {
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]); // version(0) + flags
+    }
+    data.extend_from_slice(&referenceId.to_be_bytes()); // reference_ID
+    data.extend_from_slice(&timescale.to_be_bytes()); // timescale
+    if version1 {
+        data.extend_from_slice(&earliestPresentationTime.to_be_bytes()); // earliest_presentation_time: int64
+        data.extend_from_slice(&[0x00; 8]); // first_offset: int64
+    } else {
+        data.extend_from_slice(&(earliestPresentationTime as u32).to_be_bytes()); // earliest_presentation_time: int32
+        data.extend_from_slice(&[0x00; 4]); // first_offset: int32
+    }
+    data.extend_from_slice(&[0x00, 0x00]); // reserved
+    data.extend_from_slice(&(references.len() as u16).to_be_bytes()); // reference_count
+
+    for reference in references {
+        // reference_type: 0 (media) + referenced_size
+        data.extend_from_slice(&(reference.size & 0x7FFF_FFFF).to_be_bytes());
+        data.extend_from_slice(&reference.duration.to_be_bytes()); // subsegment_duration
+        let sap: u32 = if reference.startsWithSAP {
+            0x9000_0000
+        } else {
+            0
+        };
+        data.extend_from_slice(&sap.to_be_bytes()); // starts_with_SAP + SAP_type: 1 + SAP_delta_time
+    }
+    return genBox(b"sidx", &[&data]);
+}
+
+pub struct RandomAccessEntry {
+    // presentation time of the sync sample, in timescale
+    pub time: u64,
+    // of the moof holding it, from the start of the file
+    pub moofOffset: u64,
+    // 1-based, in the single traf / trun of the fragment
+    pub sampleNumber: u32,
+}
+
+// Movie fragment random access box: tfra for one track, then mfro with the mfra size,
+// so a reader can find it from the end of the file
+pub fn mfra(trackId: u32, entries: &[RandomAccessEntry]) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]); // version(1) + flags
+    data.extend_from_slice(&trackId.to_be_bytes()); // track_ID
+
+    // reserved + length_size_of_traf_num: 1 byte + length_size_of_trun_num: 1 byte
+    // + length_size_of_sample_num: 4 bytes
+    data.extend_from_slice(&[0x00, 0x00, 0x00, 0x03]);
+    data.extend_from_slice(&(entries.len() as u32).to_be_bytes()); // number_of_entry
+    for entry in entries {
+        data.extend_from_slice(&entry.time.to_be_bytes()); // time: int64
+        data.extend_from_slice(&entry.moofOffset.to_be_bytes()); // moof_offset: int64
+        data.extend_from_slice(&[0x01, 0x01]); // traf_number + trun_number
+        data.extend_from_slice(&entry.sampleNumber.to_be_bytes()); // sample_number
+    }
+    let tfra = genBox(b"tfra", &[&data]);
+
+    // mfra header + tfra + mfro
+    let mfraSize = (8 + tfra.len() + 16) as u32;
+    let mut mfro = vec![0x00, 0x00, 0x00, 0x00]; // version(0) + flags
+    mfro.extend_from_slice(&mfraSize.to_be_bytes()); // size
+    return genBox(b"mfra", &[&tfra, &genBox(b"mfro", &[&mfro])]);
+}
+
+// Sample auxiliary information sizes box
+fn saiz(sampleEncryptions: &[SampleEncryption]) -> Vec<u8> {
+    let sampleCount = sampleEncryptions.len();
+    let defaultSize = sampleEncryptions[0].size();
+    let isConstant = sampleEncryptions
+        .iter()
+        .all(|sample| sample.size() == defaultSize);
+
+    let mut data = vec![
+        0x00,
+        0x00,
+        0x00,
+        0x00,                                              // version(0) + flags
+        if isConstant { defaultSize as u8 } else { 0x00 }, // default_sample_info_size
+        (sampleCount >> 24) & 0xFF,                        // sample_count
+        (sampleCount >> 16) & 0xFF,
+        (sampleCount >> 8) & 0xFF,
+        (sampleCount) & 0xFF,
+    ];
+    if !isConstant {
+        for sample in sampleEncryptions {
+            data.push(sample.size() as u8); // sample_info_size
+        }
+    }
+    return genBox(b"saiz", &[&data]);
+}
+
+// Sample auxiliary information offsets box
+fn saio(offset: usize) -> Vec<u8> {
+    let data = [
+        0x00,
+        0x00,
+        0x00,
+        0x00, // version(0) + flags
+        0x00,
+        0x00,
+        0x00,
+        0x01,                  // entry_count
+        (offset >> 24) & 0xFF, // offset
+        (offset >> 16) & 0xFF,
+        (offset >> 8) & 0xFF,
+        (offset) & 0xFF,
+    ];
+    return genBox(b"saio", &[&data]);
+}
+
+// Sample encryption box
+fn senc(sampleEncryptions: &[SampleEncryption]) -> Vec<u8> {
+    let sampleCount = sampleEncryptions.len();
+    let useSubsamples = sampleEncryptions
+        .iter()
+        .any(|sample| !sample.subsamples.is_empty());
+
+    let mut data = vec![
+        0x00,
+        0x00,
+        0x00,
+        if useSubsamples { 0x02 } else { 0x00 }, // version(0) + flags: UseSubSampleEncryption
+        (sampleCount >> 24) & 0xFF,              // sample_count
+        (sampleCount >> 16) & 0xFF,
+        (sampleCount >> 8) & 0xFF,
+        (sampleCount) & 0xFF,
+    ];
+    for sample in sampleEncryptions {
+        data.extend_from_slice(&sample.iv); // InitializationVector
+        if !useSubsamples {
+            continue;
+        }
+        let subsampleCount = sample.subsamples.len();
+        data.extend_from_slice(&[
+            (subsampleCount >> 8) & 0xFF, // subsample_count
+            (subsampleCount) & 0xFF,
+        ]);
+        for subsample in &sample.subsamples {
+            let clearBytes = subsample.clearBytes;
+            let protectedBytes = subsample.protectedBytes;
+            data.extend_from_slice(&[
+                (clearBytes >> 8) & 0xFF, // BytesOfClearData
+                (clearBytes) & 0xFF,
+                (protectedBytes >> 24) & 0xFF, // BytesOfProtectedData
+                (protectedBytes >> 16) & 0xFF,
+                (protectedBytes >> 8) & 0xFF,
+                (protectedBytes) & 0xFF,
+            ]);
+        }
+    }
+    return genBox(b"senc", &[&data]);
+}
+
 // Sample Dependency Type box
 fn sdtp(track: &VideoTrack) -> Vec<u8> {
     let samples = track.samples || [];
@@ -756,19 +1378,24 @@ fn sdtp(track: &VideoTrack) -> Vec<u8> {
     return genBox(b"sdtp", &[&data]);
 }
 
-// Track fragment run box
+// Track fragment run box, version 1 with signed composition offsets once one is negative
 fn trun(track: &VideoTrack, offset: usize) -> Vec<u8> {
     let samples = track.samples || [];
     let sampleCount = samples.length;
     let dataSize = 12 + 16 * sampleCount;
     let data = Vec::with_capacity(dataSize);
+    let version = if samples.iter().any(|sample| sample.cts < 0) {
+        0x01
+    } else {
+        0x00
+    };
 
     data.extend_from_slice(
         &[
-            0x00,
+            version,
             0x00,
             0x0F,
-            0x01,                       // version(0) & flags
+            0x01,                       // version & flags
             (sampleCount >> 24) & 0xFF, // sample_count
             (sampleCount >> 16) & 0xFF,
             (sampleCount >> 8) & 0xFF,
@@ -808,6 +1435,479 @@ fn trun(track: &VideoTrack, offset: usize) -> Vec<u8> {
     return genBox(b"trun", &[&data]);
 }
 
-fn mdat(data: &[u8]) -> Vec<u8> {
+// Event message box, version 1: presentation time on the media timeline
+pub fn emsg(
+    schemeIdUri: &str,
+    value: &str,
+    timescale: u32,
+    presentationTime: u64,
+    eventDuration: u32,
+    id: u32,
+    messageData: &[u8],
+) -> Vec<u8> {
+    let mut data = vec![
+        0x01,
+        0x00,
+        0x00,
+        0x00,                     // version(1) + flags
+        (timescale >> 24) & 0xFF, // timescale
+        (timescale >> 16) & 0xFF,
+        (timescale >> 8) & 0xFF,
+        (timescale) & 0xFF,
+        (presentationTime >> 56) & 0xFF, // presentation_time: 8 bytes
+        (presentationTime >> 48) & 0xFF,
+        (presentationTime >> 40) & 0xFF,
+        (presentationTime >> 32) & 0xFF,
+        (presentationTime >> 24) & 0xFF,
+        (presentationTime >> 16) & 0xFF,
+        (presentationTime >> 8) & 0xFF,
+        (presentationTime) & 0xFF,
+        (eventDuration >> 24) & 0xFF, // event_duration
+        (eventDuration >> 16) & 0xFF,
+        (eventDuration >> 8) & 0xFF,
+        (eventDuration) & 0xFF,
+        (id >> 24) & 0xFF, // id
+        (id >> 16) & 0xFF,
+        (id >> 8) & 0xFF,
+        (id) & 0xFF,
+    ];
+    data.extend_from_slice(schemeIdUri.as_bytes()); // scheme_id_uri: null-terminated
+    data.push(0x00);
+    data.extend_from_slice(value.as_bytes()); // value: null-terminated
+    data.push(0x00);
+    data.extend_from_slice(messageData); // message_data
+    return genBox(b"emsg", &[&data]);
+}
+
+pub fn mdat(data: &[u8]) -> Vec<u8> {
     return genBox(b"mdat", data);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remux::mp4_muxer::{Flag, Mp4Sample};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn videoMeta(duration: u64) -> Meta {
+        Meta {
+            id: 1,
+            _type: "video".into(),
+            codec: "avc1.64001f".into(),
+            timescale: 1000,
+            duration,
+            codecWidth: 1280,
+            codecHeight: 720,
+            presentWidth: 1280,
+            presentHeight: 720,
+            sarNum: 1,
+            sarDen: 1,
+            color: None,
+            hdr: HdrInfo::default(),
+            rotation: 0,
+            mirror: false,
+            channelCount: 0,
+            audioSampleRate: 0,
+            config: vec![],
+            avcc: vec![],
+            editList: vec![],
+            encryption: None,
+        }
+    }
+
+    fn videoSample(dts: i64, cts: i64, isKeyframe: bool) -> Mp4Sample {
+        Mp4Sample {
+            dts,
+            pts: dts + cts,
+            cts,
+            duration: 40,
+            originalDts: dts,
+            size: 100,
+            isKeyframe,
+            unit: vec![],
+            units: vec![],
+            flags: Flag {
+                isLeading: 0,
+                dependsOn: if isKeyframe { 2 } else { 1 },
+                isDependedOn: if isKeyframe { 1 } else { 0 },
+                hasRedundancy: 0,
+                isNonSync: if isKeyframe { 0 } else { 1 },
+            },
+        }
+    }
+
+    fn videoTrack(samples: Vec<Mp4Sample>) -> VideoTrack {
+        VideoTrack {
+            _type: "video",
+            sequenceNumber: 1,
+            id: 1,
+            samples,
+            length: 0,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn trun_version_follows_composition_offsets() {
+        let track = videoTrack(vec![videoSample(0, 0, true), videoSample(40, 40, false)]);
+        let trun = trun(&track, 100);
+        assert_eq!(trun.len(), 8 + 12 + 2 * 16);
+        assert_eq!(&trun[8..12], &[0x00, 0x00, 0x0F, 0x01]);
+        assert_eq!(&trun[48..52], &[0x00, 0x00, 0x00, 0x28]);
+
+        // B-frame presented before its reference, with the composition delay moved into the dts
+        let track = videoTrack(vec![videoSample(0, 80, true), videoSample(40, -40, false)]);
+        let trun = super::trun(&track, 100);
+        assert_eq!(&trun[8..12], &[0x01, 0x00, 0x0F, 0x01]);
+        // sample_count, data_offset
+        assert_eq!(&trun[12..16], &[0x00, 0x00, 0x00, 0x02]);
+        assert_eq!(&trun[16..20], &[0x00, 0x00, 0x00, 0x64]);
+        // duration, size of the first sample
+        assert_eq!(
+            &trun[20..28],
+            &[0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x64]
+        );
+        // flags, composition offset of each sample
+        assert_eq!(
+            &trun[28..36],
+            &[0x02, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50]
+        );
+        assert_eq!(
+            &trun[44..52],
+            &[0x01, 0x01, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xD8]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn elst_skips_the_composition_delay() {
+        let edts = edts(&[EditListEntry {
+            segmentDuration: 10000,
+            mediaTime: 80,
+        }]);
+        assert_eq!(edts.len(), 36);
+        assert_eq!(&edts[4..8], b"edts");
+        assert_eq!(&edts[12..16], b"elst");
+        // version + flags, entry_count
+        assert_eq!(
+            &edts[16..24],
+            &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]
+        );
+        // segment_duration, media_time, media_rate
+        assert_eq!(
+            &edts[24..36],
+            &[0x00, 0x00, 0x27, 0x10, 0x00, 0x00, 0x00, 0x50, 0x00, 0x01, 0x00, 0x00]
+        );
+
+        // an empty edit holds presentation back
+        let edts = super::edts(&[EditListEntry {
+            segmentDuration: 40,
+            mediaTime: -1,
+        }]);
+        assert_eq!(&edts[28..32], &[0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[wasm_bindgen_test]
+    fn tfdt_version_follows_base_media_decode_time() {
+        let tfdt = tfdt(0xFFFF_FFFF);
+        assert_eq!(tfdt.len(), 16);
+        assert_eq!(tfdt[8], 0);
+        assert_eq!(&tfdt[12..], &[0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let tfdt = super::tfdt(0x1_0000_0010);
+        assert_eq!(tfdt.len(), 20);
+        assert_eq!(&tfdt[4..8], b"tfdt");
+        assert_eq!(tfdt[8], 1);
+        assert_eq!(
+            &tfdt[12..],
+            &[0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x10]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn mvhd_version_follows_duration() {
+        let mvhd = mvhd(1000, 0xFFFF_FFFF);
+        assert_eq!(mvhd.len(), 108);
+        assert_eq!(mvhd[8], 0);
+        assert_eq!(&mvhd[24..28], &[0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let mvhd = super::mvhd(1000, 0x1_0000_0000);
+        assert_eq!(mvhd.len(), 120);
+        assert_eq!(mvhd[8], 1);
+        // version + flags, creation_time, modification_time, timescale
+        assert_eq!(&mvhd[28..32], &1000u32.to_be_bytes());
+        assert_eq!(
+            &mvhd[32..40],
+            &[0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]
+        );
+        // next_track_ID
+        assert_eq!(&mvhd[116..], &[0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[wasm_bindgen_test]
+    fn tkhd_version_follows_duration() {
+        let tkhd = tkhd(videoMeta(0xFFFF_FFFF));
+        assert_eq!(tkhd.len(), 92);
+        assert_eq!(&tkhd[8..12], &[0x00, 0x00, 0x00, 0x07]);
+        // track_ID, reserved, duration
+        assert_eq!(&tkhd[20..24], &1u32.to_be_bytes());
+        assert_eq!(&tkhd[28..32], &[0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let tkhd = super::tkhd(videoMeta(0x1_0000_0000));
+        assert_eq!(tkhd.len(), 104);
+        assert_eq!(&tkhd[8..12], &[0x01, 0x00, 0x00, 0x07]);
+        assert_eq!(&tkhd[28..32], &1u32.to_be_bytes());
+        assert_eq!(
+            &tkhd[36..44],
+            &[0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]
+        );
+        // width and height close the box
+        assert_eq!(
+            &tkhd[96..],
+            &[0x05, 0x00, 0x00, 0x00, 0x02, 0xD0, 0x00, 0x00]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn mdhd_version_follows_duration() {
+        let mdhd = mdhd(videoMeta(0xFFFF_FFFF));
+        assert_eq!(mdhd.len(), 32);
+        assert_eq!(mdhd[8], 0);
+        assert_eq!(&mdhd[20..24], &1000u32.to_be_bytes());
+        assert_eq!(&mdhd[24..28], &[0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let mdhd = super::mdhd(videoMeta(0x1_0000_0000));
+        assert_eq!(mdhd.len(), 44);
+        assert_eq!(mdhd[8], 1);
+        assert_eq!(&mdhd[28..32], &1000u32.to_be_bytes());
+        assert_eq!(
+            &mdhd[32..40],
+            &[0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]
+        );
+        // language, pre_defined
+        assert_eq!(&mdhd[40..], &[0x55, 0xC4, 0x00, 0x00]);
+    }
+
+    #[wasm_bindgen_test]
+    fn sidx_references() {
+        let references = [
+            SegmentReference {
+                size: 1000,
+                duration: 2000,
+                startsWithSAP: true,
+            },
+            SegmentReference {
+                size: 500,
+                duration: 1000,
+                startsWithSAP: false,
+            },
+        ];
+        let sidx = sidx(1, 1000, 40, &references);
+        assert_eq!(sidx.len(), 32 + 2 * 12);
+        assert_eq!(&sidx[4..8], b"sidx");
+        assert_eq!(sidx[8], 0);
+        // earliest_presentation_time, first_offset, reserved + reference_count
+        assert_eq!(&sidx[20..24], &40u32.to_be_bytes());
+        assert_eq!(&sidx[24..28], &[0x00; 4]);
+        assert_eq!(&sidx[28..32], &[0x00, 0x00, 0x00, 0x02]);
+        assert_eq!(&sidx[32..36], &1000u32.to_be_bytes());
+        assert_eq!(&sidx[36..40], &2000u32.to_be_bytes());
+        assert_eq!(&sidx[40..44], &[0x90, 0x00, 0x00, 0x00]);
+        assert_eq!(&sidx[52..56], &[0x00; 4]);
+
+        let sidx = super::sidx(1, 1000, 0x1_0000_0000, &[]);
+        assert_eq!(sidx.len(), 40);
+        assert_eq!(sidx[8], 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn mfra_ends_with_its_size() {
+        let entries = [RandomAccessEntry {
+            time: 2000,
+            moofOffset: 1500,
+            sampleNumber: 1,
+        }];
+        let mfra = mfra(1, &entries);
+        let size = mfra.len();
+        // mfra header + tfra with one entry + mfro
+        assert_eq!(size, 8 + 24 + 22 + 16);
+        assert_eq!(&mfra[12..16], b"tfra");
+        assert_eq!(&mfra[size - 12..size - 8], b"mfro");
+        assert_eq!(&mfra[size - 4..], &(size as u32).to_be_bytes());
+        // time, moof_offset
+        assert_eq!(&mfra[32..40], &2000u64.to_be_bytes());
+        assert_eq!(&mfra[40..48], &1500u64.to_be_bytes());
+    }
+
+    #[wasm_bindgen_test]
+    fn prft_version_follows_media_time() {
+        // 2026-10-18T12:34:56.500Z
+        let ntpTimestamp = 0xEE7F_3B70_8000_0000;
+        let prft = prft(1, 0x04, ntpTimestamp, 90000);
+        assert_eq!(prft.len(), 28);
+        assert_eq!(&prft[4..8], b"prft");
+        assert_eq!(&prft[8..12], &[0x00, 0x00, 0x00, 0x04]);
+        assert_eq!(&prft[12..16], &1u32.to_be_bytes());
+        assert_eq!(&prft[16..24], &ntpTimestamp.to_be_bytes());
+        assert_eq!(&prft[24..28], &90000u32.to_be_bytes());
+
+        let prft = super::prft(1, 0x00, ntpTimestamp, 0x1_0000_0000);
+        assert_eq!(prft.len(), 32);
+        assert_eq!(prft[8], 1);
+        assert_eq!(
+            &prft[24..32],
+            &[0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn emsg_version_1_fields() {
+        let emsg = emsg(
+            "urn:scte:scte35:2013:bin",
+            "",
+            1000,
+            0x1_0000_0010,
+            30000,
+            7,
+            &[0xFC, 0x30],
+        );
+        assert_eq!(emsg.len(), 32 + 25 + 1 + 2);
+        assert_eq!(&emsg[4..8], b"emsg");
+        assert_eq!(&emsg[8..12], &[0x01, 0x00, 0x00, 0x00]);
+        // timescale, presentation_time, event_duration, id
+        assert_eq!(&emsg[12..16], &1000u32.to_be_bytes());
+        assert_eq!(&emsg[16..24], &0x1_0000_0010u64.to_be_bytes());
+        assert_eq!(&emsg[24..28], &30000u32.to_be_bytes());
+        assert_eq!(&emsg[28..32], &7u32.to_be_bytes());
+        // null-terminated scheme_id_uri and value, then message_data
+        assert_eq!(&emsg[32..57], b"urn:scte:scte35:2013:bin\0");
+        assert_eq!(emsg[57], 0x00);
+        assert_eq!(&emsg[58..], &[0xFC, 0x30]);
+    }
+
+    #[wasm_bindgen_test]
+    fn webvtt_boxes() {
+        let mut expected = vec![0x00, 0x00, 0x00, 0x1E];
+        expected.extend_from_slice(b"wvtt");
+        // reserved, data_reference_index
+        expected.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        expected.extend_from_slice(&[0x00, 0x00, 0x00, 0x0E]);
+        expected.extend_from_slice(b"vttCWEBVTT");
+        assert_eq!(wvtt(), expected);
+
+        let mut expected = vec![0x00, 0x00, 0x00, 0x12];
+        expected.extend_from_slice(b"vttc");
+        expected.extend_from_slice(&[0x00, 0x00, 0x00, 0x0A]);
+        expected.extend_from_slice(b"paylHi");
+        assert_eq!(vttc("Hi"), expected);
+
+        assert_eq!(vtte(), [0x00, 0x00, 0x00, 0x08, b'v', b't', b't', b'e']);
+    }
+
+    #[wasm_bindgen_test]
+    fn colr_nclx() {
+        let mut color = ColorInfo {
+            colour_primaries: 9,
+            transfer_characteristics: 16,
+            matrix_coefficients: 9,
+            full_range: false,
+        };
+        let mut expected = vec![0x00, 0x00, 0x00, 0x13];
+        expected.extend_from_slice(b"colrnclx");
+        expected.extend_from_slice(&[0x00, 0x09, 0x00, 0x10, 0x00, 0x09, 0x00]);
+        assert_eq!(colr(color), expected);
+
+        color.full_range = true;
+        assert_eq!(colr(color)[18], 0x80);
+    }
+
+    #[wasm_bindgen_test]
+    fn pasp_spacing() {
+        let mut expected = vec![0x00, 0x00, 0x00, 0x10];
+        expected.extend_from_slice(b"pasp");
+        expected.extend_from_slice(&[0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x03]);
+        assert_eq!(pasp(4, 3), expected);
+    }
+
+    #[wasm_bindgen_test]
+    fn mdcv_and_clli() {
+        let mdcv = mdcv(MasteringDisplay {
+            displayPrimaries: [(8500, 39850), (6550, 2300), (35400, 14600)],
+            whitePoint: (15635, 16450),
+            maxLuminance: 10000000,
+            minLuminance: 50,
+        });
+        let mut expected = vec![0x00, 0x00, 0x00, 0x20];
+        expected.extend_from_slice(b"mdcv");
+        // display_primaries G, B, R, white_point, max and min luminance
+        expected.extend_from_slice(&[
+            0x21, 0x34, 0x9B, 0xAA, 0x19, 0x96, 0x08, 0xFC, 0x8A, 0x48, 0x39, 0x08, 0x3D, 0x13,
+            0x40, 0x42, 0x00, 0x98, 0x96, 0x80, 0x00, 0x00, 0x00, 0x32,
+        ]);
+        assert_eq!(mdcv, expected);
+
+        let clli = clli(ContentLightLevel {
+            maxCLL: 1000,
+            maxFALL: 400,
+        });
+        let mut expected = vec![0x00, 0x00, 0x00, 0x0C];
+        expected.extend_from_slice(b"clli");
+        expected.extend_from_slice(&[0x03, 0xE8, 0x01, 0x90]);
+        assert_eq!(clli, expected);
+    }
+
+    // where a picture point ends up on screen
+    fn display(matrix: [i32; 9], x: i32, y: i32) -> (i32, i32) {
+        (
+            (x * matrix[0] + y * matrix[3] + matrix[6]) >> 16,
+            (x * matrix[1] + y * matrix[4] + matrix[7]) >> 16,
+        )
+    }
+
+    #[wasm_bindgen_test]
+    fn display_matrix_rotation() {
+        assert_eq!(
+            displayMatrix(0, false, 1920, 1080),
+            [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000]
+        );
+
+        // clockwise: the top left corner goes to the top right of the 1080x1920 display
+        let matrix = displayMatrix(90, false, 1920, 1080);
+        assert_eq!(
+            matrix,
+            [0, 0x10000, 0, -0x10000, 0, 0, 1080 << 16, 0, 0x40000000]
+        );
+        assert_eq!(display(matrix, 0, 0), (1080, 0));
+        assert_eq!(display(matrix, 1920, 0), (1080, 1920));
+        assert_eq!(display(matrix, 0, 1080), (0, 0));
+
+        let matrix = displayMatrix(180, false, 1920, 1080);
+        assert_eq!(display(matrix, 0, 0), (1920, 1080));
+        assert_eq!(display(matrix, 1920, 1080), (0, 0));
+
+        let matrix = displayMatrix(270, false, 1920, 1080);
+        assert_eq!(display(matrix, 0, 0), (0, 1920));
+        assert_eq!(display(matrix, 1920, 0), (0, 0));
+    }
+
+    #[wasm_bindgen_test]
+    fn display_matrix_mirror() {
+        let matrix = displayMatrix(0, true, 1920, 1080);
+        assert_eq!(
+            matrix,
+            [-0x10000, 0, 0, 0, 0x10000, 0, 1920 << 16, 0, 0x40000000]
+        );
+        assert_eq!(display(matrix, 0, 0), (1920, 0));
+        assert_eq!(display(matrix, 1920, 1080), (0, 1080));
+
+        // mirrored, then turned: the top left corner ends up at the bottom right
+        let matrix = displayMatrix(90, true, 1920, 1080);
+        assert_eq!(display(matrix, 0, 0), (1080, 1920));
+        assert_eq!(display(matrix, 1920, 0), (1080, 0));
+
+        let matrix = displayMatrix(180, true, 1920, 1080);
+        assert_eq!(display(matrix, 0, 0), (0, 1080));
+
+        let matrix = displayMatrix(270, true, 1920, 1080);
+        assert_eq!(display(matrix, 0, 0), (0, 0));
+        assert_eq!(display(matrix, 1920, 1080), (1080, 1920));
+    }
+}
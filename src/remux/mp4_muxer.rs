@@ -18,15 +18,33 @@
 
 //  import Log from "../utils/logger.js";
 //  import MP4 from "./mp4-generator.js";
-//  import AAC from "./aac-silent.js";
 //  import Browser from "../utils/browser.js";
 //  import { SampleInfo, MediaSegmentInfo, MediaSegmentInfoList } from "../core/media-segment-info.js";
 //  import { IllegalStateException } from "../utils/exception.js";
+use super::aac_silent;
+use super::adts::{self, AdtsConfig};
+use super::annexb::{self, AnnexBExporter, NaluCodec};
+use super::cenc::{CencEncryptor, SampleEncryption};
+use super::clip_extractor::mp4Fragment;
+use super::mp3_silent;
+use super::mp4_generator;
+use super::opus_silent;
+use super::segment_index::SegmentIndex;
+use super::text_track::{self, TextTrack, TEXT_TRACK_ID};
+use super::timestamp_sanitizer::{Discontinuity, SanitizerStatistics, TimestampSanitizer};
+use super::webcodecs;
+use crate::config::{
+    Config, OutputMode, ProducerReferenceTimeSource, SyncStrategy, TextTrackMode, VideoGapPolicy,
+};
 use crate::core::media_segment_info::MediaSegmentInfo;
 use crate::core::media_segment_info::MediaSegmentInfoList;
 use crate::core::media_segment_info::SampleInfo;
-use crate::demux::flv_demuxer::Track;
-use crate::demux::flv_demuxer::{AudioTrack, FLVDemuxer, VideoTrack};
+use crate::demux::captions::CaptionCue;
+use crate::demux::flv_demuxer::{AudioTrack, FLVDemuxer, VideoSample, VideoTrack};
+use crate::demux::flv_demuxer::{TimedMetadata, Track, TrackMetaData, Unit};
+use crate::demux::hdr::HdrInfo;
+use crate::demux::producer_reference::{self, ProducerReference};
+use crate::demux::scte35::{self, Scte35Cue, SpliceInfo, SCTE35_SCHEME_ID_URI};
 use crate::utils::logger::Log;
 use js_sys::Function;
 use js_sys::Math;
@@ -47,9 +65,75 @@ struct MediaSegment {
     pub data: Vec<u8>,
     pub sampleCount: usize,
     pub info: MediaSegmentInfo,
+    pub syncReport: SyncReport,
+}
+
+// Timestamp corrections applied to the samples of one media segment, see Config.syncStrategy
+#[wasm_bindgen]
+#[derive(Copy, Clone, Default)]
+pub struct SyncReport {
+    pub droppedFrames: u32,
+    // silent audio frames or duplicated video frames
+    pub insertedFrames: u32,
+    // ms, dts - original dts of the first sample
+    pub dtsShift: i64,
+    // audio frames shifted back onto their original timestamps by VideoMaster
+    pub resyncCount: u32,
+}
+
+// Video timestamp gaps of whole frames seen by the remuxer, see Config.videoGapPolicy
+#[wasm_bindgen]
+#[derive(Copy, Clone, Default)]
+pub struct VideoGapStatistics {
+    pub gapCount: u32,
+    pub missingFrames: u32,
+    // gaps left to the sample before them, with VideoGapPolicy::Duplicate too when that sample
+    // cannot be repeated
+    pub stretchedGaps: u32,
+    pub duplicatedFrames: u32,
+    pub discontinuities: u32,
+}
+
+// An event waiting to be written as emsg, times in milliseconds
+struct PendingEvent {
+    schemeIdUri: String,
+    value: String,
+    dts: i64,
+    duration: u32,
+    messageData: Vec<u8>,
+}
+
+// Sample flags, written into sdtp & trun
+#[derive(Copy, Clone, Default)]
+pub struct Flag {
+    pub isLeading: u8,
+    pub dependsOn: u8,
+    pub isDependedOn: u8,
+    pub hasRedundancy: u8,
+    pub isNonSync: u8,
+}
+
+// A remuxed sample with corrected timestamps, waiting to be written into moof & mdat
+pub struct Mp4Sample {
+    pub dts: i64,
+    pub pts: i64,
+    pub cts: i64,
+    pub duration: i64,
+    pub originalDts: i64,
+    pub size: usize,
+    pub isKeyframe: bool,
+    // audio sample payload
+    pub unit: Vec<u8>,
+    // video sample NALUs
+    pub units: Vec<Unit>,
+    pub flags: Flag,
 }
 
 pub struct MP4Remuxer {
+    TAG: &'static str,
+    _config: Config,
+    _isLive: bool,
+
     _dtsBase: i64,
     _audioDtsBase: i64,
     _videoDtsBase: i64,
@@ -58,6 +142,36 @@ pub struct MP4Remuxer {
 
     _onInitSegment: Option<Function>,
     _onMediaSegment: Option<Function>,
+    _onDecoderConfig: Option<Function>,
+    _onEncodedChunk: Option<Function>,
+    _onElementaryStream: Option<Function>,
+    _onTextCue: Option<Function>,
+    _onScte35Cue: Option<Function>,
+    _onDiscontinuity: Option<Function>,
+
+    _annexBExporter: Option<AnnexBExporter>,
+    _adtsConfig: Option<AdtsConfig>,
+
+    _audioEncryptor: Option<CencEncryptor>,
+    _videoEncryptor: Option<CencEncryptor>,
+
+    // script tag events waiting for the media segment covering their dts
+    _pendingEvents: Vec<PendingEvent>,
+    _emsgId: u32,
+
+    _textTrack: TextTrack,
+
+    _videoGapStatistics: VideoGapStatistics,
+    // cts of the first video sample, with Config.negativeCompositionOffsets
+    _compositionDelay: Option<i64>,
+    // the video init segment waits for the composition delay, written into its elst
+    _pendingVideoMetadata: Option<TrackMetaData>,
+    // with Config.enableSegmentIndex
+    _audioSegmentIndex: Option<SegmentIndex>,
+    _videoSegmentIndex: Option<SegmentIndex>,
+    // latest wall clock reference from the demuxer, for the prft boxes
+    _producerReference: Option<ProducerReference>,
+    _timestampSanitizer: Option<TimestampSanitizer>,
 
     _audioSegmentInfoList: MediaSegmentInfoList,
     _videoSegmentInfoList: MediaSegmentInfoList,
@@ -65,13 +179,18 @@ pub struct MP4Remuxer {
 
 // Fragmented mp4 remuxer
 impl MP4Remuxer {
-    fn new(config: !) -> MP4Remuxer {
-        //  self.TAG = "MP4Remuxer";
-
-        self._config = config;
-        self._isLive = (config.isLive == true);
+    fn new(config: Config) -> MP4Remuxer {
+        let timestampSanitizer = if config.sanitizeTimestamps {
+            Some(TimestampSanitizer::new(&config))
+        } else {
+            None
+        };
 
         MP4Remuxer {
+            TAG: "MP4Remuxer",
+            _isLive: config.isLive,
+            _config: config,
+
             _dtsBase: -1,
             _dtsBaseInited: false,
             _audioDtsBase: -1,
@@ -100,6 +219,31 @@ impl MP4Remuxer {
 
             _onInitSegment: None,
             _onMediaSegment: None,
+            _onDecoderConfig: None,
+            _onEncodedChunk: None,
+            _onElementaryStream: None,
+            _onTextCue: None,
+            _onScte35Cue: None,
+            _onDiscontinuity: None,
+
+            _annexBExporter: None,
+            _adtsConfig: None,
+
+            _audioEncryptor: None,
+            _videoEncryptor: None,
+
+            _pendingEvents: Vec::new(),
+            _emsgId: 0,
+
+            _textTrack: TextTrack::new(),
+
+            _videoGapStatistics: VideoGapStatistics::default(),
+            _compositionDelay: None,
+            _pendingVideoMetadata: None,
+            _audioSegmentIndex: None,
+            _videoSegmentIndex: None,
+            _producerReference: None,
+            _timestampSanitizer: timestampSanitizer,
             // Workaround for chrome < 50: Always force first sample as a Random Access Point in media segment
             // see https://bugs.chromium.org/p/chromium/issues/detail?id:229412
             //  _forceFirstIDR : (Browser.chrome &&
@@ -122,17 +266,34 @@ impl MP4Remuxer {
         self._dtsBaseInited = false;
         self._audioMeta = None;
         self._videoMeta = None;
+        self._pendingVideoMetadata = None;
         self._audioSegmentInfoList.clear();
         self._audioSegmentInfoList = None;
         self._videoSegmentInfoList.clear();
         self._videoSegmentInfoList = None;
         self._onInitSegment = None;
         self._onMediaSegment = None;
+        self._onDecoderConfig = None;
+        self._onEncodedChunk = None;
+        self._onElementaryStream = None;
+        self._onTextCue = None;
+        self._onScte35Cue = None;
+        self._onDiscontinuity = None;
+        self._annexBExporter = None;
+        self._adtsConfig = None;
+        self._audioEncryptor = None;
+        self._videoEncryptor = None;
+        self._pendingEvents.clear();
+        self._audioSegmentIndex = None;
+        self._videoSegmentIndex = None;
+        self._producerReference = None;
     }
 
     fn bindDataSource(&self, producer: &FLVDemuxer) -> &Self {
         producer.set_onDataAvailable(MP4Remuxer::remux);
         producer.set_onTrackMetadata(MP4Remuxer::_onTrackMetadataReceived);
+        producer.set_onTimedMetadata(MP4Remuxer::_onTimedMetadataReceived);
+        producer.set_onProducerReference(MP4Remuxer::_onProducerReferenceReceived);
         return self;
     }
 
@@ -156,6 +317,109 @@ impl MP4Remuxer {
         self._onMediaSegment = callback;
     }
 
+    #[wasm_bindgen(getter, js_name = "onDecoderConfig")]
+    pub fn get_onDecoderConfig(&self) -> Option<Function> {
+        return self._onDecoderConfig;
+    }
+
+    #[wasm_bindgen(setter, js_name = "onDecoderConfig")]
+    pub fn set_onDecoderConfig(&self, callback: Option<Function>) {
+        self._onDecoderConfig = callback;
+    }
+
+    #[wasm_bindgen(getter, js_name = "onEncodedChunk")]
+    pub fn get_onEncodedChunk(&self) -> Option<Function> {
+        return self._onEncodedChunk;
+    }
+
+    #[wasm_bindgen(setter, js_name = "onEncodedChunk")]
+    pub fn set_onEncodedChunk(&self, callback: Option<Function>) {
+        self._onEncodedChunk = callback;
+    }
+
+    #[wasm_bindgen(getter, js_name = "onElementaryStream")]
+    pub fn get_onElementaryStream(&self) -> Option<Function> {
+        return self._onElementaryStream;
+    }
+
+    #[wasm_bindgen(setter, js_name = "onElementaryStream")]
+    pub fn set_onElementaryStream(&self, callback: Option<Function>) {
+        self._onElementaryStream = callback;
+    }
+
+    #[wasm_bindgen(getter, js_name = "onTextCue")]
+    pub fn get_onTextCue(&self) -> Option<Function> {
+        return self._onTextCue;
+    }
+
+    #[wasm_bindgen(setter, js_name = "onTextCue")]
+    pub fn set_onTextCue(&self, callback: Option<Function>) {
+        self._onTextCue = callback;
+    }
+
+    // Scte35Cue, to be written as EXT-X-DATERANGE into the application's playlists
+    #[wasm_bindgen(getter, js_name = "onScte35Cue")]
+    pub fn get_onScte35Cue(&self) -> Option<Function> {
+        return self._onScte35Cue;
+    }
+
+    #[wasm_bindgen(setter, js_name = "onScte35Cue")]
+    pub fn set_onScte35Cue(&self, callback: Option<Function>) {
+        self._onScte35Cue = callback;
+    }
+
+    // (type, gapStartDts, gapEndDts): the media timeline of the track jumps over the gap, see
+    // VideoGapPolicy::Discontinuity
+    #[wasm_bindgen(getter, js_name = "onDiscontinuity")]
+    pub fn get_onDiscontinuity(&self) -> Option<Function> {
+        return self._onDiscontinuity;
+    }
+
+    #[wasm_bindgen(setter, js_name = "onDiscontinuity")]
+    pub fn set_onDiscontinuity(&self, callback: Option<Function>) {
+        self._onDiscontinuity = callback;
+    }
+
+    #[wasm_bindgen(getter, js_name = "videoGapStatistics")]
+    pub fn get_videoGapStatistics(&self) -> VideoGapStatistics {
+        self._videoGapStatistics
+    }
+
+    #[wasm_bindgen(getter, js_name = "timestampSanitizerStatistics")]
+    pub fn get_timestampSanitizerStatistics(&self) -> Option<SanitizerStatistics> {
+        self._timestampSanitizer
+            .as_ref()
+            .map(|sanitizer| sanitizer.statistics())
+    }
+
+    // sidx of the fragments of a track ("audio" / "video") emitted so far, to write between its
+    // init segment and first fragment. Empty unless Config.enableSegmentIndex
+    #[wasm_bindgen(js_name = "segmentIndex")]
+    pub fn segmentIndex(&self, r#type: &str) -> Vec<u8> {
+        match self._segmentIndex(r#type) {
+            Some(segmentIndex) => segmentIndex.sidx(),
+            None => vec![],
+        }
+    }
+
+    // mfra of the fragments of a track, to append after the last one. firstFragmentOffset:
+    // bytes before the first fragment in the file, the init segment and sidx
+    #[wasm_bindgen(js_name = "randomAccessIndex")]
+    pub fn randomAccessIndex(&self, r#type: &str, firstFragmentOffset: f64) -> Vec<u8> {
+        match self._segmentIndex(r#type) {
+            Some(segmentIndex) => segmentIndex.mfra(firstFragmentOffset as u64),
+            None => vec![],
+        }
+    }
+
+    fn _segmentIndex(&self, r#type: &str) -> Option<&SegmentIndex> {
+        match r#type {
+            "audio" => self._audioSegmentIndex.as_ref(),
+            "video" => self._videoSegmentIndex.as_ref(),
+            _ => None,
+        }
+    }
+
     fn insertDiscontinuity(&self) {
         self._audioNextDts = self._videoNextDts = undefined;
     }
@@ -171,14 +435,94 @@ impl MP4Remuxer {
         if (!self._onMediaSegment) {
             //  throw new IllegalStateException("MP4Remuxer: onMediaSegment callback must be specificed!");
         }
+        if let Some(sanitizer) = &mut self._timestampSanitizer {
+            let audioFrameDuration = match &self._audioMeta {
+                Some(meta) => meta.refSampleDuration,
+                None => 0.0,
+            };
+            let videoFrameDuration = match &self._videoMeta {
+                Some(meta) => meta.refSampleDuration,
+                None => 0.0,
+            };
+            if let Some(discontinuity) = sanitizer.sanitize(
+                audioTrack,
+                videoTrack,
+                audioFrameDuration,
+                videoFrameDuration,
+            ) {
+                Log::w(
+                    self.TAG,
+                    "Timestamp discontinuity, not stitching segments across it",
+                );
+                self._remuxAcrossDiscontinuity(audioTrack, videoTrack, discontinuity);
+                return;
+            }
+        }
+        if (!self._dtsBaseInited) {
+            self._calculateDtsBase(audioTrack, videoTrack);
+        }
+        self._remuxVideo(videoTrack);
+        self._remuxAudio(audioTrack);
+    }
+
+    // The samples before the discontinuity are remuxed and flushed, the ones after it go to
+    // new media segments so that no sample duration spans the jump
+    fn _remuxAcrossDiscontinuity(
+        &mut self,
+        audioTrack: &mut AudioTrack,
+        videoTrack: &mut VideoTrack,
+        discontinuity: Discontinuity,
+    ) {
+        let audioSamples = match discontinuity.audio {
+            Some(index) => audioTrack.samples.split_off(index),
+            None => vec![],
+        };
+        let videoSamples = match discontinuity.video {
+            Some(index) => videoTrack.samples.split_off(index),
+            None => vec![],
+        };
+        audioTrack.length = audioTrack.samples.iter().map(|sample| sample.length).sum();
+        videoTrack.length = videoTrack.samples.iter().map(|sample| sample.length).sum();
+
         if (!self._dtsBaseInited) {
             self._calculateDtsBase(audioTrack, videoTrack);
         }
         self._remuxVideo(videoTrack);
         self._remuxAudio(audioTrack);
+        self.flushStashedSamples();
+
+        self.insertDiscontinuity();
+        audioTrack.length = audioSamples.iter().map(|sample| sample.length).sum();
+        audioTrack.samples = audioSamples;
+        videoTrack.length = videoSamples.iter().map(|sample| sample.length).sum();
+        videoTrack.samples = videoSamples;
+        self._remuxVideo(videoTrack);
+        self._remuxAudio(audioTrack);
     }
 
     fn _onTrackMetadataReceived(&self, metadata: TrackMetaData) {
+        if self._config.outputMode == OutputMode::WebCodecs {
+            self._onDecoderConfigReceived(metadata);
+            return;
+        }
+        if self._config.outputMode == OutputMode::ElementaryStream {
+            self._onElementaryStreamMetadataReceived(metadata);
+            return;
+        }
+
+        if let TrackMetaData::Video(meta) = &metadata {
+            if self._config.negativeCompositionOffsets && self._compositionDelay.is_none() {
+                // sent from _remuxVideo once the first sample gives the composition delay
+                self._videoMeta = Some(meta.clone());
+                self._pendingVideoMetadata = Some(metadata);
+                return;
+            }
+        }
+
+        if self._config.encryption.is_some() {
+            self._setupEncryption(&metadata);
+        }
+
         let metabox = None;
 
         let container = "mp4";
@@ -193,11 +537,11 @@ impl MP4Remuxer {
         //          metabox = Vec::new();
         //      } else {
         //          // "audio/mp4, codecs="codec""
-        //          metabox = MP4.generateInitSegment(metadata);
+        //          metabox = MP4.generateInitSegment(self._initSegmentMeta(&metadata));
         //      }
         //  } else if (_type == "video") {
         //      self._videoMeta = metadata;
-        //      metabox = MP4.generateInitSegment(metadata);
+        //      metabox = MP4.generateInitSegment(self._initSegmentMeta(&metadata));
         //  } else {
         //      return;
         //  }
@@ -217,6 +561,400 @@ impl MP4Remuxer {
         }
     }
 
+    fn _onProducerReferenceReceived(&mut self, reference: ProducerReference) {
+        self._producerReference = Some(reference);
+    }
+
+    // prft for a fragment whose tfdt is mediaTime, sampleDts: dts of its first sample on the
+    // demuxer timeline. Empty until the demuxer sent a wall clock reference
+    fn _producerReferenceBox(&self, trackId: u32, sampleDts: i64, mediaTime: i64) -> Vec<u8> {
+        if !self._config.enableProducerReferenceTime {
+            return vec![];
+        }
+        let reference = match &self._producerReference {
+            Some(reference) => reference,
+            None => return vec![],
+        };
+        let wallClock = reference.wallClock + (sampleDts - reference.dts) as f64;
+        let flags = match self._config.producerReferenceTimeSource {
+            // the tags reached us about when the fragment is written
+            ProducerReferenceTimeSource::Arrival => 0x04,
+            // the encoder clock
+            ProducerReferenceTimeSource::Sei | ProducerReferenceTimeSource::ScriptTag => 0x00,
+        };
+        mp4_generator::prft(
+            trackId,
+            flags,
+            producer_reference::ntpTimestamp(wallClock),
+            mediaTime.max(0) as u64,
+        )
+    }
+
+    fn _onTimedMetadataReceived(&mut self, metadata: TimedMetadata) {
+        if metadata.name == "onTextData" && self._config.textTrackMode != TextTrackMode::Disabled {
+            self._onTextDataReceived(&metadata);
+            return;
+        }
+
+        if self._config.enableScte35 && metadata.name == "onCuePoint" {
+            if let Some(spliceInfo) = scte35::parseCuePoint(&metadata.data) {
+                self._onScte35Received(metadata.dts, spliceInfo);
+                return;
+            }
+        }
+
+        // only fMP4 segments can carry emsg
+        if self._config.outputMode != OutputMode::FMP4 {
+            return;
+        }
+
+        if !self._config.enableTimedMetadata {
+            return;
+        }
+        let schemeIdUri = match self._config.timedMetadataSchemes.get(&metadata.name) {
+            Some(schemeIdUri) => schemeIdUri.clone(),
+            None => self._config.timedMetadataDefaultScheme.clone(),
+        };
+        self._pendingEvents.push(PendingEvent {
+            schemeIdUri,
+            value: metadata.name,
+            dts: metadata.dts,
+            duration: 0,
+            messageData: metadata.data,
+        });
+    }
+
+    // emsg at the splice time in fMP4 output, and a cue for the playlists in any output mode
+    fn _onScte35Received(&mut self, tagDts: i64, spliceInfo: SpliceInfo) {
+        let spliceDts = spliceInfo.spliceDts(tagDts);
+
+        if self._config.outputMode == OutputMode::FMP4 {
+            self._pendingEvents.push(PendingEvent {
+                schemeIdUri: SCTE35_SCHEME_ID_URI.to_string(),
+                value: String::new(),
+                dts: spliceDts,
+                duration: spliceInfo.duration().unwrap_or(0) as u32,
+                messageData: spliceInfo.raw.clone(),
+            });
+        }
+
+        if let Some(onScte35Cue) = &self._onScte35Cue {
+            // on the media timeline, as the audio / video samples
+            let dtsBase = if self._dtsBaseInited {
+                self._dtsBase
+            } else {
+                0
+            };
+            let time = std::cmp::max(spliceDts - dtsBase, 0) as f64;
+            let cue = Scte35Cue::new(time, spliceInfo);
+            let _ = onScte35Cue.call1(&JsValue::null(), &JsValue::from(cue));
+        }
+    }
+
+    fn _onTextDataReceived(&mut self, metadata: &TimedMetadata) {
+        let (text, trackId) = match text_track::parseTextData(&metadata.data) {
+            Some(textData) => textData,
+            None => return,
+        };
+        // on the media timeline, as the audio / video samples
+        let dtsBase = if self._dtsBaseInited {
+            self._dtsBase
+        } else {
+            0
+        };
+        let time = std::cmp::max(metadata.dts - dtsBase, 0);
+
+        let cue = match self._textTrack.push(time, text, trackId) {
+            Some(cue) => cue,
+            None => return,
+        };
+        match self._config.textTrackMode {
+            TextTrackMode::Cues => match &self._onTextCue {
+                Some(onTextCue) => {
+                    let _ = onTextCue.call1(&JsValue::null(), &JsValue::from(cue));
+                }
+                None => panic!("MP4Remuxer: onTextCue callback must be specified!"),
+            },
+            TextTrackMode::Wvtt => self._emitTextSegment(&cue),
+            TextTrackMode::Disabled => {}
+        }
+    }
+
+    // wvtt text track: an init segment first, then one moof + mdat per cue
+    fn _emitTextSegment(&mut self, cue: &CaptionCue) {
+        if !self._textTrack.initSegmentSent {
+            let meta = mp4_generator::Meta {
+                id: TEXT_TRACK_ID as u32,
+                _type: "text".into(),
+                codec: "wvtt".into(),
+                timescale: 1000,
+                duration: 0,
+                codecWidth: 0,
+                codecHeight: 0,
+                presentWidth: 0,
+                presentHeight: 0,
+                sarNum: 0,
+                sarDen: 0,
+                color: None,
+                hdr: HdrInfo::default(),
+                rotation: 0,
+                mirror: false,
+                channelCount: 0,
+                audioSampleRate: 0,
+                config: vec![],
+                avcc: vec![],
+                editList: vec![],
+                encryption: None,
+            };
+            let initSegment = InitSegment {
+                r#type: "text",
+                data: mp4_generator::generateInitSegment(meta),
+                codec: "wvtt".into(),
+                container: "text/mp4".into(),
+                mediaDuration: 0,
+            };
+            if let Some(onInitSegment) = &self._onInitSegment {
+                let _ = onInitSegment.call2(
+                    &JsValue::null(),
+                    &JsValue::from("text"),
+                    &JsValue::from(initSegment),
+                );
+            }
+            self._textTrack.initSegmentSent = true;
+        }
+
+        let (samples, mdatData) = self._textTrack.samples(cue);
+        let sampleCount = samples.len();
+        let mut info = MediaSegmentInfo::new();
+        info.beginDts = samples[0].dts as usize;
+        info.endDts = cue.endTime as usize;
+        info.beginPts = samples[0].dts as usize;
+        info.endPts = cue.endTime as usize;
+
+        self._textTrack.sequenceNumber += 1;
+        let segment = MediaSegment {
+            r#type: "text",
+            data: mp4Fragment(
+                TEXT_TRACK_ID,
+                self._textTrack.sequenceNumber,
+                samples,
+                mdatData,
+            ),
+            sampleCount,
+            info,
+            syncReport: SyncReport::default(),
+        };
+        if let Some(onMediaSegment) = &self._onMediaSegment {
+            let _ = onMediaSegment.call2(
+                &JsValue::null(),
+                &JsValue::from("text"),
+                &JsValue::from(segment),
+            );
+        }
+    }
+
+    // emsg boxes of the pending events up to endDts, to be put in front of the segment's moof
+    fn _takeEmsgBoxes(&mut self, endDts: i64) -> Vec<u8> {
+        let mut boxes = Vec::new();
+        if self._pendingEvents.is_empty() || !self._dtsBaseInited {
+            return boxes;
+        }
+
+        let dtsBase = self._dtsBase;
+        let (due, pending): (Vec<PendingEvent>, Vec<PendingEvent>) = self
+            ._pendingEvents
+            .drain(..)
+            .partition(|event| event.dts - dtsBase < endDts);
+        self._pendingEvents = pending;
+
+        for event in due {
+            // events earlier than the first sample are pinned to the timeline start
+            let presentationTime = std::cmp::max(event.dts - dtsBase, 0) as u64;
+            boxes.extend(mp4_generator::emsg(
+                &event.schemeIdUri,
+                &event.value,
+                1000,
+                presentationTime,
+                event.duration,
+                self._emsgId,
+                &event.messageData,
+            ));
+            self._emsgId = self._emsgId.wrapping_add(1);
+        }
+        boxes
+    }
+
+    fn _initSegmentMeta(&self, metadata: &TrackMetaData) -> mp4_generator::Meta {
+        let mut meta = mp4_generator::Meta::from(metadata);
+        meta.encryption = self._config.encryption.clone();
+        if self._config.negativeCompositionOffsets && meta._type == "video" {
+            // the first frame is presented at the composition delay, skipped so that
+            // presentation starts at 0
+            meta.editList.push(mp4_generator::EditListEntry {
+                segmentDuration: meta.duration.min(u32::MAX as u64) as u32,
+                mediaTime: self._compositionDelay.unwrap_or(0) as i32,
+            });
+        }
+        meta
+    }
+
+    // Common encryption: one encryptor per track, samples are encrypted in place before muxing
+    fn _setupEncryption(&mut self, metadata: &TrackMetaData) {
+        let encryption = match &self._config.encryption {
+            Some(encryption) => encryption,
+            None => return,
+        };
+
+        let encryptor = match metadata {
+            TrackMetaData::Audio(_) => CencEncryptor::forAudio(encryption),
+            TrackMetaData::Video(meta) => {
+                let codec = naluCodec(&meta.codec);
+                let parameterSets = match codec {
+                    NaluCodec::H264 => annexb::parseAVCDecoderConfigurationRecord(&meta.avcc),
+                    NaluCodec::H265 => annexb::parseHEVCDecoderConfigurationRecord(&meta.avcc),
+                };
+                let naluLengthSize = match parameterSets {
+                    Some(parameterSets) => parameterSets.naluLengthSize,
+                    None => 4,
+                };
+                CencEncryptor::forVideo(encryption, codec, naluLengthSize)
+            }
+        };
+        let encryptor = match encryptor {
+            Ok(encryptor) => encryptor,
+            Err(error) => panic!("MP4Remuxer: invalid encryption config: {:?}", error),
+        };
+
+        match metadata {
+            TrackMetaData::Audio(_) => self._audioEncryptor = Some(encryptor),
+            TrackMetaData::Video(_) => self._videoEncryptor = Some(encryptor),
+        }
+    }
+
+    // WebCodecs output: hand the decoder configuration to JS instead of an init segment
+    fn _onDecoderConfigReceived(&self, metadata: TrackMetaData) {
+        let onDecoderConfig = match &self._onDecoderConfig {
+            Some(callback) => callback,
+            None => panic!("MP4Remuxer: onDecoderConfig callback must be specified!"),
+        };
+
+        match metadata {
+            TrackMetaData::Audio(meta) => {
+                let config = webcodecs::audioDecoderConfig(&meta);
+                self._audioMeta = Some(meta);
+                onDecoderConfig.call2(
+                    &JsValue::null(),
+                    &JsValue::from("audio"),
+                    &JsValue::from(config),
+                );
+            }
+            TrackMetaData::Video(meta) => {
+                let config = webcodecs::videoDecoderConfig(&meta);
+                self._videoMeta = Some(meta);
+                onDecoderConfig.call2(
+                    &JsValue::null(),
+                    &JsValue::from("video"),
+                    &JsValue::from(config),
+                );
+            }
+        }
+    }
+
+    // Elementary stream output: keep the parameter sets around to repeat them before every IDR,
+    // and the AudioSpecificConfig fields needed to build ADTS headers
+    fn _onElementaryStreamMetadataReceived(&self, metadata: TrackMetaData) {
+        match metadata {
+            TrackMetaData::Audio(meta) => {
+                // mp3 frames are passed through as they are
+                if meta.codec != "mp3" {
+                    self._adtsConfig = adts::parseAdtsConfig(&meta.originalConfig);
+                }
+                self._audioMeta = Some(meta);
+            }
+            TrackMetaData::Video(meta) => {
+                let exporter = AnnexBExporter::new(naluCodec(&meta.codec));
+                exporter.setDecoderConfigurationRecord(&meta.avcc);
+                self._annexBExporter = Some(exporter);
+                self._videoMeta = Some(meta);
+            }
+        }
+    }
+
+    fn _emitElementaryStream(&self, r#type: &'static str, mp4Samples: Vec<Mp4Sample>) {
+        let onElementaryStream = match &self._onElementaryStream {
+            Some(callback) => callback,
+            None => panic!("MP4Remuxer: onElementaryStream callback must be specified!"),
+        };
+
+        let data: Vec<u8> = if r#type == "video" {
+            let exporter = match &self._annexBExporter {
+                Some(exporter) => exporter,
+                None => return,
+            };
+            mp4Samples
+                .iter()
+                .flat_map(|sample| exporter.exportSample(&sample.units, sample.isKeyframe))
+                .collect()
+        } else if self._audioMeta.codec == "mp3" {
+            mp4Samples
+                .into_iter()
+                .flat_map(|sample| sample.unit)
+                .collect()
+        } else {
+            let adtsConfig = match &self._adtsConfig {
+                Some(adtsConfig) => adtsConfig,
+                None => return,
+            };
+            mp4Samples
+                .iter()
+                .filter_map(|sample| adts::wrapAdts(adtsConfig, &sample.unit))
+                .flatten()
+                .collect()
+        };
+
+        if data.is_empty() {
+            return;
+        }
+        onElementaryStream.call2(
+            &JsValue::null(),
+            &JsValue::from(r#type),
+            &JsValue::from(js_sys::Uint8Array::from(&data[..])),
+        );
+    }
+
+    // WebCodecs output: one EncodedChunk per sample, no moof / mdat boxing
+    fn _emitEncodedChunks(&self, r#type: &'static str, mp4Samples: Vec<Mp4Sample>) {
+        let onEncodedChunk = match &self._onEncodedChunk {
+            Some(callback) => callback,
+            None => panic!("MP4Remuxer: onEncodedChunk callback must be specified!"),
+        };
+
+        for sample in mp4Samples {
+            let data = if r#type == "video" {
+                // AVCC units keep their length prefix, which matches the avcC description
+                sample
+                    .units
+                    .into_iter()
+                    .flat_map(|unit| unit.data)
+                    .collect()
+            } else {
+                sample.unit
+            };
+            let chunk = webcodecs::encodedChunk(
+                sample.isKeyframe || r#type == "audio",
+                sample.pts,
+                sample.duration,
+                1000,
+                data,
+            );
+            onEncodedChunk.call2(
+                &JsValue::null(),
+                &JsValue::from(r#type),
+                &JsValue::from(chunk),
+            );
+        }
+    }
+
     fn _calculateDtsBase(&self, audioTrack: &AudioTrack, videoTrack: &VideoTrack) {
         if (self._dtsBaseInited) {
             return;
@@ -229,7 +967,11 @@ impl MP4Remuxer {
             self._videoDtsBase = videoTrack.samples[0].dts;
         }
 
-        self._dtsBase = Math.min(self._audioDtsBase, self._videoDtsBase);
+        self._dtsBase = timelineDtsBase(
+            self._config.syncStrategy,
+            audioTrack.samples.first().map(|sample| sample.dts),
+            videoTrack.samples.first().map(|sample| sample.dts),
+        );
         self._dtsBaseInited = true;
     }
 
@@ -270,6 +1012,20 @@ impl MP4Remuxer {
         self._remuxAudio(audioTrack, true);
     }
 
+    // A silent frame of the audio track, None when it cannot be generated for the codec
+    fn _getSilentFrame(&self) -> Option<Vec<u8>> {
+        let meta = self._audioMeta.as_ref()?;
+        match meta.codec.as_str() {
+            "mp3" => mp3_silent::getSilentFrame(&meta.originalConfig),
+            "opus" => {
+                // Opus always decodes at 48kHz
+                let frameLength = meta.refSampleDuration * 48000.0 / meta.timescale as f64;
+                opus_silent::silentPacket(meta.channelCount, frameLength.round() as u32)
+            }
+            _ => aac_silent::getSilentFrame(&meta.originalConfig),
+        }
+    }
+
     fn _remuxAudio(&self, audioTrack: &mut AudioTrack, force: bool) {
         if (self._audioMeta == null) {
             return;
@@ -334,9 +1090,12 @@ impl MP4Remuxer {
         }
 
         let firstSampleOriginalDts = samples[0].dts - self._dtsBase;
+        let mut syncReport = SyncReport::default();
 
         // calculate dtsCorrection
-        if (self._audioNextDts) {
+        if self._config.syncStrategy == SyncStrategy::WallClock {
+            dtsCorrection = 0;
+        } else if (self._audioNextDts) {
             dtsCorrection = firstSampleOriginalDts - self._audioNextDts;
         } else {
             // self._audioNextDts == undefined
@@ -354,7 +1113,7 @@ impl MP4Remuxer {
                 if (lastSample != null) {
                     let distance =
                         (firstSampleOriginalDts - (lastSample.originalDts + lastSample.duration));
-                    if (distance <= 3) {
+                    if (distance <= self._config.dtsGapTolerance) {
                         distance = 0;
                     }
                     let expectedDts = lastSample.dts + lastSample.duration + distance;
@@ -373,9 +1132,7 @@ impl MP4Remuxer {
                 ._videoSegmentInfoList
                 .getLastSegmentBefore(firstSampleOriginalDts);
             if (videoSegment != null && videoSegment.beginDts < firstSampleDts) {
-                let silentUnit =
-                    getSilentFrame(self._audioMeta.originalCodec, self._audioMeta.channelCount);
-                if (silentUnit) {
+                if let Some(silentUnit) = self._getSilentFrame() {
                     let dts = videoSegment.beginDts;
                     let silentFrameDuration = firstSampleDts - videoSegment.beginDts;
                     Log::v(
@@ -385,12 +1142,12 @@ impl MP4Remuxer {
                             dts, silentFrameDuration
                         ),
                     );
+                    mdatBytes += silentUnit.len();
                     samples.unshift(Unit {
                         unit: silentUnit,
                         dts: dts,
                         pts: dts,
                     });
-                    mdatBytes += silentUnit.byteLength;
                 } // silentUnit == null: Cannot generate, skip
             } else {
                 insertPrefixSilentFrame = false;
@@ -412,49 +1169,69 @@ impl MP4Remuxer {
                 continue; //pass the first sample with the invalid dts
             }
 
-            if (self._audioMeta.codec != "mp3") {
-                // for AAC codec, we need to keep dts increase based on refSampleDuration
-                let curRefDts = originalDts;
-                const maxAudioFramesDrift: u32 = 3;
-                if (self._audioNextDts) {
-                    curRefDts = self._audioNextDts;
-                }
+            let maxAudioFramesDrift = self._config.maxAudioFramesDrift as f64;
+
+            // for AAC codec, we need to keep dts increase based on refSampleDuration
+            let curRefDts = originalDts;
+            if (self._audioNextDts) {
+                curRefDts = self._audioNextDts;
+            }
+
+            let drift = if self._audioMeta.codec != "mp3" {
+                audioDrift(
+                    self._config.syncStrategy,
+                    (originalDts - curRefDts) as f64,
+                    refSampleDuration,
+                    maxAudioFramesDrift,
+                    self._fillAudioTimestampGap && !Browser.safari,
+                    &mut syncReport,
+                )
+            } else {
+                AudioDrift::Keep
+            };
 
+            if drift != AudioDrift::Keep {
                 dtsCorrection = originalDts - curRefDts;
-                if (dtsCorrection <= -maxAudioFramesDrift * refSampleDuration) {
+                if drift == AudioDrift::Resync {
+                    // audio follows the video timeline: back onto the original timestamp
+                    Log::v(
+                        self.TAG,
+                        format!(
+                            "Shifting audio by {} ms onto originalDts: {} ms",
+                            dtsCorrection, originalDts
+                        ),
+                    );
+                    curRefDts = originalDts;
+                    dtsCorrection = 0;
+                }
+
+                if drift == AudioDrift::Drop {
                     // If we"re overlapping by more than maxAudioFramesDrift number of frame, drop this sample
                     Log::w(self.TAG, format!("Dropping 1 audio frame (originalDts: {} ms ,curRefDts: {} ms)  due to dtsCorrection: {} ms overlap.", originalDts, curRefDts, dtsCorrection));
                     continue;
-                } else if (dtsCorrection >= maxAudioFramesDrift * refSampleDuration
-                    && self._fillAudioTimestampGap
-                    && !Browser.safari)
-                {
+                } else if let AudioDrift::Fill(frameCount) = drift {
                     // Silent frame generation, if large timestamp gap detected && config.fixAudioTimestampGap
                     needFillSilentFrames = true;
-                    // We need to insert silent frames to fill timestamp gap
-                    let frameCount = Math.floor(dtsCorrection / refSampleDuration);
 
                     dts = Math.floor(curRefDts);
                     sampleDuration = Math.floor(curRefDts + refSampleDuration) - dts;
 
-                    let silentUnit =
-                        getSilentFrame(self._audioMeta.originalCodec, self._audioMeta.channelCount);
-                    if (silentUnit == null) {
-                        // Repeat last frame
-                        silentUnit = unit;
-                    }
+                    // Repeat last frame if the codec has no silent frame
+                    let silentUnit = self._getSilentFrame().unwrap_or_else(|| unit.clone());
                     silentFrames = [];
 
                     for j in 0..frameCount {
                         curRefDts = curRefDts + refSampleDuration;
                         let intDts = Math.floor(curRefDts); // change to integer
                         let intDuration = Math.floor(curRefDts + refSampleDuration) - intDts;
-                        let frame = Frame {
+                        let frame = Mp4Sample {
                             dts: intDts,
                             pts: intDts,
                             cts: 0,
-                            unit: silentUnit,
-                            size: silentUnit.byteLength,
+                            unit: silentUnit.clone(),
+                            units: vec![],
+                            size: silentUnit.len(),
+                            isKeyframe: true,
                             duration: intDuration, // wait for next sample
                             originalDts: originalDts,
                             flags: Flag {
@@ -462,10 +1239,11 @@ impl MP4Remuxer {
                                 dependsOn: 1,
                                 isDependedOn: 0,
                                 hasRedundancy: 0,
+                                isNonSync: 0,
                             },
                         };
                         silentFrames.push(frame);
-                        mdatBytes += unit.byteLength;
+                        mdatBytes += silentUnit.len();
                     }
 
                     self._audioNextDts = curRefDts + refSampleDuration;
@@ -475,7 +1253,7 @@ impl MP4Remuxer {
                     self._audioNextDts = curRefDts + refSampleDuration;
                 }
             } else {
-                // keep the original dts calculate algorithm for mp3
+                // keep the original dts calculate algorithm for mp3, and on the wall clock
                 dts = originalDts - dtsCorrection;
 
                 if (i != samples.length - 1) {
@@ -496,17 +1274,66 @@ impl MP4Remuxer {
                     }
                 }
                 self._audioNextDts = dts + sampleDuration;
+
+                if (sampleDuration >= maxAudioFramesDrift * refSampleDuration
+                    && self._fillAudioTimestampGap
+                    && !Browser.safari)
+                {
+                    if let Some(silentUnit) = self._getSilentFrame() {
+                        // The sample keeps its reference duration, silent frames cover the rest of
+                        // the gap up to the next sample
+                        needFillSilentFrames = true;
+                        let gapEndDts = dts + sampleDuration;
+                        let frameCount = Math.floor(sampleDuration / refSampleDuration) - 1;
+                        let mut curRefDts = dts as f64;
+                        sampleDuration = Math.floor(curRefDts + refSampleDuration) - dts;
+                        silentFrames = [];
+
+                        for j in 0..frameCount {
+                            curRefDts = curRefDts + refSampleDuration;
+                            let intDts = Math.floor(curRefDts);
+                            let intDuration = if j == frameCount - 1 {
+                                // the last one ends at the next sample
+                                gapEndDts - intDts
+                            } else {
+                                Math.floor(curRefDts + refSampleDuration) - intDts
+                            };
+                            silentFrames.push(Mp4Sample {
+                                dts: intDts,
+                                pts: intDts,
+                                cts: 0,
+                                unit: silentUnit.clone(),
+                                units: vec![],
+                                size: silentUnit.len(),
+                                isKeyframe: true,
+                                duration: intDuration,
+                                originalDts: originalDts,
+                                flags: Flag {
+                                    isLeading: 0,
+                                    dependsOn: 1,
+                                    isDependedOn: 0,
+                                    hasRedundancy: 0,
+                                    isNonSync: 0,
+                                },
+                            });
+                            mdatBytes += silentUnit.len();
+                        }
+                        syncReport.insertedFrames += frameCount as u32;
+                    }
+                }
             }
 
             if (firstDts == -1) {
                 firstDts = dts;
             }
-            mp4Samples.push(SampleInfo {
+            mp4Samples.push(Mp4Sample {
                 dts: dts,
                 pts: dts,
                 cts: 0,
                 unit: sample.unit,
+                units: vec![],
                 size: sample.unit.byteLength,
+                isKeyframe: true,
                 duration: sampleDuration,
                 originalDts: originalDts,
                 flags: Flag {
@@ -514,6 +1341,7 @@ impl MP4Remuxer {
                     dependsOn: 1,
                     isDependedOn: 0,
                     hasRedundancy: 0,
+                    isNonSync: 0,
                 },
             });
 
@@ -530,6 +1358,20 @@ impl MP4Remuxer {
             return;
         }
 
+        if self._config.outputMode == OutputMode::WebCodecs {
+            track.samples = [];
+            track.length = 0;
+            self._emitEncodedChunks("audio", mp4Samples);
+            return;
+        }
+
+        if self._config.outputMode == OutputMode::ElementaryStream {
+            track.samples = [];
+            track.length = 0;
+            self._emitElementaryStream("audio", mp4Samples);
+            return;
+        }
+
         // allocate mdatbox
         if (mpegRawTrack) {
             // allocate for raw mpeg buffer
@@ -546,6 +1388,15 @@ impl MP4Remuxer {
             mdatbox.set(MP4.types.mdat, 4);
         }
 
+        // encrypt in place, sizes are left unchanged
+        let sampleEncryptions: Vec<SampleEncryption> = match &mut self._audioEncryptor {
+            Some(encryptor) if !mpegRawTrack => mp4Samples
+                .iter_mut()
+                .map(|sample| encryptor.encryptAudioSample(&mut sample.unit))
+                .collect(),
+            _ => vec![],
+        };
+
         // Write samples into mdatbox
         for i in 0..mp4Samples.length {
             let unit = mp4Samples[i].unit;
@@ -593,17 +1444,44 @@ impl MP4Remuxer {
             moofbox = Vec::new();
         } else {
             // Generate moof for fmp4 segment
-            moofbox = MP4.moof(track, firstDts);
+            if self._audioEncryptor.is_some() {
+                moofbox = mp4_generator::encryptedMoof(track, firstDts as u64, &sampleEncryptions);
+            } else {
+                moofbox = MP4.moof(track, firstDts as u64);
+            }
         }
 
         track.samples = [];
         track.length = 0;
 
+        // the video segments carry the events, unless this is an audio only stream
+        let mut segmentData = Vec::new();
+        if !mpegRawTrack && self._videoMeta.is_none() {
+            segmentData = self._takeEmsgBoxes(lastDts);
+        }
+        if !mpegRawTrack {
+            let sampleDts = mp4Samples[0].originalDts + self._dtsBase;
+            segmentData.extend(self._producerReferenceBox(track.id as u32, sampleDts, firstDts));
+        }
+        let moofOffset = segmentData.len();
+        segmentData.extend(self._mergeBoxes(moofbox, mdatbox));
+
+        if self._config.enableSegmentIndex && !mpegRawTrack {
+            let timescale = self._audioMeta.timescale;
+            self._audioSegmentIndex
+                .get_or_insert_with(|| SegmentIndex::new(track.id as u32, timescale))
+                .push(segmentData.len(), moofOffset, &mp4Samples, |_| true);
+        }
+
         let segment = MediaSegment {
             r#type: "audio",
-            data: self._mergeBoxes(moofbox, mdatbox).buffer,
+            data: segmentData,
             sampleCount: mp4Samples.length,
             info: info,
+            syncReport: SyncReport {
+                dtsShift: mp4Samples[0].dts - mp4Samples[0].originalDts,
+                ..syncReport
+            },
         };
 
         if (mpegRawTrack && firstSegmentAfterSeek) {
@@ -666,10 +1544,25 @@ impl MP4Remuxer {
             self._videoStashedLastSample = lastSample;
         }
 
-        let firstSampleOriginalDts = samples[0].dts - self._dtsBase;
+        // decode times move up by the composition delay and composition offsets down by it:
+        // presentation times are unchanged, B-frames get negative offsets
+        let compositionDelay = if self._config.negativeCompositionOffsets {
+            let compositionDelay = *self._compositionDelay.get_or_insert(samples[0].cts as i64);
+            if let Some(metadata) = self._pendingVideoMetadata.take() {
+                self._onTrackMetadataReceived(metadata);
+            }
+            compositionDelay
+        } else {
+            0
+        };
+
+        let firstSampleOriginalDts = samples[0].dts - self._dtsBase + compositionDelay;
+        let mut syncReport = SyncReport::default();
 
         // calculate dtsCorrection
-        if (self._videoNextDts) {
+        if self._config.syncStrategy == SyncStrategy::WallClock {
+            dtsCorrection = 0;
+        } else if (self._videoNextDts) {
             dtsCorrection = firstSampleOriginalDts - self._videoNextDts;
         } else {
             // self._videoNextDts == undefined
@@ -682,7 +1575,7 @@ impl MP4Remuxer {
                 if (lastSample != null) {
                     let distance =
                         (firstSampleOriginalDts - (lastSample.originalDts + lastSample.duration));
-                    if (distance <= 3) {
+                    if (distance <= self._config.dtsGapTolerance) {
                         distance = 0;
                     }
                     let expectedDts = lastSample.dts + lastSample.duration + distance;
@@ -696,14 +1589,16 @@ impl MP4Remuxer {
 
         let info = MediaSegmentInfo::new();
         let mp4Samples = [];
+        // (index of the first sample after the gap, its dts)
+        let mut discontinuity: Option<(usize, i64)> = None;
 
         // Correct dts for each sample, and calculate sample duration. Then output to mp4Samples
         for i in 0..samples.length {
             let sample = samples[i];
-            let originalDts = sample.dts - self._dtsBase;
+            let originalDts = sample.dts - self._dtsBase + compositionDelay;
             let isKeyframe = sample.isKeyframe;
             let dts = originalDts - dtsCorrection;
-            let cts = sample.cts;
+            let cts = sample.cts as i64 - compositionDelay;
             let pts = dts + cts;
 
             if (firstDts == -1) {
@@ -714,13 +1609,13 @@ impl MP4Remuxer {
             let sampleDuration = 0;
 
             if (i != samples.length - 1) {
-                let nextDts = samples[i + 1].dts - self._dtsBase - dtsCorrection;
+                let nextDts = samples[i + 1].dts - self._dtsBase + compositionDelay - dtsCorrection;
                 sampleDuration = nextDts - dts;
             } else {
                 // the last sample
                 if (lastSample != null) {
                     // use stashed sample"s dts to calculate sample duration
-                    let nextDts = lastSample.dts - self._dtsBase - dtsCorrection;
+                    let nextDts = lastSample.dts - self._dtsBase + compositionDelay - dtsCorrection;
                     sampleDuration = nextDts - dts;
                 } else if (mp4Samples.length >= 1) {
                     // use second last sample duration
@@ -731,16 +1626,74 @@ impl MP4Remuxer {
                 }
             }
 
+            // frames dropped by the encoder: the sample spans more than one frame duration
+            let refSampleDuration = self._videoMeta.refSampleDuration;
+            let missingFrames = missingFrames(sampleDuration, refSampleDuration);
+            let mut duplicatedSamples = vec![];
+            if missingFrames >= 1 {
+                let gapEndDts = dts + sampleDuration;
+                self._videoGapStatistics.gapCount += 1;
+                self._videoGapStatistics.missingFrames += missingFrames as u32;
+
+                let meta = &self._videoMeta;
+                // H.264 only: the pictures of an HEVC CVS must differ in PicOrderCntVal
+                let repeatable = naluCodec(&meta.codec) == NaluCodec::H264
+                    && annexb::parseAVCDecoderConfigurationRecord(&meta.avcc)
+                        .map_or(false, |avcc| {
+                            isRepeatable(&sample.units, avcc.naluLengthSize)
+                        });
+
+                match self._config.videoGapPolicy {
+                    VideoGapPolicy::Duplicate if repeatable => {
+                        let timing =
+                            spreadOverGap(dts, gapEndDts, missingFrames, refSampleDuration);
+                        sampleDuration = timing[0].1;
+                        for &(duplicateDts, duplicateDuration) in &timing[1..] {
+                            duplicatedSamples.push(Mp4Sample {
+                                dts: duplicateDts,
+                                pts: duplicateDts + cts,
+                                cts: cts,
+                                unit: vec![],
+                                units: sample.units.clone(),
+                                size: sample.length,
+                                isKeyframe: false,
+                                duration: duplicateDuration,
+                                originalDts: originalDts,
+                                flags: Flag {
+                                    isLeading: 0,
+                                    dependsOn: 1,
+                                    isDependedOn: 0,
+                                    hasRedundancy: 0,
+                                    isNonSync: 1,
+                                },
+                            });
+                            mdatBytes += sample.length;
+                        }
+                        self._videoGapStatistics.duplicatedFrames += missingFrames as u32;
+                        syncReport.insertedFrames += missingFrames as u32;
+                    }
+                    VideoGapPolicy::Stretch | VideoGapPolicy::Duplicate => {
+                        self._videoGapStatistics.stretchedGaps += 1;
+                    }
+                    VideoGapPolicy::Discontinuity => {
+                        sampleDuration = refSampleDuration.round() as i64;
+                        discontinuity = Some((i + 1, gapEndDts));
+                        self._videoGapStatistics.discontinuities += 1;
+                    }
+                }
+            }
+
             if (isKeyframe) {
                 let syncPoint = SampleInfo::new(dts, pts, sampleDuration, sample.dts, true);
                 syncPoint.fileposition = sample.fileposition;
                 info.appendSyncPoint(syncPoint);
             }
 
-            mp4Samples.push(SampleInfo {
+            mp4Samples.push(Mp4Sample {
                 dts: dts,
                 pts: pts,
                 cts: cts,
+                unit: vec![],
                 units: sample.units,
                 size: sample.length,
                 isKeyframe: isKeyframe,
@@ -754,6 +1707,44 @@ impl MP4Remuxer {
                     isNonSync: if isKeyframe { 0 } else { 1 },
                 },
             });
+            mp4Samples.extend(duplicatedSamples);
+
+            if discontinuity.is_some() {
+                break;
+            }
+        }
+
+        // the samples after a discontinuity are left for a media segment of their own
+        let remainingSamples = match discontinuity {
+            Some((index, _)) => samples.split_off(index),
+            None => vec![],
+        };
+        for sample in &remainingSamples {
+            mdatBytes -= sample.length;
+        }
+
+        if self._config.outputMode == OutputMode::WebCodecs {
+            let latest = &mp4Samples[mp4Samples.len() - 1];
+            self._videoNextDts = latest.dts + latest.duration;
+            track.samples = [];
+            track.length = 0;
+            self._emitEncodedChunks("video", mp4Samples);
+            if let Some((_, nextDts)) = discontinuity {
+                self._remuxVideoAfterDiscontinuity(track, remainingSamples, nextDts, force);
+            }
+            return;
+        }
+
+        if self._config.outputMode == OutputMode::ElementaryStream {
+            let latest = &mp4Samples[mp4Samples.len() - 1];
+            self._videoNextDts = latest.dts + latest.duration;
+            track.samples = [];
+            track.length = 0;
+            self._emitElementaryStream("video", mp4Samples);
+            if let Some((_, nextDts)) = discontinuity {
+                self._remuxVideoAfterDiscontinuity(track, remainingSamples, nextDts, force);
+            }
+            return;
         }
 
         // allocate mdatbox
@@ -764,6 +1755,15 @@ impl MP4Remuxer {
         mdatbox[3] = (mdatBytes) & 0xFF;
         mdatbox.set(MP4.types.mdat, 4);
 
+        // encrypt in place, sizes are left unchanged
+        let sampleEncryptions: Vec<SampleEncryption> = match &mut self._videoEncryptor {
+            Some(encryptor) => mp4Samples
+                .iter_mut()
+                .map(|sample| encryptor.encryptVideoSample(&mut sample.units))
+                .collect(),
+            None => vec![],
+        };
+
         // Write samples into mdatbox
         for i in 0..mp4Samples.length {
             let units = mp4Samples[i].units;
@@ -816,19 +1816,80 @@ impl MP4Remuxer {
             flags.isNonSync = 0;
         }
 
-        let moofbox = MP4.moof(track, firstDts);
+        let moofbox = if self._videoEncryptor.is_some() {
+            mp4_generator::encryptedMoof(track, firstDts as u64, &sampleEncryptions)
+        } else {
+            MP4.moof(track, firstDts as u64)
+        };
         track.samples = [];
         track.length = 0;
 
+        let mut segmentData = self._takeEmsgBoxes(lastDts);
+        let sampleDts = mp4Samples[0].originalDts + self._dtsBase - compositionDelay;
+        segmentData.extend(self._producerReferenceBox(track.id as u32, sampleDts, firstDts));
+        let moofOffset = segmentData.len();
+        segmentData.extend(self._mergeBoxes(moofbox, mdatbox));
+
+        if self._config.enableSegmentIndex {
+            let timescale = self._videoMeta.timescale;
+            self._videoSegmentIndex
+                .get_or_insert_with(|| SegmentIndex::new(track.id as u32, timescale))
+                .push(segmentData.len(), moofOffset, &mp4Samples, |sample| {
+                    sample.isKeyframe
+                });
+        }
+
         self._onMediaSegment(
             "video",
             MediaSegment {
                 r#type: "video",
-                data: self._mergeBoxes(moofbox, mdatbox).buffer,
+                data: segmentData,
                 sampleCount: mp4Samples.length,
                 info: info,
+                syncReport: SyncReport {
+                    dtsShift: mp4Samples[0].dts - mp4Samples[0].originalDts,
+                    ..syncReport
+                },
             },
         );
+
+        if let Some((_, nextDts)) = discontinuity {
+            self._remuxVideoAfterDiscontinuity(track, remainingSamples, nextDts, force);
+        }
+    }
+
+    // Remuxes the samples after a video discontinuity, from nextDts on so that the gap stays in
+    // the timeline rather than being closed by dtsCorrection
+    fn _remuxVideoAfterDiscontinuity(
+        &mut self,
+        track: &mut VideoTrack,
+        samples: Vec<VideoSample>,
+        nextDts: i64,
+        force: bool,
+    ) {
+        // the end of the samples before the gap
+        let gapStartDts = self._videoNextDts;
+        if let Some(onDiscontinuity) = &self._onDiscontinuity {
+            let _ = onDiscontinuity.call3(
+                &JsValue::null(),
+                &JsValue::from("video"),
+                &JsValue::from_f64(gapStartDts as f64),
+                &JsValue::from_f64(nextDts as f64),
+            );
+        }
+        self._videoNextDts = nextDts;
+        if samples.is_empty() {
+            return;
+        }
+
+        track.length = samples.iter().map(|sample| sample.length).sum();
+        track.samples = samples;
+        // the stashed sample comes after them
+        if let Some(sample) = self._videoStashedLastSample.take() {
+            track.length += sample.length;
+            track.samples.push(sample);
+        }
+        self._remuxVideo(track, force);
     }
 
     fn _mergeBoxes(&self, moof: Vec<u8>, mdat: Vec<u8>) -> Vec<u8> {
@@ -840,3 +1901,251 @@ impl MP4Remuxer {
         moof.extend_from_slice(mdat)
     }
 }
+
+// Whole frames of frameDuration missing before the next sample
+// dts the remuxed timeline starts from: the first sample of either track, the first video
+// sample with VideoMaster so that audio ahead of it is dropped
+fn timelineDtsBase(
+    syncStrategy: SyncStrategy,
+    audioDts: Option<i64>,
+    videoDts: Option<i64>,
+) -> i64 {
+    match (audioDts, videoDts) {
+        (_, Some(videoDts)) if syncStrategy == SyncStrategy::VideoMaster => videoDts,
+        (Some(audioDts), Some(videoDts)) => audioDts.min(videoDts),
+        (audioDts, videoDts) => audioDts.or(videoDts).unwrap_or(0),
+    }
+}
+
+// What becomes of an AAC / Opus frame dtsCorrection ms away from the end of the previous one
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum AudioDrift {
+    // on its own timestamp: the wall clock, or mp3
+    Keep,
+    // right after the previous frame
+    Follow,
+    // VideoMaster: back onto its own timestamp, the audio timeline restarts there
+    Resync,
+    // overlapping the previous frames
+    Drop,
+    // after that many silent frames
+    Fill(i64),
+}
+
+// Picks the correction of Config.syncStrategy and counts it in the segment's report
+fn audioDrift(
+    syncStrategy: SyncStrategy,
+    dtsCorrection: f64,
+    frameDuration: f64,
+    maxAudioFramesDrift: f64,
+    fillGap: bool,
+    syncReport: &mut SyncReport,
+) -> AudioDrift {
+    let maxDrift = maxAudioFramesDrift * frameDuration;
+    let drift = if syncStrategy == SyncStrategy::WallClock {
+        AudioDrift::Keep
+    } else if syncStrategy == SyncStrategy::VideoMaster && dtsCorrection.abs() >= maxDrift {
+        AudioDrift::Resync
+    } else if dtsCorrection <= -maxDrift {
+        AudioDrift::Drop
+    } else if dtsCorrection >= maxDrift && fillGap {
+        AudioDrift::Fill((dtsCorrection / frameDuration).floor() as i64)
+    } else {
+        AudioDrift::Follow
+    };
+
+    match drift {
+        AudioDrift::Resync => syncReport.resyncCount += 1,
+        AudioDrift::Drop => syncReport.droppedFrames += 1,
+        AudioDrift::Fill(frameCount) => syncReport.insertedFrames += frameCount as u32,
+        AudioDrift::Keep | AudioDrift::Follow => {}
+    }
+    drift
+}
+
+fn missingFrames(sampleDuration: i64, frameDuration: f64) -> i64 {
+    if frameDuration <= 0.0 {
+        return 0;
+    }
+    (sampleDuration as f64 / frameDuration).round() as i64 - 1
+}
+
+// (dts, duration) of a sample at dts and of its missingFrames duplicates up to gapEndDts: whole
+// milliseconds with the rounding carried over, so that 29.97fps does not drift
+fn spreadOverGap(
+    dts: i64,
+    gapEndDts: i64,
+    missingFrames: i64,
+    frameDuration: f64,
+) -> Vec<(i64, i64)> {
+    let frameDts = |index: i64| dts + (index as f64 * frameDuration).round() as i64;
+    (0..=missingFrames)
+        .map(|index| {
+            // the last one ends at the next sample
+            let endDts = if index == missingFrames {
+                gapEndDts
+            } else {
+                frameDts(index + 1)
+            };
+            (frameDts(index), endDts - frameDts(index))
+        })
+        .collect()
+}
+
+// Whether an H.264 sample can be decoded a second time without changing the decoder state: a
+// non-reference picture, nal_ref_idc 0 on every slice. Two consecutive IDRs must differ in
+// idr_pic_id (H.264 7.4.3) and a reference picture would be predicted from its own copy
+fn isRepeatable(units: &[Unit], naluLengthSize: usize) -> bool {
+    units.iter().all(|unit| match unit.data.get(naluLengthSize) {
+        Some(&header) => !NaluCodec::H264.isVCL(header & 0x1F) || header & 0x60 == 0,
+        None => false,
+    })
+}
+
+fn naluCodec(codec: &str) -> NaluCodec {
+    if codec.starts_with("hvc1") || codec.starts_with("hev1") {
+        NaluCodec::H265
+    } else {
+        NaluCodec::H264
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn missing_frames_of_a_gap() {
+        assert_eq!(missingFrames(33, 33.367), 0);
+        assert_eq!(missingFrames(40, 33.367), 0);
+        assert_eq!(missingFrames(67, 33.367), 1);
+        assert_eq!(missingFrames(134, 33.367), 3);
+        assert_eq!(missingFrames(100, 0.0), 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn duplicates_carry_the_rounding() {
+        // 29.97fps, 3 frames missing after the sample at 1000
+        let timing = spreadOverGap(1000, 1134, 3, 33.367);
+        assert_eq!(timing, vec![(1000, 33), (1033, 34), (1067, 33), (1100, 34)]);
+    }
+
+    #[wasm_bindgen_test]
+    fn last_duplicate_ends_at_the_next_sample() {
+        let timing = spreadOverGap(0, 130, 2, 40.0);
+        assert_eq!(timing, vec![(0, 40), (40, 40), (80, 50)]);
+        let durations: i64 = timing.iter().map(|&(_, duration)| duration).sum();
+        assert_eq!(durations, 130);
+    }
+
+    fn unit(header: u8) -> Unit {
+        Unit {
+            _type: (header & 0x1F) as u32,
+            data: vec![0x00, 0x00, 0x00, 0x02, header, 0x80],
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn only_non_reference_pictures_are_repeated() {
+        // non-reference P slice, after an SEI
+        assert!(isRepeatable(&[unit(0x06), unit(0x01)], 4));
+        // parameter sets have nal_ref_idc set, they are not pictures
+        assert!(isRepeatable(&[unit(0x67), unit(0x68), unit(0x01)], 4));
+        // reference P slice
+        assert!(!isRepeatable(&[unit(0x41)], 4));
+        // IDR: a copy would repeat its idr_pic_id
+        assert!(!isRepeatable(&[unit(0x67), unit(0x68), unit(0x65)], 4));
+        // no NAL header after the length prefix
+        assert!(!isRepeatable(&[unit(0x01)], 6));
+    }
+
+    // AAC at 44.1kHz, maxAudioFramesDrift 3: about 70ms either way
+    fn drift(syncStrategy: SyncStrategy, correction: f64, report: &mut SyncReport) -> AudioDrift {
+        audioDrift(syncStrategy, correction, 23.22, 3.0, true, report)
+    }
+
+    #[wasm_bindgen_test]
+    fn dts_base_of_each_strategy() {
+        use SyncStrategy::*;
+        // audio starts 200ms ahead of video
+        let (audio, video) = (Some(1000), Some(1200));
+        assert_eq!(timelineDtsBase(AudioMaster, audio, video), 1000);
+        assert_eq!(timelineDtsBase(WallClock, audio, video), 1000);
+        assert_eq!(timelineDtsBase(VideoMaster, audio, video), 1200);
+        // a single track
+        assert_eq!(timelineDtsBase(VideoMaster, audio, None), 1000);
+        assert_eq!(timelineDtsBase(AudioMaster, None, video), 1200);
+    }
+
+    #[wasm_bindgen_test]
+    fn audio_master_drops_and_fills() {
+        let mut report = SyncReport::default();
+        // within 3 frames: laid out back to back
+        assert_eq!(
+            drift(SyncStrategy::AudioMaster, -60.0, &mut report),
+            AudioDrift::Follow
+        );
+        assert_eq!(
+            drift(SyncStrategy::AudioMaster, 60.0, &mut report),
+            AudioDrift::Follow
+        );
+        assert_eq!(
+            drift(SyncStrategy::AudioMaster, -70.0, &mut report),
+            AudioDrift::Drop
+        );
+        assert_eq!(
+            drift(SyncStrategy::AudioMaster, 100.0, &mut report),
+            AudioDrift::Fill(4)
+        );
+        // fixAudioTimestampGap off
+        let unfilled = audioDrift(
+            SyncStrategy::AudioMaster,
+            100.0,
+            23.22,
+            3.0,
+            false,
+            &mut report,
+        );
+        assert_eq!(unfilled, AudioDrift::Follow);
+
+        assert_eq!(report.droppedFrames, 1);
+        assert_eq!(report.insertedFrames, 4);
+        assert_eq!(report.resyncCount, 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn video_master_resyncs_instead() {
+        let mut report = SyncReport::default();
+        assert_eq!(
+            drift(SyncStrategy::VideoMaster, -70.0, &mut report),
+            AudioDrift::Resync
+        );
+        assert_eq!(
+            drift(SyncStrategy::VideoMaster, 100.0, &mut report),
+            AudioDrift::Resync
+        );
+        assert_eq!(
+            drift(SyncStrategy::VideoMaster, 20.0, &mut report),
+            AudioDrift::Follow
+        );
+
+        assert_eq!(report.resyncCount, 2);
+        assert_eq!(report.droppedFrames, 0);
+        assert_eq!(report.insertedFrames, 0);
+    }
+
+    #[wasm_bindgen_test]
+    fn wall_clock_keeps_audio_timestamps() {
+        let mut report = SyncReport::default();
+        for &correction in &[-500.0, 0.0, 500.0] {
+            assert_eq!(
+                drift(SyncStrategy::WallClock, correction, &mut report),
+                AudioDrift::Keep
+            );
+        }
+        assert_eq!(report.droppedFrames, 0);
+        assert_eq!(report.insertedFrames, 0);
+        assert_eq!(report.resyncCount, 0);
+    }
+}
@@ -0,0 +1,71 @@
+// Silent Opus packets (RFC 6716): CELT-only fullband frames whose range coded payload starts
+// with the silence flag set, the decoder outputs zeros for the whole frame.
+
+// first bytes of a CELT frame decoding to silence = 1
+const CELT_SILENCE: [u8; 2] = [0xFF, 0xFE];
+
+// TOC config of the CELT-only fullband frames, RFC 6716 3.1
+const CELT_FB_2_5MS: u8 = 28;
+const CELT_FB_20MS: u8 = 31;
+
+// 20ms frames in a code 3 packet, 120ms at most
+const MAX_FRAMES_PER_PACKET: u32 = 6;
+
+// frameLength: PCM samples per packet at 48kHz, which Opus always decodes to
+pub fn silentPacket(channelCount: usize, frameLength: u32) -> Option<Vec<u8>> {
+    // multistream (more than 2 channels) packets need the self-delimited framing per stream
+    if channelCount == 0 || channelCount > 2 {
+        return None;
+    }
+    let stereo = ((channelCount == 2) as u8) << 2;
+
+    let mut packet = vec![];
+    match frameLength {
+        // 2.5, 5, 10, 20ms: code 0, a single frame
+        120 | 240 | 480 | 960 => {
+            let config = CELT_FB_2_5MS + (frameLength / 120).trailing_zeros() as u8;
+            packet.push((config << 3) | stereo);
+            packet.extend_from_slice(&CELT_SILENCE);
+        }
+        // 40 to 120ms: code 3, constant bitrate 20ms frames
+        _ if frameLength % 960 == 0 && frameLength / 960 <= MAX_FRAMES_PER_PACKET => {
+            let frameCount = frameLength / 960;
+            packet.push((CELT_FB_20MS << 3) | stereo | 0x03);
+            packet.push(frameCount as u8); // v: 0, p: 0, M
+            for _ in 0..frameCount {
+                packet.extend_from_slice(&CELT_SILENCE);
+            }
+        }
+        _ => return None,
+    }
+    Some(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn single_frame_packets() {
+        // CELT-only fullband 20ms, mono / stereo
+        assert_eq!(silentPacket(1, 960), Some(vec![0xF8, 0xFF, 0xFE]));
+        assert_eq!(silentPacket(2, 960), Some(vec![0xFC, 0xFF, 0xFE]));
+        assert_eq!(silentPacket(1, 120).unwrap()[0], CELT_FB_2_5MS << 3);
+        assert_eq!(silentPacket(1, 480).unwrap()[0], 30 << 3);
+    }
+
+    #[wasm_bindgen_test]
+    fn code_3_packets() {
+        // 60ms: three 20ms frames
+        let packet = silentPacket(2, 2880).unwrap();
+        assert_eq!(packet, vec![0xFF, 0x03, 0xFF, 0xFE, 0xFF, 0xFE, 0xFF, 0xFE]);
+    }
+
+    #[wasm_bindgen_test]
+    fn unsupported_layouts() {
+        assert!(silentPacket(6, 960).is_none());
+        assert!(silentPacket(1, 1024).is_none());
+        assert!(silentPacket(1, 960 * 7).is_none());
+    }
+}
@@ -0,0 +1,168 @@
+// Fragments of one track in output order, indexed for a single byte-range seekable file laid
+// out as: init segment, sidx, fragments, mfra
+
+use super::mp4_generator::{self, RandomAccessEntry, SegmentReference};
+use super::mp4_muxer::Mp4Sample;
+
+struct Fragment {
+    size: u32,
+    // of the moof from the start of the fragment, emsg boxes come first
+    moofOffset: u32,
+    earliestPresentationTime: i64,
+    duration: i64,
+    // (presentation time, 0-based index) of the first sync sample
+    syncSample: Option<(i64, usize)>,
+}
+
+pub struct SegmentIndex {
+    _trackId: u32,
+    _timescale: u32,
+    _fragments: Vec<Fragment>,
+}
+
+impl SegmentIndex {
+    pub fn new(trackId: u32, timescale: u32) -> SegmentIndex {
+        SegmentIndex {
+            _trackId: trackId,
+            _timescale: timescale,
+            _fragments: Vec::new(),
+        }
+    }
+
+    // A fragment as emitted: size in bytes, its samples and which of them are sync samples
+    pub fn push(
+        &mut self,
+        size: usize,
+        moofOffset: usize,
+        samples: &[Mp4Sample],
+        isSyncSample: impl Fn(&Mp4Sample) -> bool,
+    ) {
+        if samples.is_empty() {
+            return;
+        }
+        let first = &samples[0];
+        let last = &samples[samples.len() - 1];
+        self._fragments.push(Fragment {
+            size: size as u32,
+            moofOffset: moofOffset as u32,
+            earliestPresentationTime: samples.iter().map(|sample| sample.pts).min().unwrap(),
+            duration: last.dts + last.duration - first.dts,
+            syncSample: samples
+                .iter()
+                .position(|sample| isSyncSample(sample))
+                .map(|index| (samples[index].pts, index)),
+        });
+    }
+
+    pub fn sidx(&self) -> Vec<u8> {
+        let earliestPresentationTime = match self._fragments.first() {
+            Some(fragment) => fragment.earliestPresentationTime.max(0) as u64,
+            None => return vec![],
+        };
+        let references: Vec<SegmentReference> = self
+            ._fragments
+            .iter()
+            .map(|fragment| SegmentReference {
+                size: fragment.size,
+                duration: fragment.duration.max(0) as u32,
+                startsWithSAP: fragment.syncSample.map_or(false, |(_, index)| index == 0),
+            })
+            .collect();
+        mp4_generator::sidx(
+            self._trackId,
+            self._timescale,
+            earliestPresentationTime,
+            &references,
+        )
+    }
+
+    // firstFragmentOffset: of the first fragment from the start of the file,
+    // init segment + sidx
+    pub fn mfra(&self, firstFragmentOffset: u64) -> Vec<u8> {
+        if self._fragments.is_empty() {
+            return vec![];
+        }
+        let mut entries = vec![];
+        let mut fragmentOffset = firstFragmentOffset;
+        for fragment in &self._fragments {
+            if let Some((time, index)) = fragment.syncSample {
+                entries.push(RandomAccessEntry {
+                    time: time.max(0) as u64,
+                    moofOffset: fragmentOffset + fragment.moofOffset as u64,
+                    sampleNumber: index as u32 + 1,
+                });
+            }
+            fragmentOffset += fragment.size as u64;
+        }
+        mp4_generator::mfra(self._trackId, &entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remux::mp4_muxer::Flag;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn sample(dts: i64, isKeyframe: bool) -> Mp4Sample {
+        Mp4Sample {
+            dts,
+            pts: dts + 40,
+            cts: 40,
+            duration: 40,
+            originalDts: dts,
+            size: 100,
+            isKeyframe,
+            unit: vec![],
+            units: vec![],
+            flags: Flag::default(),
+        }
+    }
+
+    fn segmentIndex() -> SegmentIndex {
+        let mut index = SegmentIndex::new(1, 1000);
+        index.push(1000, 0, &[sample(0, true), sample(40, false)], |sample| {
+            sample.isKeyframe
+        });
+        // an emsg box ahead of the moof, the sync sample second
+        index.push(500, 30, &[sample(80, false), sample(120, true)], |sample| {
+            sample.isKeyframe
+        });
+        index
+    }
+
+    #[wasm_bindgen_test]
+    fn sidx_of_the_fragments() {
+        let sidx = segmentIndex().sidx();
+        assert_eq!(sidx.len(), 32 + 2 * 12);
+        // earliest_presentation_time
+        assert_eq!(&sidx[20..24], &40u32.to_be_bytes());
+        // referenced_size, subsegment_duration, starts_with_SAP of each fragment
+        assert_eq!(&sidx[32..36], &1000u32.to_be_bytes());
+        assert_eq!(&sidx[36..40], &80u32.to_be_bytes());
+        assert_eq!(&sidx[40..44], &[0x90, 0x00, 0x00, 0x00]);
+        assert_eq!(&sidx[44..48], &500u32.to_be_bytes());
+        assert_eq!(&sidx[48..52], &80u32.to_be_bytes());
+        assert_eq!(&sidx[52..56], &[0x00; 4]);
+    }
+
+    #[wasm_bindgen_test]
+    fn mfra_points_at_the_sync_samples() {
+        let mfra = segmentIndex().mfra(2000);
+        assert_eq!(mfra.len(), 8 + 24 + 2 * 22 + 16);
+        // time, moof_offset, traf_number + trun_number, sample_number
+        assert_eq!(&mfra[32..40], &40u64.to_be_bytes());
+        assert_eq!(&mfra[40..48], &2000u64.to_be_bytes());
+        assert_eq!(&mfra[50..54], &1u32.to_be_bytes());
+        assert_eq!(&mfra[54..62], &160u64.to_be_bytes());
+        assert_eq!(&mfra[62..70], &3030u64.to_be_bytes());
+        assert_eq!(&mfra[72..76], &2u32.to_be_bytes());
+    }
+
+    #[wasm_bindgen_test]
+    fn empty_index() {
+        let index = SegmentIndex::new(1, 1000);
+        assert!(index.sidx().is_empty());
+        assert!(index.mfra(0).is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+// onTextData subtitles: each tag shows its text until the next one replaces or clears it
+
+use super::mp4_generator;
+use super::mp4_muxer::{Flag, Mp4Sample};
+use crate::demux::amf_parser::{parseScriptData, AMFValue};
+use crate::demux::captions::CaptionCue;
+
+pub const TEXT_TRACK_ID: i32 = 3;
+
+// text and trackid of an onTextData tag body
+pub fn parseTextData(tagBody: &[u8]) -> Option<(String, u32)> {
+    let scriptData = parseScriptData(tagBody, 0, tagBody.len());
+    let textData = scriptData.get("onTextData")?;
+    let text = match textData.get("text") {
+        Some(AMFValue::String(text)) => text.clone(),
+        _ => return None,
+    };
+    let trackId = match textData.get("trackid") {
+        Some(AMFValue::Number(trackId)) => *trackId as u32,
+        _ => 0,
+    };
+    Some((text, trackId))
+}
+
+pub struct TextTrack {
+    pub sequenceNumber: i32,
+    pub initSegmentSent: bool,
+    // start and text of the cue on screen
+    _current: Option<(i64, String, u32)>,
+    // end of the last wvtt sample, samples must cover the timeline without holes
+    _nextDts: Option<i64>,
+}
+
+impl TextTrack {
+    pub fn new() -> TextTrack {
+        TextTrack {
+            sequenceNumber: 0,
+            initSegmentSent: false,
+            _current: None,
+            _nextDts: None,
+        }
+    }
+
+    // Starts the cue of a new onTextData tag, returning the one it ends
+    pub fn push(&mut self, time: i64, text: String, trackId: u32) -> Option<CaptionCue> {
+        let ended = self._current.take().and_then(|(start, text, trackId)| {
+            if time > start {
+                Some(CaptionCue::new(
+                    format!("TEXT{}", trackId),
+                    start as f64,
+                    time as f64,
+                    text,
+                ))
+            } else {
+                None
+            }
+        });
+        // an empty text clears the screen
+        if !text.trim().is_empty() {
+            self._current = Some((time, text, trackId));
+        }
+        ended
+    }
+
+    // wvtt samples for a cue: a vtte sample fills the time since the previous cue
+    pub fn samples(&mut self, cue: &CaptionCue) -> (Vec<Mp4Sample>, Vec<u8>) {
+        let start = cue.startTime as i64;
+        let end = cue.endTime as i64;
+        let mut samples = vec![];
+        let mut mdat = vec![];
+
+        if let Some(nextDts) = self._nextDts {
+            if start > nextDts {
+                let payload = mp4_generator::vtte();
+                samples.push(textSample(nextDts, start - nextDts, payload.len()));
+                mdat.extend(payload);
+            }
+        }
+        let payload = mp4_generator::vttc(&cue.text());
+        samples.push(textSample(start, end - start, payload.len()));
+        mdat.extend(payload);
+
+        self._nextDts = Some(end);
+        (samples, mdat)
+    }
+}
+
+fn textSample(dts: i64, duration: i64, size: usize) -> Mp4Sample {
+    Mp4Sample {
+        dts,
+        pts: dts,
+        cts: 0,
+        duration,
+        originalDts: dts,
+        size,
+        isKeyframe: true,
+        unit: vec![],
+        units: vec![],
+        flags: Flag {
+            isLeading: 0,
+            dependsOn: 2,
+            isDependedOn: 0,
+            hasRedundancy: 0,
+            isNonSync: 0,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn cue(start: f64, end: f64, text: &str) -> CaptionCue {
+        CaptionCue::new("TEXT0".to_string(), start, end, text.to_string())
+    }
+
+    fn summary(cue: Option<CaptionCue>) -> Option<(String, f64, f64, String)> {
+        cue.map(|cue| (cue.channel(), cue.startTime, cue.endTime, cue.text()))
+    }
+
+    #[wasm_bindgen_test]
+    fn parse_text_data() {
+        // "onTextData" { text: "Hi", trackid: 2 }
+        let mut body = vec![0x02, 0x00, 0x0A];
+        body.extend_from_slice(b"onTextData");
+        body.extend_from_slice(&[0x03, 0x00, 0x04]);
+        body.extend_from_slice(b"text");
+        body.extend_from_slice(&[0x02, 0x00, 0x02]);
+        body.extend_from_slice(b"Hi");
+        body.extend_from_slice(&[0x00, 0x07]);
+        body.extend_from_slice(b"trackid");
+        body.extend_from_slice(&[0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        body.extend_from_slice(&[0x00, 0x00, 0x09]);
+        assert_eq!(parseTextData(&body), Some(("Hi".to_string(), 2)));
+
+        // any other script tag
+        body[3..13].copy_from_slice(b"onCuePoint");
+        assert_eq!(parseTextData(&body), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn cue_ends_on_the_next_text() {
+        let mut track = TextTrack::new();
+        assert!(track.push(0, "A".to_string(), 0).is_none());
+        assert_eq!(
+            summary(track.push(1000, "B".to_string(), 0)),
+            Some(("TEXT0".to_string(), 0.0, 1000.0, "A".to_string()))
+        );
+        // an empty text clears the screen without starting a cue
+        assert_eq!(
+            summary(track.push(2000, " ".to_string(), 0)),
+            Some(("TEXT0".to_string(), 1000.0, 2000.0, "B".to_string()))
+        );
+        assert!(track.push(3000, "C".to_string(), 1).is_none());
+        // replaced at once: nothing was on screen
+        assert!(track.push(3000, "D".to_string(), 1).is_none());
+        assert_eq!(
+            summary(track.push(4000, "".to_string(), 1)),
+            Some(("TEXT1".to_string(), 3000.0, 4000.0, "D".to_string()))
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn vtte_fills_the_gap_between_cues() {
+        let mut track = TextTrack::new();
+        let (samples, mdat) = track.samples(&cue(0.0, 1000.0, "A"));
+        assert_eq!(samples.len(), 1);
+        assert_eq!((samples[0].dts, samples[0].duration), (0, 1000));
+        assert_eq!(samples[0].size, mdat.len());
+        assert_eq!(mdat, mp4_generator::vttc("A"));
+
+        // back to back: no empty sample
+        let (samples, _) = track.samples(&cue(1000.0, 2000.0, "B"));
+        assert_eq!(samples.len(), 1);
+
+        let (samples, mdat) = track.samples(&cue(3000.0, 4000.0, "C"));
+        assert_eq!(samples.len(), 2);
+        assert_eq!((samples[0].dts, samples[0].duration), (2000, 1000));
+        assert_eq!((samples[1].dts, samples[1].duration), (3000, 1000));
+        assert_eq!(samples[0].size, 8);
+        assert_eq!(
+            [mp4_generator::vtte(), mp4_generator::vttc("C")].concat(),
+            mdat
+        );
+    }
+}
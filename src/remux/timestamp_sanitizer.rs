@@ -0,0 +1,298 @@
+// Repairs the timestamps of broken publishers before the samples reach the remuxer:
+// backwards jumping or duplicate DTS, negative CTS, and audio drifting seconds away from video
+
+use crate::config::Config;
+use crate::demux::flv_demuxer::{AudioTrack, VideoTrack};
+use crate::utils::logger::Log;
+use wasm_bindgen::prelude::*;
+
+const TAG: &'static str = "TimestampSanitizer";
+
+// Anomalies found so far, per class
+#[wasm_bindgen]
+#[derive(Copy, Clone, Default)]
+pub struct SanitizerStatistics {
+    pub backwardDts: u32,
+    pub duplicateDts: u32,
+    pub negativeCts: u32,
+    pub avOffsets: u32,
+    // DTS jumps beyond Config.timestampJumpThreshold, either direction
+    pub jumps: u32,
+}
+
+// Index of the first sample of each track past a DTS jump signalled as a discontinuity: the
+// samples from there on go to media segments of their own
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub struct Discontinuity {
+    pub audio: Option<usize>,
+    pub video: Option<usize>,
+}
+
+#[derive(Default)]
+struct TrackState {
+    // dts of the last sample passed on, repaired
+    lastDts: Option<i64>,
+    // added to every dts of the track, splicing backward jumps and A/V offsets
+    offset: i64,
+}
+
+pub struct TimestampSanitizer {
+    _jumpThreshold: i64,
+    _signalDiscontinuity: bool,
+    _maxAVOffset: i64,
+
+    _audio: TrackState,
+    _video: TrackState,
+
+    _statistics: SanitizerStatistics,
+}
+
+impl TimestampSanitizer {
+    pub fn new(config: &Config) -> TimestampSanitizer {
+        TimestampSanitizer {
+            _jumpThreshold: config.timestampJumpThreshold,
+            _signalDiscontinuity: config.signalTimestampDiscontinuity,
+            _maxAVOffset: config.maxAVOffset,
+
+            _audio: TrackState::default(),
+            _video: TrackState::default(),
+
+            _statistics: SanitizerStatistics::default(),
+        }
+    }
+
+    pub fn statistics(&self) -> SanitizerStatistics {
+        self._statistics
+    }
+
+    // Repairs the samples of both tracks in place, frame durations in ms.
+    // Returns where to split the tracks when a discontinuity has to be signalled to the remuxer
+    pub fn sanitize(
+        &mut self,
+        audioTrack: &mut AudioTrack,
+        videoTrack: &mut VideoTrack,
+        audioFrameDuration: f64,
+        videoFrameDuration: f64,
+    ) -> Option<Discontinuity> {
+        let mut discontinuity = Discontinuity::default();
+
+        if !videoTrack.samples.is_empty() {
+            let mut dts: Vec<i64> = videoTrack.samples.iter().map(|sample| sample.dts).collect();
+            discontinuity.video = self._repairDts("video", &mut dts, videoFrameDuration);
+            for (sample, dts) in videoTrack.samples.iter_mut().zip(dts) {
+                if sample.cts < 0 {
+                    self._statistics.negativeCts += 1;
+                    Log::w(
+                        TAG,
+                        &format!("Negative cts {} at dts {}, clamped", sample.cts, dts),
+                    );
+                    sample.cts = 0;
+                }
+                sample.dts = dts;
+                sample.pts = dts + sample.cts as i64;
+            }
+        }
+
+        if !audioTrack.samples.is_empty() {
+            let mut dts: Vec<i64> = audioTrack.samples.iter().map(|sample| sample.dts).collect();
+            discontinuity.audio = self._repairDts("audio", &mut dts, audioFrameDuration);
+            for (sample, dts) in audioTrack.samples.iter_mut().zip(dts) {
+                sample.dts = dts;
+                sample.pts = dts;
+            }
+        }
+
+        // a discontinuity is passed on as it is, the tracks realign after it
+        if discontinuity != Discontinuity::default() {
+            return Some(discontinuity);
+        }
+        self._alignAudio(audioTrack, videoTrack);
+        None
+    }
+
+    // Monotonic DTS: small backward steps are clamped, large jumps spliced or signalled,
+    // then runs of duplicates spread up to the next distinct DTS. Returns the index of the
+    // first sample past a signalled jump, later jumps of the batch are spliced
+    fn _repairDts(&mut self, r#type: &str, dts: &mut [i64], frameDuration: f64) -> Option<usize> {
+        let frameDuration = (frameDuration.round() as i64).max(1);
+        let state = if r#type == "video" {
+            &mut self._video
+        } else {
+            &mut self._audio
+        };
+        let previousDts = state.lastDts;
+        let mut discontinuity = None;
+
+        let mut lastDts = previousDts;
+        for (index, value) in dts.iter_mut().enumerate() {
+            *value += state.offset;
+            if let Some(last) = lastDts {
+                let jump = *value - last;
+                if jump.abs() > self._jumpThreshold {
+                    self._statistics.jumps += 1;
+                    Log::w(
+                        TAG,
+                        &format!("{} dts jumps by {} ms at {}", r#type, jump, *value),
+                    );
+                    if self._signalDiscontinuity && discontinuity.is_none() {
+                        discontinuity = Some(index);
+                    } else if jump < 0 {
+                        // continue one frame after the last sample
+                        state.offset += last + frameDuration - *value;
+                        *value = last + frameDuration;
+                    }
+                } else if jump < 0 {
+                    self._statistics.backwardDts += 1;
+                    // a duplicate of the last one from here on
+                    *value = last;
+                }
+            }
+            lastDts = Some(*value);
+        }
+
+        self._statistics.duplicateDts += spreadDuplicates(previousDts, dts, frameDuration);
+        state.lastDts = dts.last().copied();
+        discontinuity
+    }
+
+    // Audio further than maxAVOffset away from video is shifted onto it
+    fn _alignAudio(&mut self, audioTrack: &mut AudioTrack, videoTrack: &VideoTrack) {
+        if audioTrack.samples.is_empty() || videoTrack.samples.is_empty() {
+            return;
+        }
+        let (audioDts, videoDts) = match (self._audio.lastDts, self._video.lastDts) {
+            (Some(audioDts), Some(videoDts)) => (audioDts, videoDts),
+            _ => return,
+        };
+
+        let avOffset = audioDts - videoDts;
+        if avOffset.abs() <= self._maxAVOffset {
+            return;
+        }
+
+        self._statistics.avOffsets += 1;
+        Log::w(
+            TAG,
+            &format!("Audio is {} ms off video, realigned", avOffset),
+        );
+        self._audio.offset -= avOffset;
+        self._audio.lastDts = Some(audioDts - avOffset);
+        for sample in audioTrack.samples.iter_mut() {
+            sample.dts -= avOffset;
+            sample.pts = sample.dts;
+        }
+    }
+}
+
+// Spreads each run of equal DTS evenly up to the next distinct one, frameDuration apart when
+// the run ends the batch. previousDts is the last DTS of the previous batch, a run continuing
+// it is spread as well. Returns the number of duplicates
+fn spreadDuplicates(previousDts: Option<i64>, dts: &mut [i64], frameDuration: i64) -> u32 {
+    // runs are found on the values as they were, not the spread ones
+    let original = dts.to_vec();
+    let mut duplicates = 0;
+    let mut i = 0;
+    while i < original.len() {
+        // the run start: the previous sample when dts[i] repeats it
+        let (anchor, anchorIndex) = match (i, previousDts) {
+            (0, Some(previous)) if original[0] == previous => (previous, -1),
+            (0, _) => {
+                i += 1;
+                continue;
+            }
+            _ if original[i] == original[i - 1] => (original[i - 1], i as i64 - 1),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        let mut end = i;
+        while end < original.len() && original[end] == anchor {
+            end += 1;
+        }
+        let step = match original.get(end) {
+            Some(&next) => (next - anchor) as f64 / (end as i64 - anchorIndex) as f64,
+            None => frameDuration as f64,
+        };
+        for j in i..end {
+            dts[j] = anchor + (step * (j as i64 - anchorIndex) as f64).round() as i64;
+        }
+
+        duplicates += (end - i) as u32;
+        i = end;
+    }
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn sanitizer(signalDiscontinuity: bool) -> TimestampSanitizer {
+        TimestampSanitizer::new(&Config {
+            signalTimestampDiscontinuity: signalDiscontinuity,
+            ..Config::default()
+        })
+    }
+
+    #[wasm_bindgen_test]
+    fn duplicates_spread_to_the_next_dts() {
+        let mut dts = [0, 0, 0, 90];
+        assert_eq!(spreadDuplicates(None, &mut dts, 30), 2);
+        assert_eq!(dts, [0, 30, 60, 90]);
+    }
+
+    #[wasm_bindgen_test]
+    fn duplicates_of_the_previous_batch_are_spread() {
+        let mut dts = [100, 100, 160];
+        assert_eq!(spreadDuplicates(Some(100), &mut dts, 20), 2);
+        assert_eq!(dts, [120, 140, 160]);
+    }
+
+    #[wasm_bindgen_test]
+    fn duplicates_ending_the_batch_are_a_frame_apart() {
+        let mut dts = [0, 40, 40, 40];
+        assert_eq!(spreadDuplicates(None, &mut dts, 40), 2);
+        assert_eq!(dts, [0, 40, 80, 120]);
+    }
+
+    #[wasm_bindgen_test]
+    fn small_backward_step_is_clamped() {
+        let mut sanitizer = sanitizer(false);
+        let mut dts = [0, 40, 30, 80];
+        assert_eq!(sanitizer._repairDts("video", &mut dts, 40.0), None);
+        assert_eq!(dts, [0, 40, 60, 80]);
+        let statistics = sanitizer.statistics();
+        assert_eq!(statistics.backwardDts, 1);
+        assert_eq!(statistics.duplicateDts, 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn backward_jump_is_spliced() {
+        let mut sanitizer = sanitizer(false);
+        let mut dts = [5000, 5040, 5080, 100, 140];
+        assert_eq!(sanitizer._repairDts("video", &mut dts, 40.0), None);
+        assert_eq!(dts, [5000, 5040, 5080, 5120, 5160]);
+        // the offset carries over to the next batch
+        let mut dts = [180];
+        assert_eq!(sanitizer._repairDts("video", &mut dts, 40.0), None);
+        assert_eq!(dts, [5200]);
+        assert_eq!(sanitizer.statistics().jumps, 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn signalled_jump_splits_the_batch() {
+        let mut sanitizer = sanitizer(true);
+        let mut dts = [5000, 5040, 5080, 100, 140];
+        assert_eq!(sanitizer._repairDts("video", &mut dts, 40.0), Some(3));
+        assert_eq!(dts, [5000, 5040, 5080, 100, 140]);
+
+        // only the first jump of a batch splits it, a later one is spliced
+        let mut dts = [180, 9000, 200];
+        assert_eq!(sanitizer._repairDts("video", &mut dts, 40.0), Some(1));
+        assert_eq!(dts, [180, 9000, 9040]);
+        assert_eq!(sanitizer.statistics().jumps, 3);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::demux::flv_demuxer::{AudioTrackMetaData, VideoTrackMetaData};
+use wasm_bindgen::prelude::*;
+
+// WebCodecs timestamps and durations are integers in microseconds
+const MICROSECONDS_PER_SECOND: f64 = 1000000.0;
+
+// Init dictionary for VideoDecoder.configure()
+#[wasm_bindgen]
+pub struct VideoDecoderConfig {
+    codec: String,
+    pub codedWidth: u32,
+    pub codedHeight: u32,
+    pub displayAspectWidth: u32,
+    pub displayAspectHeight: u32,
+    description: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl VideoDecoderConfig {
+    #[wasm_bindgen(getter)]
+    pub fn codec(&self) -> String {
+        self.codec.clone()
+    }
+
+    // avcC / hvcC decoder configuration record, which keeps the samples length prefixed
+    #[wasm_bindgen(getter)]
+    pub fn description(&self) -> Vec<u8> {
+        self.description.clone()
+    }
+}
+
+// Init dictionary for AudioDecoder.configure()
+#[wasm_bindgen]
+pub struct AudioDecoderConfig {
+    codec: String,
+    pub sampleRate: u32,
+    pub numberOfChannels: u32,
+    description: Option<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl AudioDecoderConfig {
+    #[wasm_bindgen(getter)]
+    pub fn codec(&self) -> String {
+        self.codec.clone()
+    }
+
+    // AudioSpecificConfig for AAC, absent for MP3
+    #[wasm_bindgen(getter)]
+    pub fn description(&self) -> Option<Vec<u8>> {
+        self.description.clone()
+    }
+}
+
+// Init dictionary for new EncodedVideoChunk() / new EncodedAudioChunk()
+#[wasm_bindgen]
+pub struct EncodedChunkInit {
+    r#type: &'static str,
+    pub timestamp: f64,
+    pub duration: f64,
+    data: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl EncodedChunkInit {
+    #[wasm_bindgen(getter, js_name = "type")]
+    pub fn r#type(&self) -> String {
+        self.r#type.into()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn data(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+}
+
+pub fn videoDecoderConfig(meta: &VideoTrackMetaData) -> VideoDecoderConfig {
+    VideoDecoderConfig {
+        codec: meta.codec.clone(),
+        codedWidth: meta.codecWidth,
+        codedHeight: meta.codecHeight,
+        displayAspectWidth: meta.presentWidth,
+        displayAspectHeight: meta.presentHeight,
+        description: meta.avcc.clone(),
+    }
+}
+
+pub fn audioDecoderConfig(meta: &AudioTrackMetaData) -> AudioDecoderConfig {
+    let description = if meta.codec == "mp3" {
+        None
+    } else {
+        Some(meta.config.clone())
+    };
+
+    AudioDecoderConfig {
+        codec: meta.codec.clone(),
+        sampleRate: meta.audioSampleRate as u32,
+        numberOfChannels: meta.channelCount as u32,
+        description,
+    }
+}
+
+// `pts` and `duration` are in track timescale units
+pub fn encodedChunk(
+    isKeyframe: bool,
+    pts: i64,
+    duration: i64,
+    timescale: u32,
+    data: Vec<u8>,
+) -> EncodedChunkInit {
+    let scale = MICROSECONDS_PER_SECOND / timescale as f64;
+
+    EncodedChunkInit {
+        r#type: if isKeyframe { "key" } else { "delta" },
+        timestamp: (pts as f64 * scale).round(),
+        duration: (duration as f64 * scale).round(),
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn audioMeta(codec: &str, config: Vec<u8>) -> AudioTrackMetaData {
+        AudioTrackMetaData {
+            id: 2,
+            data: vec![],
+            timescale: 1000,
+            duration: 0,
+            codec: codec.into(),
+            originalCodec: codec.into(),
+            config: config.clone(),
+            originalConfig: config,
+            audioSampleRate: 44100.0,
+            channelCount: 2,
+            refSampleDuration: 1152.0 / 44.1,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn chunk_times_round_to_microseconds() {
+        let chunk = encodedChunk(true, 1001, 33, 1000, vec![0x01]);
+        assert_eq!(chunk.r#type(), "key");
+        assert_eq!(chunk.timestamp, 1001000.0);
+        assert_eq!(chunk.duration, 33000.0);
+        assert_eq!(chunk.data(), vec![0x01]);
+
+        // 90kHz: 33366.67us and 11.11us
+        let chunk = encodedChunk(false, 3003, 1, 90000, vec![]);
+        assert_eq!(chunk.r#type(), "delta");
+        assert_eq!(chunk.timestamp, 33367.0);
+        assert_eq!(chunk.duration, 11.0);
+    }
+
+    #[wasm_bindgen_test]
+    fn mp3_has_no_description() {
+        let config = audioDecoderConfig(&audioMeta("mp3", vec![0xFF, 0xFB, 0x90, 0x64]));
+        assert_eq!(config.codec(), "mp3");
+        assert_eq!(config.sampleRate, 44100);
+        assert_eq!(config.numberOfChannels, 2);
+        assert_eq!(config.description(), None);
+
+        let config = audioDecoderConfig(&audioMeta("mp4a.40.2", vec![0x12, 0x10]));
+        assert_eq!(config.description(), Some(vec![0x12, 0x10]));
+    }
+}
@@ -0,0 +1,51 @@
+// MSB-first bitstream writer, the counterpart of the ExpGolomb reader
+
+pub struct BitWriter {
+    _buffer: Vec<u8>,
+    // bits used in the last byte of _buffer, 0 when it is full
+    _bitsInLastByte: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter {
+            _buffer: Vec::new(),
+            _bitsInLastByte: 0,
+        }
+    }
+
+    // Writes the low `bits` bits of value, up to 32
+    pub fn write(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if self._bitsInLastByte == 0 {
+                self._buffer.push(0);
+            }
+            let bit = ((value >> i) & 0x01) as u8;
+            let last = self._buffer.len() - 1;
+            self._buffer[last] |= bit << (7 - self._bitsInLastByte);
+            self._bitsInLastByte = (self._bitsInLastByte + 1) % 8;
+        }
+    }
+
+    pub fn writeBool(&mut self, value: bool) {
+        self.write(value as u32, 1);
+    }
+
+    pub fn bitLength(&self) -> usize {
+        if self._bitsInLastByte == 0 {
+            self._buffer.len() * 8
+        } else {
+            (self._buffer.len() - 1) * 8 + self._bitsInLastByte
+        }
+    }
+
+    // Pads with zero bits up to the next byte boundary
+    pub fn byteAlign(&mut self) {
+        self._bitsInLastByte = 0;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.byteAlign();
+        self._buffer
+    }
+}
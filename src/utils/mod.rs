@@ -1,2 +1,3 @@
+pub mod bit_writer;
 pub mod browser;
 pub mod logger;
\ No newline at end of file